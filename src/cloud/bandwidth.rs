@@ -0,0 +1,135 @@
+use crate::BROADCAST_TX;
+use crate::config_manager::{CONFIG, SafeSend};
+use crate::id::Id;
+use crate::socket_utils::{BandwidthLimit, BandwidthState, ServerMsg};
+use chrono::Local;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// shared by all downloads
+pub static BANDWIDTH: Lazy<BandwidthLimiter> = Lazy::new(BandwidthLimiter::default);
+
+/// a token bucket of bytes, it can burst for a second
+#[derive(Debug, Default)]
+pub struct ByteBucket {
+    tokens: f64,
+    refilled_at: Option<Instant>,
+}
+
+impl ByteBucket {
+    /// take `bytes`, returns how long to wait before they are available
+    /// - `rate`: bytes per second, unlimited when it is `None`
+    pub fn reserve_at(&mut self, rate: Option<u64>, bytes: u64, now: Instant) -> Duration {
+        let refilled_at = self.refilled_at.replace(now);
+        let Some(rate) = rate.filter(|rate| *rate > 0).map(|rate| rate as f64) else {
+            self.tokens = 0.0;
+            return Duration::ZERO;
+        };
+        if let Some(refilled_at) = refilled_at {
+            let elapsed = now.saturating_duration_since(refilled_at).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate).min(rate);
+        }
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate)
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct BandwidthLimiter {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    global: ByteBucket,
+    global_override: BandwidthLimit,
+    task_overrides: HashMap<Id, BandwidthLimit>,
+    /// the global limit which is reported to the clients
+    reported: Option<Option<u64>>,
+}
+
+impl Inner {
+    fn global_limit(&self) -> Option<u64> {
+        self.global_override
+            .or_else(|| CONFIG.load().bandwidth.global_limit_at(Local::now().time()))
+    }
+
+    fn task_limit(&self, id: Id) -> Option<u64> {
+        self.task_overrides
+            .get(&id)
+            .copied()
+            .unwrap_or_default()
+            .or_else(|| CONFIG.load().bandwidth.per_task)
+    }
+
+    fn state(&self) -> BandwidthState {
+        BandwidthState {
+            global: self.global_limit(),
+            global_override: self.global_override,
+            per_task: CONFIG.load().bandwidth.per_task,
+        }
+    }
+}
+
+impl BandwidthLimiter {
+    fn inner(&self) -> std::sync::MutexGuard<'_, Inner> {
+        // the buckets are always valid, even if a thread panicked while holding them
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// wait until `bytes` of the task are allowed by both the global and the task limit
+    pub async fn consume(&self, id: Id, task_bucket: &mut ByteBucket, bytes: u64) {
+        let now = Instant::now();
+        let (wait_time, changed_state) = {
+            let mut inner = self.inner();
+            let global_limit = inner.global_limit();
+            let task_limit = inner.task_limit(id);
+            let global_wait = inner.global.reserve_at(global_limit, bytes, now);
+            let task_wait = task_bucket.reserve_at(task_limit, bytes, now);
+            // e.g. a time-of-day profile begins
+            let changed_state = (inner.reported != Some(global_limit)).then(|| {
+                inner.reported = Some(global_limit);
+                inner.state()
+            });
+            (global_wait.max(task_wait), changed_state)
+        };
+        if let Some(state) = changed_state {
+            BROADCAST_TX.send_msg(ServerMsg::Bandwidth(state));
+        }
+        if !wait_time.is_zero() {
+            tokio::time::sleep(wait_time).await;
+        }
+    }
+
+    /// - `id`: the task, or all downloads when it is `None`
+    pub fn set(&self, id: Option<Id>, limit: BandwidthLimit) -> BandwidthState {
+        let mut inner = self.inner();
+        match id {
+            None => inner.global_override = limit,
+            Some(id) if limit == BandwidthLimit::Auto => {
+                inner.task_overrides.remove(&id);
+            }
+            Some(id) => {
+                inner.task_overrides.insert(id, limit);
+            }
+        }
+        let state = inner.state();
+        inner.reported = Some(state.global);
+        state
+    }
+
+    /// the task is finished or failed
+    pub fn remove_task(&self, id: Id) {
+        self.inner().task_overrides.remove(&id);
+    }
+
+    pub fn state(&self) -> BandwidthState {
+        self.inner().state()
+    }
+}
@@ -0,0 +1,60 @@
+use crate::cloud::client::Pan115Client;
+use crate::errors::CloudError;
+use crate::socket_utils::CloudDir;
+use arc_swap::ArcSwap;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// how many files and folders are listed in a page of the cloud browser
+pub const CLOUD_DIR_PAGE_SIZE: u32 = 50;
+/// listed pages are reused for this long, unless the client asks to refresh
+const CLOUD_DIR_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// - `key`: (cid, page)
+/// - `value`: (listed at, page)
+type CloudDirCache = HashMap<(String, u32), (Instant, CloudDir)>;
+
+static CLOUD_DIR_CACHE: Lazy<ArcSwap<CloudDirCache>> = Lazy::new(ArcSwap::default);
+
+/// list a page of a folder of the default account
+/// - `refresh`: ignore the cache and drop the cached pages of the folder
+pub async fn list_cloud_dir(cid: &str, page: u32, refresh: bool) -> Result<CloudDir, CloudError> {
+    let key = (cid.to_string(), page);
+    if refresh {
+        CLOUD_DIR_CACHE.rcu(|cache| {
+            let mut cache = HashMap::clone(cache);
+            cache.retain(|(folder_id, _), _| folder_id != cid);
+            cache
+        });
+    } else if let Some((listed_at, dir)) = CLOUD_DIR_CACHE.load().get(&key)
+        && listed_at.elapsed() < CLOUD_DIR_CACHE_TTL
+    {
+        return Ok(dir.clone());
+    }
+    let response = Pan115Client::new()?
+        .list_files(
+            cid,
+            (page * CLOUD_DIR_PAGE_SIZE) as i32,
+            CLOUD_DIR_PAGE_SIZE as i32,
+        )
+        .await?;
+    let dir = CloudDir {
+        cid: cid.to_string(),
+        page,
+        count: response.count.max(0) as u32,
+        entries: response.files.into_iter().map(|info| info.into()).collect(),
+    };
+    CLOUD_DIR_CACHE.rcu(|cache| {
+        let mut cache = HashMap::clone(cache);
+        cache.retain(|_, (listed_at, _)| listed_at.elapsed() < CLOUD_DIR_CACHE_TTL);
+        cache.insert(key.clone(), (Instant::now(), dir.clone()));
+        cache
+    });
+    Ok(dir)
+}
+
+/// the cloud files are changed by us, e.g. cleaned up after downloading
+pub fn clear_cloud_dir_cache() {
+    CLOUD_DIR_CACHE.store(Default::default());
+}
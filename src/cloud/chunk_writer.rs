@@ -0,0 +1,50 @@
+use bytes::Bytes;
+use sha1::{Digest, Sha1};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// how many chunks can wait for the disk, the http read waits when the queue is full
+const QUEUE_CHUNKS: usize = 64;
+/// chunks from the network are small, they are buffered before they are written
+const WRITE_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// writes and hashes the chunks of a download on a blocking thread,
+/// so slow storage can't block the runtime
+pub struct ChunkWriter {
+    tx: mpsc::Sender<Bytes>,
+    handle: JoinHandle<io::Result<Sha1>>,
+}
+
+impl ChunkWriter {
+    /// - `hasher`: the hash of the data which is already in `file`
+    pub fn spawn(file: File, mut hasher: Sha1) -> Self {
+        let (tx, mut rx) = mpsc::channel::<Bytes>(QUEUE_CHUNKS);
+        let handle = tokio::task::spawn_blocking(move || {
+            let mut writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, file);
+            while let Some(chunk) = rx.blocking_recv() {
+                writer.write_all(&chunk)?;
+                hasher.update(&chunk);
+            }
+            let file = writer.into_inner().map_err(|e| e.into_error())?;
+            // sync_all() is slow but ensures data is fully flushed to disk,
+            // it is done before the file passes the hash check and is renamed.
+            file.sync_all()?;
+            Ok(hasher)
+        });
+        Self { tx, handle }
+    }
+
+    /// wait while the queue is full, returns `false` if the writer is stopped by an error,
+    /// the error is returned by `finish`
+    pub async fn write(&self, chunk: Bytes) -> bool {
+        self.tx.send(chunk).await.is_ok()
+    }
+
+    /// flush and sync the file, returns the hash of the whole file
+    pub async fn finish(self) -> io::Result<Sha1> {
+        drop(self.tx);
+        self.handle.await.map_err(io::Error::other)?
+    }
+}
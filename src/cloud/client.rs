@@ -0,0 +1,535 @@
+use crate::cloud::download::{DownloadData, DownloadInfo, decode, encode};
+use crate::cloud::rate_limit::API_LIMITER;
+use crate::cloud_manager::{
+    AddTaskOutcome, CloudDownloadResponse, FileInfo, FileInfoResponse, FileListResponse, MOBILE_UA,
+    TasksResponse,
+};
+use crate::config_manager::{ApiUrls, CONFIG, Config};
+use crate::errors::CloudError;
+use crate::{CLIENT_WITH_RETRY, EXPIRED_ACCOUNTS, LOGIN_STATUS};
+use rand::Rng;
+use reqwest::StatusCode;
+use reqwest::header::{COOKIE, HeaderValue, USER_AGENT};
+use reqwest_middleware::{ClientWithMiddleware, RequestBuilder};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::time::UNIX_EPOCH;
+
+/// 115 answers these error numbers when the cookies are expired or kicked out
+const SESSION_EXPIRED_ERRNO: [i64; 2] = [99, 990001];
+/// 115 answers these error numbers when we are too fast, e.g. it asks for a verification
+const THROTTLED_ERRNO: [i64; 1] = [911];
+/// a throttled request is sent again after the limiter slows down, for at most this many times
+const THROTTLED_RETRIES: usize = 3;
+
+/// a client of the 115 apis, it owns the cookies and the base urls, and decodes the responses
+/// and errors in the same way
+#[derive(Clone)]
+pub struct Pan115Client {
+    client: ClientWithMiddleware,
+    cookies: HeaderValue,
+    user_id: Option<String>,
+    urls: ApiUrls,
+    /// `None` is the default account
+    account: Option<String>,
+    /// an expired session of a detached client doesn't change the login status of any account
+    detached: bool,
+}
+
+impl Pan115Client {
+    /// create a client of the default account with the cookies and the base urls in `CONFIG`
+    pub fn new() -> Result<Self, CloudError> {
+        Self::for_account(None)
+    }
+
+    /// create a client of an account in `CONFIG`, `None` is the default account
+    pub fn for_account(account: Option<&str>) -> Result<Self, CloudError> {
+        Self::from_config(&CONFIG.load(), account)
+    }
+
+    /// create a client of an account with its cookies and the base urls in `config`
+    pub fn from_config(config: &Config, account: Option<&str>) -> Result<Self, CloudError> {
+        let cookies = config
+            .account_cookies(account)
+            .ok_or(CloudError::Cookies(format!("unknown account: {account:?}")))?;
+        let mut client = Self::from_cookies(config, cookies)?;
+        client.account = account.map(|name| name.to_string());
+        Ok(client)
+    }
+
+    /// create a client with the base urls in `config`, the cookies may not be saved yet
+    pub fn from_cookies(config: &Config, cookies: &str) -> Result<Self, CloudError> {
+        let client = Self::with_cookies(cookies)?;
+        Ok(match &config.api_urls {
+            Some(urls) => client.with_urls(urls.clone()),
+            None => client,
+        })
+    }
+
+    pub fn with_cookies(cookies: &str) -> Result<Self, CloudError> {
+        Ok(Self {
+            client: CLIENT_WITH_RETRY.clone(),
+            cookies: cookies.parse()?,
+            user_id: parse_user_id(cookies),
+            urls: ApiUrls::default(),
+            account: None,
+            detached: false,
+        })
+    }
+
+    pub fn account(&self) -> Option<&str> {
+        self.account.as_deref()
+    }
+
+    pub fn with_client(mut self, client: &ClientWithMiddleware) -> Self {
+        self.client = client.clone();
+        self
+    }
+
+    /// e.g. to validate cookies which are not saved yet, they are not of the current session
+    pub fn detached(mut self) -> Self {
+        self.detached = true;
+        self
+    }
+
+    /// point the client at other servers, e.g. a local mock server
+    pub fn with_urls(mut self, urls: ApiUrls) -> Self {
+        self.urls = urls;
+        self
+    }
+
+    pub fn user_id(&self) -> Option<&str> {
+        self.user_id.as_deref()
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        api: &str,
+        request: RequestBuilder,
+    ) -> Result<T, CloudError> {
+        for _ in 0..THROTTLED_RETRIES {
+            let Some(request) = request.try_clone() else {
+                break;
+            };
+            match self.send_once(api, request).await {
+                Err(CloudError::Throttled(e)) => eprintln!("{e}, retrying..."),
+                result => return result,
+            }
+        }
+        self.send_once(api, request).await
+    }
+
+    /// every request is limited by `API_LIMITER`, and tells it whether 115 throttles us
+    async fn send_once<T: DeserializeOwned>(
+        &self,
+        api: &str,
+        request: RequestBuilder,
+    ) -> Result<T, CloudError> {
+        API_LIMITER.acquire().await;
+        let response = request.header(COOKIE, self.cookies.clone()).send().await?;
+        let status = response.status();
+        let result = if status == StatusCode::TOO_MANY_REQUESTS
+            || status == StatusCode::METHOD_NOT_ALLOWED
+        {
+            Err(CloudError::Throttled(format!(
+                "{api}: HTTP status {status}"
+            )))
+        } else {
+            decode_response(api, &response.text().await?)
+        };
+        match &result {
+            Err(CloudError::SessionExpired(_)) => {
+                if !self.detached {
+                    set_account_login(self.account(), false);
+                }
+            }
+            Err(CloudError::Throttled(_)) => API_LIMITER.throttled(),
+            _ => API_LIMITER.succeeded(),
+        }
+        result
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        api: &str,
+        url: &str,
+        query: &impl Serialize,
+    ) -> Result<T, CloudError> {
+        self.send(api, self.client.get(url).query(query)).await
+    }
+
+    async fn post<T: DeserializeOwned>(
+        &self,
+        api: &str,
+        url: &str,
+        query: &impl Serialize,
+        form: &impl Serialize,
+    ) -> Result<T, CloudError> {
+        self.send(api, self.client.post(url).query(query).form(form))
+            .await
+    }
+
+    fn lixian_url(&self) -> String {
+        format!("{}/web/lixian/", self.urls.web)
+    }
+
+    /// - `folder_id`: the folder to save the tasks to, 115's default folder is used when it is
+    ///   `None`
+    pub async fn add_task_urls(
+        &self,
+        urls: &[String],
+        folder_id: Option<&str>,
+    ) -> Result<Vec<(String, AddTaskOutcome)>, CloudError> {
+        let mut data = HashMap::new();
+        for (index, url) in urls.iter().enumerate() {
+            data.insert(format!("url[{index}]"), url.as_str());
+        }
+        if let Some(folder_id) = folder_id {
+            data.insert("wp_path_id".to_string(), folder_id);
+        }
+        let request = self
+            .client
+            .post(self.lixian_url())
+            .header(USER_AGENT, MOBILE_UA)
+            .query(&[("ct", "lixian"), ("ac", "add_task_urls")])
+            .form(&data);
+        let response: CloudDownloadResponse = self.send("add_task_urls", request).await?;
+        let mut outcomes = response
+            .result
+            .into_iter()
+            .map(|i| (i.url.clone(), i.outcome()))
+            .collect::<Vec<_>>();
+        // the links which are not in the result are kept for retry
+        for url in urls {
+            if !outcomes.iter().any(|(link, _)| link == url) {
+                outcomes.push((url.clone(), AddTaskOutcome::Unknown));
+            }
+        }
+        Ok(outcomes)
+    }
+
+    pub async fn del_task(&self, hash: &str) -> Result<(), CloudError> {
+        let uid = self
+            .user_id()
+            .ok_or(CloudError::Cookies("invalid cookies!".to_string()))?;
+        let _: Value = self
+            .post(
+                "task_del",
+                &self.lixian_url(),
+                &[("ct", "lixian"), ("ac", "task_del")],
+                &[("hash[0]", hash), ("uid", uid)],
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn task_lists(&self, page: i32) -> Result<TasksResponse, CloudError> {
+        self.post(
+            "task_lists",
+            &self.lixian_url(),
+            &json!({"ct": "lixian", "ac": "task_lists", "page": page}),
+            &(),
+        )
+        .await
+    }
+
+    pub async fn list_files(
+        &self,
+        folder_id: &str,
+        offset: i32,
+        limit: i32,
+    ) -> Result<FileListResponse, CloudError> {
+        let params = json!(
+            {
+                "aid": "1",
+                "cid": folder_id,
+                // Order
+                "o": "file_name",
+                // is ascend order?
+                "asc": "1",
+                "offset": offset,
+                "show_dir": "1",
+                "limit": limit,
+                "code": "",
+                "scid": "",
+                "snap": "0",
+                "natsort": "0",
+                "record_open_time": "1",
+                "count_folders": "1",
+                "type": "",
+                "source": "",
+                "format": "json",
+                "fc_mix": "0",
+            }
+        );
+        self.get(
+            "list_files",
+            &format!("{}/files", self.urls.webapi),
+            &params,
+        )
+        .await
+    }
+
+    pub async fn list_all_files(&self, folder_id: &str) -> Result<Vec<FileInfo>, CloudError> {
+        let response = self.list_files(folder_id, 0, 20).await?;
+        let file_count = response.count;
+        let mut current = response.files.len() as i32;
+        let mut files = response.files;
+        while current < file_count {
+            let mut response = self
+                .list_files(folder_id, current, file_count - current)
+                .await?;
+            current += response.files.len() as i32;
+            files.append(&mut response.files);
+        }
+        Ok(files)
+    }
+
+    pub async fn get_file_info(&self, folder_id: &str) -> Result<FileInfoResponse, CloudError> {
+        self.get(
+            "get_file_info",
+            &format!("{}/category/get", self.urls.webapi),
+            &[("cid", folder_id)],
+        )
+        .await
+    }
+
+    /// returns the cid of the new folder
+    pub async fn create_folder(&self, parent_id: &str, name: &str) -> Result<String, CloudError> {
+        let response: Value = self
+            .post(
+                "create_folder",
+                &format!("{}/files/add", self.urls.webapi),
+                &(),
+                &[("pid", parent_id), ("cname", name)],
+            )
+            .await?;
+        // `cid` is a number or a string, depending on the api version
+        match &response["cid"] {
+            Value::String(cid) => Ok(cid.clone()),
+            Value::Number(cid) => Ok(cid.to_string()),
+            _ => Err(
+                format!("create_folder: can not create folder {name}, response: {response}").into(),
+            ),
+        }
+    }
+
+    /// move a file or folder to the recycle bin
+    pub async fn recycle_file(&self, file_id: &str) -> Result<(), CloudError> {
+        let _: Value = self
+            .post(
+                "recycle_file",
+                &format!("{}/rb/delete", self.urls.webapi),
+                &(),
+                &[("fid[0]", file_id), ("ignore_warn", "1")],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// find the record of a file in the recycle bin, `rb/clean` only takes the record id
+    pub async fn recycled_id(&self, pick_code: &str) -> Result<Option<String>, CloudError> {
+        const LIMIT: u64 = 100;
+        let mut offset = 0;
+        loop {
+            let response: Value = self
+                .get(
+                    "recycled_files",
+                    &format!("{}/rb", self.urls.webapi),
+                    &json!({"aid": "7", "cid": "0", "offset": offset, "limit": LIMIT, "format": "json"}),
+                )
+                .await?;
+            let records = response["data"]
+                .as_array()
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            if let Some(record) = records
+                .iter()
+                .find(|record| record["pick_code"] == pick_code)
+            {
+                return match &record["id"] {
+                    Value::String(id) => Ok(Some(id.clone())),
+                    Value::Number(id) => Ok(Some(id.to_string())),
+                    _ => Err(format!("recycled_files: no record id, response: {record}").into()),
+                };
+            }
+            offset += records.len() as u64;
+            let count = value_to_u64(&response["count"]).unwrap_or_default();
+            if records.is_empty() || offset >= count {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// delete a file from the recycle bin permanently, it fails if the file is still there
+    pub async fn clean_recycled_file(&self, pick_code: &str) -> Result<(), CloudError> {
+        let Some(record_id) = self.recycled_id(pick_code).await? else {
+            return Err(
+                format!("clean_recycled_file: {pick_code} is not in the recycle bin").into(),
+            );
+        };
+        let _: Value = self
+            .post(
+                "clean_recycled_file",
+                &format!("{}/rb/clean", self.urls.webapi),
+                &(),
+                &[("rid[0]", record_id.as_str())],
+            )
+            .await?;
+        if self.recycled_id(pick_code).await?.is_some() {
+            return Err(
+                format!("clean_recycled_file: {pick_code} is still in the recycle bin").into(),
+            );
+        }
+        Ok(())
+    }
+
+    /// - (total, used, remain) in bytes
+    pub async fn space_info(&self) -> Result<(u64, u64, u64), CloudError> {
+        let response: Value = self
+            .get(
+                "space_info",
+                &format!("{}/files/index_info", self.urls.webapi),
+                &(),
+            )
+            .await?;
+        let space_info = &response["data"]["space_info"];
+        let size = |key: &str| value_to_u64(&space_info[key]["size"]);
+        match (size("all_total"), size("all_use"), size("all_remain")) {
+            (Some(total), Some(used), Some(remain)) => Ok((total, used, remain)),
+            _ => Err(format!("space_info: can not parse response: {response}").into()),
+        }
+    }
+
+    /// - (total, used, remain) counts of offline tasks
+    pub async fn offline_quota(&self) -> Result<(u64, u64, u64), CloudError> {
+        let response: Value = self
+            .get(
+                "offline_quota",
+                &self.lixian_url(),
+                &[("ct", "lixian"), ("ac", "get_quota_package_info")],
+            )
+            .await?;
+        let count = |key: &str| value_to_u64(&response[key]);
+        match (count("count"), count("used"), count("surplus")) {
+            (Some(total), Some(used), Some(remain)) => Ok((total, used, remain)),
+            _ => Err(format!("offline_quota: can not parse response: {response}").into()),
+        }
+    }
+
+    /// only the pickcode of a single file works
+    pub async fn download_info(&self, pick_code: &str) -> Result<DownloadInfo, CloudError> {
+        let mut key = [0u8; 16];
+        rand::rng().fill(&mut key);
+        let params = serde_json::to_string(&json!({ "pickcode": pick_code }))?;
+        let data = encode(params.bytes().collect(), &key);
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("UNIX_EPOCH is always earlier than now")
+            .as_secs()
+            .to_string();
+        let response: Value = self
+            .post(
+                "download_info",
+                &format!("{}/app/chrome/downurl", self.urls.proapi),
+                &[("t", timestamp)],
+                &[("data", data)],
+            )
+            .await?;
+        let data = response["data"].as_str().unwrap_or_default().to_string();
+        let data_str = decode(data, &key)?;
+        let download_data = serde_json::from_slice::<DownloadData>(&data_str)?;
+        download_data
+            .into_values()
+            .next()
+            .ok_or(format!("download_info: no download url of {pick_code}").into())
+    }
+}
+
+/// `None` is the default account, whose state is `LOGIN_STATUS`
+pub fn set_account_login(account: Option<&str>, is_login: bool) {
+    match account {
+        None => LOGIN_STATUS.store(is_login, std::sync::atomic::Ordering::Relaxed),
+        Some(name) => {
+            EXPIRED_ACCOUNTS.rcu(|expired| {
+                let mut expired = HashSet::clone(expired);
+                if is_login {
+                    expired.remove(name);
+                } else {
+                    expired.insert(name.to_string());
+                }
+                expired
+            });
+        }
+    }
+}
+
+pub fn is_account_login(account: Option<&str>) -> bool {
+    match account {
+        None => LOGIN_STATUS.load(std::sync::atomic::Ordering::Relaxed),
+        Some(name) => !EXPIRED_ACCOUNTS.load().contains(name),
+    }
+}
+
+/// get the user id from the `UID` cookie, which looks like `<user id>_<...>_<...>`
+pub fn parse_user_id(cookies: &str) -> Option<String> {
+    cookies
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == "UID")
+        .and_then(|(_, value)| value.split('_').next())
+        .filter(|uid| !uid.is_empty())
+        .map(|uid| uid.to_string())
+}
+
+pub fn value_to_u64(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+/// check the `state` of the response, every api names its error number and message in its own
+/// way, so try all of them
+///
+/// returns `CloudError::SessionExpired` if the cookies are expired
+pub fn check_response(api: &str, response: &str) -> Result<Value, CloudError> {
+    let value: Value = serde_json::from_str(response)
+        .map_err(|e| format!("{api}: invalid response, error: {e}, response: {response}"))?;
+    if value["state"] != false {
+        return Ok(value);
+    }
+    let errno = ["errno", "errNo", "errcode", "code"]
+        .iter()
+        .find_map(|key| match &value[key] {
+            Value::Number(n) => n.as_i64(),
+            Value::String(s) => s.parse().ok(),
+            _ => None,
+        })
+        .unwrap_or_default();
+    let msg = ["error", "error_msg", "msg"]
+        .iter()
+        .find_map(|key| value[key].as_str().filter(|msg| !msg.is_empty()))
+        .unwrap_or_default();
+    if THROTTLED_ERRNO.contains(&errno) || msg.contains("频繁") {
+        return Err(CloudError::Throttled(format!(
+            "{api}: Error No: {errno}, Error message: {msg}"
+        )));
+    }
+    if SESSION_EXPIRED_ERRNO.contains(&errno) {
+        return Err(CloudError::SessionExpired(format!(
+            "{api}: Error No: {errno}, Error message: {msg}"
+        )));
+    }
+    Err(format!("{api}: Error No: {errno}, Error message: {msg}").into())
+}
+
+pub fn decode_response<T: DeserializeOwned>(api: &str, response: &str) -> Result<T, CloudError> {
+    let value = check_response(api, response)?;
+    serde_json::from_value(value).map_err(|e| {
+        format!("{api}: can not deserialize response, error: {e}, response: {response}").into()
+    })
+}
@@ -0,0 +1,159 @@
+use crate::errors::DownloadError;
+use crate::id::Id;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+/// the running downloads, the clients pause, resume and cancel them through it
+pub static DOWNLOAD_CONTROL: Lazy<DownloadControl> = Lazy::new(DownloadControl::default);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ControlState {
+    #[default]
+    Running,
+    Paused,
+    Cancelled,
+}
+
+#[derive(Debug, Default)]
+pub struct DownloadControl {
+    inner: Mutex<ControlInner>,
+}
+
+#[derive(Debug, Default)]
+struct ControlInner {
+    tasks: HashMap<Id, watch::Sender<ControlState>>,
+    /// new downloads are paused, too
+    all_paused: bool,
+}
+
+impl DownloadControl {
+    fn lock(&self) -> std::sync::MutexGuard<'_, ControlInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// the download is removed when the handle is dropped
+    pub fn register(&'static self, id: Id) -> ControlHandle {
+        let mut inner = self.lock();
+        let state = if inner.all_paused {
+            ControlState::Paused
+        } else {
+            ControlState::Running
+        };
+        let (tx, rx) = watch::channel(state);
+        inner.tasks.insert(id, tx);
+        ControlHandle {
+            id,
+            rx,
+            control: self,
+        }
+    }
+
+    /// change the state of a download, a cancelled download can't be changed again,
+    /// returns `false` if the download is not found or it is already in the state
+    fn transition(&self, id: Id, to: ControlState) -> bool {
+        let inner = self.lock();
+        let Some(tx) = inner.tasks.get(&id) else {
+            return false;
+        };
+        tx.send_if_modified(|state| {
+            let changed = *state != to && *state != ControlState::Cancelled;
+            if changed {
+                *state = to;
+            }
+            changed
+        })
+    }
+
+    pub fn pause(&self, id: Id) -> bool {
+        self.transition(id, ControlState::Paused)
+    }
+
+    pub fn resume(&self, id: Id) -> bool {
+        self.transition(id, ControlState::Running)
+    }
+
+    pub fn cancel(&self, id: Id) -> bool {
+        self.transition(id, ControlState::Cancelled)
+    }
+
+    /// pause all downloads, and the downloads which are started later
+    pub fn pause_all(&self) {
+        let mut inner = self.lock();
+        inner.all_paused = true;
+        for tx in inner.tasks.values() {
+            tx.send_if_modified(|state| {
+                let changed = *state == ControlState::Running;
+                if changed {
+                    *state = ControlState::Paused;
+                }
+                changed
+            });
+        }
+    }
+
+    pub fn resume_all(&self) {
+        let mut inner = self.lock();
+        inner.all_paused = false;
+        for tx in inner.tasks.values() {
+            tx.send_if_modified(|state| {
+                let changed = *state == ControlState::Paused;
+                if changed {
+                    *state = ControlState::Running;
+                }
+                changed
+            });
+        }
+    }
+
+    pub fn is_all_paused(&self) -> bool {
+        self.lock().all_paused
+    }
+
+    fn remove(&self, id: Id) {
+        self.lock().tasks.remove(&id);
+    }
+}
+
+/// the download checks it cooperatively
+#[derive(Debug)]
+pub struct ControlHandle {
+    id: Id,
+    rx: watch::Receiver<ControlState>,
+    control: &'static DownloadControl,
+}
+
+impl ControlHandle {
+    pub fn state(&self) -> ControlState {
+        *self.rx.borrow()
+    }
+
+    /// wait until the download is resumed or cancelled, returns the new state
+    pub async fn wait_while_paused(&mut self) -> ControlState {
+        // the sender lives until the handle is dropped
+        self.rx
+            .wait_for(|state| *state != ControlState::Paused)
+            .await
+            .map_or(ControlState::Running, |state| *state)
+    }
+
+    /// resolves when the download should stop, it is used to interrupt a transfer
+    pub async fn interrupted(&mut self) -> DownloadError {
+        let state = self
+            .rx
+            .wait_for(|state| *state != ControlState::Running)
+            .await
+            .map_or(ControlState::Running, |state| *state);
+        match state {
+            ControlState::Cancelled => DownloadError::Cancelled,
+            _ => DownloadError::Paused,
+        }
+    }
+}
+
+impl Drop for ControlHandle {
+    fn drop(&mut self) {
+        self.control.remove(self.id);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::BROADCAST_TX;
+use crate::cloud::part_file::PartFile;
+use crate::config_manager::{CONFIG, SafeSend};
+use crate::errors::CloudError;
+use crate::id::Id;
+use crate::socket_utils::{DownloadMsg, DownloadState, ServerMsg};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+const DEFAULT_DISK_RESERVE: u64 = 1 << 30;
+/// how often a running download checks the free space
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// how often a paused download checks the free space
+const PAUSE_INTERVAL: Duration = Duration::from_secs(30);
+/// set while the downloads are paused, so the clients are notified once for all of them
+static LOW_SPACE: AtomicBool = AtomicBool::new(false);
+
+pub fn disk_reserve() -> u64 {
+    CONFIG
+        .load()
+        .disk_space_reserve
+        .unwrap_or(DEFAULT_DISK_RESERVE)
+}
+
+/// the free space of the file system which `path` is on, for an unprivileged user.
+/// `path` doesn't need to exist, the nearest existing folder is checked
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = existing_ancestor(path);
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(io::Error::other)?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `c_path` is a valid C string, and `stat` is written by `statvfs` when it succeeds
+    let stat = unsafe {
+        if libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        stat.assume_init()
+    };
+    #[allow(clippy::useless_conversion)]
+    Ok(u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize)))
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "free space is only checked on unix",
+    ))
+}
+
+fn existing_ancestor(path: &Path) -> &Path {
+    path.ancestors()
+        .find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.exists())
+        .unwrap_or(Path::new("."))
+}
+
+/// the bytes which are still needed, the existing files and the downloaded parts are not counted
+/// - `files`: (where the file is saved, size of the file)
+pub fn required_space(files: &[(PathBuf, u64)]) -> u64 {
+    let len = |path: &Path| path.metadata().map_or(0, |metadata| metadata.len());
+    files
+        .iter()
+        .map(|(path, size)| {
+            let saved = len(path).max(len(PartFile::new(path).part_path()));
+            size.saturating_sub(saved.min(*size))
+        })
+        .sum()
+}
+
+/// make sure the files fit in `root` with the reserve left, returns `CloudError::DiskSpace`
+/// if they don't. the check is skipped if the free space is unknown
+pub async fn check_disk_space(root: &Path, files: Vec<(PathBuf, u64)>) -> Result<(), CloudError> {
+    let root = root.to_path_buf();
+    let (free, required) = tokio::task::spawn_blocking(move || {
+        free_space(&root).map(|free| (free, required_space(&files)))
+    })
+    .await
+    .map_err(io::Error::other)
+    .and_then(|result| result)
+    .or_else(|e| {
+        eprintln!("can not get the free disk space, skip the check, error: {e}");
+        Ok::<_, CloudError>((u64::MAX, 0))
+    })?;
+    let reserve = disk_reserve();
+    if free < required.saturating_add(reserve) {
+        return Err(CloudError::DiskSpace(format!(
+            "{required} bytes are required, and {reserve} bytes are reserved, but only {free} bytes are free"
+        )));
+    }
+    Ok(())
+}
+
+/// pauses a running download when the free space is less than the reserve
+pub struct SpaceWatcher {
+    /// the file which is downloaded
+    path: PathBuf,
+    /// the download which is paused
+    id: Id,
+    /// `None` before the first chunk, so the space is checked before anything is written
+    last_check: Option<Instant>,
+}
+
+impl SpaceWatcher {
+    pub fn new(path: &Path, id: Id) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            id,
+            last_check: None,
+        }
+    }
+
+    /// called between the chunks, the free space is checked every `CHECK_INTERVAL`,
+    /// and it waits until there is enough space, the download is shown as paused meanwhile
+    pub async fn wait_for_space(&mut self) {
+        if self
+            .last_check
+            .is_some_and(|last_check| last_check.elapsed() < CHECK_INTERVAL)
+        {
+            return;
+        }
+        let mut paused = false;
+        loop {
+            let path = self.path.clone();
+            let free = tokio::task::spawn_blocking(move || free_space(&path))
+                .await
+                .map_err(io::Error::other)
+                .and_then(|result| result);
+            let reserve = disk_reserve();
+            match free {
+                Ok(free) if free < reserve => {
+                    if !LOW_SPACE.swap(true, Ordering::Relaxed) {
+                        eprintln!("downloads are paused, free disk space: {free} bytes");
+                        BROADCAST_TX.send_msg(ServerMsg::Error(Box::new((
+                            "Downloads are paused".to_string(),
+                            format!(
+                                "free disk space of {:?} is {free} bytes, less than the reserve of {reserve} bytes",
+                                self.path
+                            ),
+                        ))));
+                    }
+                    if !paused {
+                        paused = true;
+                        self.send_state(DownloadState::Paused);
+                    }
+                    tokio::time::sleep(PAUSE_INTERVAL).await;
+                }
+                // the downloads go on if the free space is unknown
+                _ => {
+                    if LOW_SPACE.swap(false, Ordering::Relaxed) {
+                        println!("downloads are resumed");
+                        BROADCAST_TX.send_msg(ServerMsg::Info(
+                            "Downloads are resumed, there is enough disk space now".into(),
+                        ));
+                    }
+                    if paused {
+                        self.send_state(DownloadState::Resumed);
+                    }
+                    break;
+                }
+            }
+        }
+        self.last_check = Some(Instant::now());
+    }
+
+    fn send_state(&self, state: DownloadState) {
+        let msg = ServerMsg::Download(DownloadMsg { id: self.id, state });
+        BROADCAST_TX.send_msg(msg);
+    }
+}
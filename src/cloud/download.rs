@@ -1,15 +1,11 @@
-use crate::config_manager::CONFIG;
+use crate::cloud::client::Pan115Client;
 use crate::crypto::{rsa, xor};
 use crate::errors::CloudError;
 use base64::engine::general_purpose;
 use base64::{DecodeError, Engine};
-use rand::{self, Rng};
-use reqwest::header::{COOKIE, HeaderMap};
 use reqwest_middleware::ClientWithMiddleware;
 use serde::Deserialize;
-use serde_json::json;
 use std::collections::HashMap;
-use std::time::UNIX_EPOCH;
 
 #[derive(Deserialize)]
 pub struct DownloadResponse {
@@ -32,7 +28,7 @@ pub struct DownloadInfo {
     pub url: FileDownloadUrl,
 }
 
-type DownloadData = HashMap<String, DownloadInfo>;
+pub type DownloadData = HashMap<String, DownloadInfo>;
 
 pub fn encode(mut input: Vec<u8>, key: &[u8]) -> String {
     // Prepare buffer
@@ -63,44 +59,8 @@ pub async fn get_download_link(
     client: &ClientWithMiddleware,
     pick_code: String,
 ) -> Result<DownloadInfo, CloudError> {
-    let mut key = [0u8; 16];
-    rand::rng().fill(&mut key);
-    let params = json!({
-        "pickcode": pick_code
-    });
-    let params = serde_json::to_string(&params)?;
-    let data = encode(params.bytes().collect(), &key);
-    let mut form_data = HashMap::new();
-    form_data.insert("data", data);
-    let cookies = &CONFIG.load().cookies;
-    let mut headers = HeaderMap::new();
-    headers.insert(COOKIE, cookies.parse()?);
-    let response = client
-        .post("http://proapi.115.com/app/chrome/downurl")
-        .headers(headers)
-        .query(&[(
-            "t",
-            std::time::SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("UNIX_EPOCH is always earlier than now")
-                .as_secs()
-                .to_string(),
-        )])
-        .form(&form_data)
-        .send()
-        .await?
-        .text()
-        .await?;
-    // println!("{}", response);
-    let result = serde_json::from_str::<DownloadResponse>(&response).inspect_err(|_| {
-        eprintln!("can not get download link, response: {}", response);
-    })?;
-    if result.state {
-        let data_str = decode(result.data, &key)?;
-        let download_data = serde_json::from_slice::<DownloadData>(&data_str)?;
-        if let Some(download_info) = download_data.into_values().next() {
-            return Ok(download_info);
-        }
-    }
-    Err(result.msg.into())
+    Pan115Client::new()?
+        .with_client(client)
+        .download_info(&pick_code)
+        .await
 }
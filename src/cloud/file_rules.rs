@@ -0,0 +1,89 @@
+use crate::cloud_manager::FileInfo;
+use crate::config_manager::FileRules;
+use crate::errors::CloudError;
+use regex::Regex;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    /// the extension is not in `extensions`
+    Extension,
+    /// - size of the file
+    TooSmall(u64),
+    /// the name matches none of `include`
+    NotIncluded,
+    /// - the pattern which the name matches
+    Excluded(String),
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Extension => write!(f, "extension is not allowed"),
+            Self::TooSmall(size) => write!(f, "too small ({size} bytes)"),
+            Self::NotIncluded => write!(f, "not included"),
+            Self::Excluded(pattern) => write!(f, "excluded by `{pattern}`"),
+        }
+    }
+}
+
+/// `FileRules` with compiled patterns
+#[derive(Debug, Default)]
+pub struct FileSelector {
+    extensions: Vec<String>,
+    min_size: u64,
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl FileSelector {
+    pub fn new(rules: &FileRules) -> Result<Self, CloudError> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    Regex::new(pattern).map_err(|e| {
+                        CloudError::Param(format!("invalid file rule `{pattern}`, error: {e}"))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(Self {
+            extensions: rules
+                .extensions
+                .iter()
+                .map(|ext| ext.trim_start_matches('.').to_lowercase())
+                .collect(),
+            min_size: rules.min_size,
+            include: compile(&rules.include)?,
+            exclude: compile(&rules.exclude)?,
+        })
+    }
+
+    /// folders are only checked by `exclude`, so that a whole folder of extras can be skipped
+    pub fn check(&self, info: &FileInfo) -> Result<(), SkipReason> {
+        if let Some(pattern) = self.exclude.iter().find(|re| re.is_match(&info.name)) {
+            return Err(SkipReason::Excluded(pattern.to_string()));
+        }
+        if info.file_id.is_none() {
+            return Ok(());
+        }
+        if !self.extensions.is_empty() {
+            let extension = Path::new(&info.name)
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase());
+            if !extension.is_some_and(|ext| self.extensions.contains(&ext)) {
+                return Err(SkipReason::Extension);
+            }
+        }
+        let size = info.size.unwrap_or_default();
+        if size < self.min_size {
+            return Err(SkipReason::TooSmall(size));
+        }
+        if !self.include.is_empty() && !self.include.iter().any(|re| re.is_match(&info.name)) {
+            return Err(SkipReason::NotIncluded);
+        }
+        Ok(())
+    }
+}
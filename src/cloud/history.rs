@@ -0,0 +1,64 @@
+use crate::BROADCAST_TX;
+use crate::cloud::store::JsonStore;
+use crate::config_manager::{HistoryEntry, SafeSend};
+use crate::socket_utils::{HistoryCoder, ServerMsg};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+/// the history file is written on every finished file, so the history is kept short
+pub const MAX_HISTORY: usize = 500;
+
+pub static DOWNLOAD_HISTORY: Lazy<JsonStore<DownloadHistory>> =
+    Lazy::new(|| JsonStore::new("download_history.json"));
+
+/// files which are downloaded or failed
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct DownloadHistory {
+    /// the latest one is the last
+    pub entries: Vec<HistoryEntry>,
+}
+
+impl DownloadHistory {
+    /// the oldest entries are dropped when there are more than `MAX_HISTORY`
+    pub fn push(&mut self, entry: HistoryEntry) {
+        self.entries.push(entry);
+        let excess = self.entries.len().saturating_sub(MAX_HISTORY);
+        self.entries.drain(..excess);
+    }
+
+    /// the latest entry of the file
+    pub fn history_of(&self, file_id: &str) -> Option<&HistoryEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.file.file_id == file_id)
+    }
+}
+
+/// the history which is sent to the clients
+pub fn history_msg() -> ServerMsg {
+    let history = DOWNLOAD_HISTORY
+        .load()
+        .entries
+        .iter()
+        .map(HistoryCoder::from)
+        .collect();
+    ServerMsg::History(history)
+}
+
+/// apply `cmd` to the history, and send it to the clients after it is saved
+async fn update_history<F>(cmd: F)
+where
+    F: FnOnce(&mut DownloadHistory),
+{
+    DOWNLOAD_HISTORY.update(cmd).await;
+    BROADCAST_TX.send_msg(history_msg());
+}
+
+pub async fn record_history(entry: HistoryEntry) {
+    update_history(move |history| history.push(entry)).await;
+}
+
+pub async fn clear_history() {
+    update_history(|history| history.entries.clear()).await;
+}
@@ -1 +1,17 @@
+pub mod bandwidth;
+pub mod browser;
+pub mod chunk_writer;
+pub mod client;
+pub mod control;
+pub mod disk_space;
 pub mod download;
+pub mod file_rules;
+pub mod history;
+pub mod part_file;
+pub mod path_template;
+pub mod queue;
+pub mod rate_limit;
+pub mod sanitize;
+pub mod segmented;
+pub mod store;
+pub mod task;
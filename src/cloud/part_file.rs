@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// what the part file is downloaded for, it is stored in the sidecar
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct PartInfo {
+    /// the signed url expires, it is only recorded for debugging
+    pub url: String,
+    pub size: u64,
+    pub sha1: String,
+}
+
+impl PartInfo {
+    /// a part file can be resumed if it is downloaded for the same content
+    fn is_same_file(&self, other: &PartInfo) -> bool {
+        self.size == other.size && self.sha1.eq_ignore_ascii_case(&other.sha1)
+    }
+}
+
+/// a file is downloaded to `<name>.part`, with a sidecar `<name>.part.json`,
+/// and it is renamed to `<name>` after it passes the hash check
+#[derive(Clone)]
+pub struct PartFile {
+    path: PathBuf,
+    part: PathBuf,
+    sidecar: PathBuf,
+}
+
+impl PartFile {
+    pub fn new(path: &Path) -> Self {
+        let with_suffix = |suffix: &str| {
+            let mut name = OsString::from(path.as_os_str());
+            name.push(suffix);
+            PathBuf::from(name)
+        };
+        Self {
+            path: path.to_path_buf(),
+            part: with_suffix(".part"),
+            sidecar: with_suffix(".part.json"),
+        }
+    }
+
+    pub fn part_path(&self) -> &Path {
+        &self.part
+    }
+
+    /// how many bytes of the part file can be kept, the part file is removed if it is
+    /// downloaded for another content or it is longer than the file
+    pub fn resume_offset(&self, info: &PartInfo) -> io::Result<u64> {
+        let saved = fs::read_to_string(&self.sidecar)
+            .ok()
+            .and_then(|json| serde_json::from_str::<PartInfo>(&json).ok());
+        let len = match fs::metadata(&self.part) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        match saved {
+            Some(saved) if saved.is_same_file(info) && len <= info.size => Ok(len),
+            _ => {
+                fs::remove_file(&self.part)?;
+                Ok(0)
+            }
+        }
+    }
+
+    pub fn save_info(&self, info: &PartInfo) -> io::Result<()> {
+        let json = serde_json::to_string(info).map_err(io::Error::other)?;
+        fs::write(&self.sidecar, json)
+    }
+
+    /// move the part file into place
+    pub fn finish(self) -> io::Result<()> {
+        fs::rename(&self.part, &self.path)?;
+        self.remove_sidecar()
+    }
+
+    /// the part file can not be resumed, e.g. it fails the hash check
+    pub fn remove(&self) -> io::Result<()> {
+        match fs::remove_file(&self.part) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => (),
+        }
+        self.remove_sidecar()
+    }
+
+    fn remove_sidecar(&self) -> io::Result<()> {
+        match fs::remove_file(&self.sidecar) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use crate::cloud::sanitize::{sanitize_name, sanitize_relative_path};
+use crate::errors::CloudError;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+/// keeps the layout of the cloud folder, it is used when there is no template,
+/// or the template needs something which is not in the file name
+pub const DEFAULT_PATH_TEMPLATE: &str = "{title}/{path}/{original_name}";
+/// used when `library_root` is not set, relative to the working directory
+pub const DEFAULT_LIBRARY_ROOT: &str = "downloads/115";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Var {
+    /// the bangumi name, or the name of the folder downloaded by the clients
+    Title,
+    Season,
+    Episode,
+    Subgroup,
+    Resolution,
+    /// the file name in the cloud, with its extension
+    OriginalName,
+    /// without the leading dot
+    Ext,
+    /// the sub folders of the file in the cloud folder
+    Path,
+}
+
+impl Var {
+    fn parse(name: &str) -> Option<Self> {
+        let var = match name {
+            "title" => Self::Title,
+            "season" => Self::Season,
+            "episode" => Self::Episode,
+            "subgroup" => Self::Subgroup,
+            "resolution" => Self::Resolution,
+            "original_name" => Self::OriginalName,
+            "ext" => Self::Ext,
+            "path" => Self::Path,
+            _ => return None,
+        };
+        Some(var)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Var(Var),
+}
+
+/// where a downloaded file is saved, e.g. `{title}/Season {season}/{title} - S{season}E{episode}.{ext}`,
+/// `/` separates the folders, empty folders are skipped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathTemplate {
+    parts: Vec<Part>,
+}
+
+impl Default for PathTemplate {
+    fn default() -> Self {
+        Self::parse(DEFAULT_PATH_TEMPLATE).expect("default template should be valid")
+    }
+}
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> Result<Self, CloudError> {
+        let invalid = |reason: &str| {
+            CloudError::Param(format!("invalid path template `{template}`, {reason}"))
+        };
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if rest[..start].contains('}') {
+                return Err(invalid("`}` is not opened"));
+            }
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| invalid("`{` is not closed"))?
+                + start;
+            let name = &rest[start + 1..end];
+            let var =
+                Var::parse(name).ok_or_else(|| invalid(&format!("unknown variable `{name}`")))?;
+            parts.push(Part::Var(var));
+            rest = &rest[end + 1..];
+        }
+        if rest.contains('}') {
+            return Err(invalid("`}` is not opened"));
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        let is_traversal = |text: &str| text.split(['/', '\\']).any(|name| name.trim() == "..");
+        if parts
+            .iter()
+            .any(|part| matches!(part, Part::Text(text) if is_traversal(text)))
+        {
+            return Err(invalid("`..` is not allowed"));
+        }
+        if !parts
+            .iter()
+            .any(|part| matches!(part, Part::Var(Var::OriginalName | Var::Episode)))
+        {
+            return Err(invalid(
+                "it needs `{original_name}` or `{episode}`, or every file is saved to the same path",
+            ));
+        }
+        Ok(Self { parts })
+    }
+
+    /// the path relative to the library root, `None` if a variable is missing in the file name
+    /// - `path`: the sub folders of the file in the cloud folder
+    pub fn render(&self, title: &str, path: &Path, original_name: &str) -> Option<PathBuf> {
+        let info = NameInfo::parse(original_name);
+        let mut rendered = String::new();
+        for part in &self.parts {
+            let value = match part {
+                Part::Text(text) => {
+                    rendered.push_str(text);
+                    continue;
+                }
+                Part::Var(Var::Path) => {
+                    let folders = sanitize_relative_path(path)
+                        .iter()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .collect::<Vec<_>>();
+                    rendered.push_str(&folders.join("/"));
+                    continue;
+                }
+                Part::Var(Var::Title) => Some(title.to_string()),
+                Part::Var(Var::Season) => info.episode.as_ref().map(|_| info.season_or_first()),
+                Part::Var(Var::Episode) => info.episode.clone(),
+                Part::Var(Var::Subgroup) => info.subgroup.clone(),
+                Part::Var(Var::Resolution) => info.resolution.clone(),
+                Part::Var(Var::OriginalName) => Some(original_name.to_string()),
+                Part::Var(Var::Ext) => Path::new(original_name)
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().into_owned()),
+            };
+            // a value is a single folder or file name
+            rendered.push_str(&sanitize_name(&value?));
+        }
+        let rendered = rendered
+            .split('/')
+            .map(str::trim)
+            .filter(|name| !name.is_empty() && *name != ".")
+            .map(sanitize_name)
+            .collect::<PathBuf>();
+        (rendered.file_name().is_some()).then_some(rendered)
+    }
+}
+
+/// where the files of a download are saved, `title` and the cloud names are sanitized
+/// when the paths are rendered, so the files can't be saved out of `root`
+#[derive(Debug, Clone)]
+pub struct LibraryLayout {
+    pub root: PathBuf,
+    pub title: String,
+    pub template: PathTemplate,
+}
+
+impl LibraryLayout {
+    /// - `path`: the sub folders of the file in the cloud folder
+    pub fn file_path(&self, path: &Path, original_name: &str) -> PathBuf {
+        let relative = self
+            .template
+            .render(&self.title, path, original_name)
+            .or_else(|| PathTemplate::default().render(&self.title, path, original_name))
+            .unwrap_or_else(|| PathBuf::from(sanitize_name(original_name)));
+        self.root.join(relative)
+    }
+}
+
+/// what can be read from the name of a released file
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NameInfo {
+    pub season: Option<String>,
+    pub episode: Option<String>,
+    pub subgroup: Option<String>,
+    pub resolution: Option<String>,
+}
+
+static SUBGROUP: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^\s*(?:\[([^\]]+)\]|【([^】]+)】)").unwrap());
+static SEASON_EPISODE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)\bS(\d{1,2})E(\d{1,4}(?:\.5)?)(?:v\d)?\b").unwrap());
+static SEASON: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?i)(?:\bS(\d{1,2})\b|\bSeason\s*(\d{1,2})\b|\b(\d{1,2})(?:st|nd|rd|th)\s+Season\b|第(\d{1,2})季)")
+        .unwrap()
+});
+static EPISODE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)(?:第(\d{1,4}(?:\.5)?)[话話集]|\s-\s(\d{1,4}(?:\.5)?)(?:v\d)?(?:\s|$|\[|\()|\[(\d{1,3}(?:\.5)?)(?:v\d)?(?:END)?\]|\bE[Pp]?(\d{1,4})\b)",
+    )
+    .unwrap()
+});
+static RESOLUTION: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)\b(?:(\d{3,4})p|\d{3,4}x(\d{3,4})|(4K))\b").unwrap());
+
+/// the first group which matches
+fn first_group(re: &Regex, text: &str) -> Option<String> {
+    re.captures(text).and_then(|caps| {
+        caps.iter()
+            .skip(1)
+            .flatten()
+            .next()
+            .map(|m| m.as_str().to_string())
+    })
+}
+
+/// `5` -> `05`, `12.5` -> `12.5`
+fn pad_number(number: String) -> String {
+    match number.parse::<u32>() {
+        Ok(n) => format!("{n:02}"),
+        Err(_) => number,
+    }
+}
+
+impl NameInfo {
+    pub fn parse(name: &str) -> Self {
+        let stem = Path::new(name)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| name.to_string());
+        let subgroup = first_group(&SUBGROUP, &stem).map(|group| group.trim().to_string());
+        // the subgroup tag is not a part of the title
+        let title = SUBGROUP.replace(&stem, "");
+        let (season, episode) = match SEASON_EPISODE.captures(&title) {
+            Some(caps) => (Some(caps[1].to_string()), Some(caps[2].to_string())),
+            None => (first_group(&SEASON, &title), first_group(&EPISODE, &title)),
+        };
+        let resolution = RESOLUTION.captures(&title).map(|caps| {
+            if caps.get(3).is_some() {
+                "2160p".to_string()
+            } else {
+                let height = caps.get(1).or(caps.get(2)).map_or("", |m| m.as_str());
+                format!("{height}p")
+            }
+        });
+        Self {
+            season: season.map(pad_number),
+            episode: episode.map(pad_number),
+            subgroup,
+            resolution,
+        }
+    }
+
+    /// most releases of the first season have no season in their names
+    pub fn season_or_first(&self) -> String {
+        self.season.clone().unwrap_or_else(|| "01".to_string())
+    }
+}
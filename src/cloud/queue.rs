@@ -0,0 +1,158 @@
+use crate::cloud::store::JsonStore;
+use crate::cloud_manager::{hash_reader, sha1_hex};
+use crate::config_manager::{QueueState, QueuedFile};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// the state of a file changes a few times while it is downloaded, so the queue is saved to
+/// its own file instead of config.json
+pub static DOWNLOAD_QUEUE: Lazy<JsonStore<DownloadQueue>> =
+    Lazy::new(|| JsonStore::new("download_queue.json"));
+
+/// files which are not downloaded and cleaned up yet, they are resumed after a restart
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct DownloadQueue {
+    /// - `key`: file ID
+    /// - `value`: QueuedFile
+    pub files: HashMap<String, QueuedFile>,
+}
+
+impl DownloadQueue {
+    /// the queued files of a cloud folder, in the order which they are downloaded
+    pub fn queued_files(&self, cid: &str) -> Vec<QueuedFile> {
+        let mut files = self
+            .files
+            .values()
+            .filter(|file| file.cid == cid)
+            .cloned()
+            .collect::<Vec<_>>();
+        files.sort_by(|a, b| a.target.cmp(&b.target));
+        files
+    }
+
+    /// the file is verified already if its entry has the same target and SHA1
+    pub fn is_verified(&self, file: &QueuedFile) -> bool {
+        self.files.get(&file.file_id).is_some_and(|queued| {
+            queued.state == QueueState::Verified
+                && queued.target == file.target
+                && queued.sha1 == file.sha1
+        })
+    }
+
+    /// the queued files which are downloaded by the clients, grouped by their cloud folders
+    pub fn manual_queue(&self) -> Vec<Vec<QueuedFile>> {
+        let mut cids = self
+            .files
+            .values()
+            .filter(|file| file.manual)
+            .map(|file| file.cid.as_str())
+            .collect::<Vec<_>>();
+        cids.sort();
+        cids.dedup();
+        cids.into_iter().map(|cid| self.queued_files(cid)).collect()
+    }
+}
+
+async fn update_queue<F>(cmd: F)
+where
+    F: FnOnce(&mut HashMap<String, QueuedFile>),
+{
+    DOWNLOAD_QUEUE.update(|queue| cmd(&mut queue.files)).await;
+}
+
+/// save the files before they are downloaded, files which are queued already are replaced
+pub async fn enqueue(files: Vec<QueuedFile>) {
+    update_queue(move |queue| {
+        for file in files {
+            queue.insert(file.file_id.clone(), file);
+        }
+    })
+    .await;
+}
+
+pub async fn set_queue_state(file_ids: Vec<String>, state: QueueState) {
+    update_queue(move |queue| {
+        for file_id in file_ids {
+            if let Some(file) = queue.get_mut(&file_id) {
+                file.state = state;
+            }
+        }
+    })
+    .await;
+}
+
+/// remove the files which are cleaned up or cancelled
+pub async fn dequeue(file_ids: Vec<String>) {
+    update_queue(move |queue| {
+        for file_id in file_ids {
+            queue.remove(&file_id);
+        }
+    })
+    .await;
+}
+
+/// whether the file is saved to `path` completely, it is checked by its size and SHA1
+pub fn is_saved(path: &Path, size: u64, sha1: &str) -> io::Result<bool> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    if file.metadata()?.len() != size {
+        return Ok(false);
+    }
+    let hasher = hash_reader(file.take(size), |_| ())?;
+    Ok(sha1_hex(hasher).eq_ignore_ascii_case(sha1))
+}
+
+/// called on startup, the files which were saved before the daemon stopped are verified,
+/// and the files which were downloading are queued again
+pub async fn reconcile_queue() {
+    let files = DOWNLOAD_QUEUE
+        .load()
+        .files
+        .values()
+        .filter(|file| file.state != QueueState::Verified)
+        .cloned()
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        return;
+    }
+    println!("reconcile {} queued files with the disk", files.len());
+    let saved = tokio::task::spawn_blocking(move || {
+        files
+            .into_iter()
+            .filter(|file| match is_saved(&file.target, file.size, &file.sha1) {
+                Ok(saved) => saved,
+                Err(e) => {
+                    eprintln!("can not check {:?}, error: {e}", file.target);
+                    false
+                }
+            })
+            .map(|file| file.file_id)
+            .collect::<Vec<_>>()
+    })
+    .await
+    .unwrap_or_else(|e| {
+        eprintln!("can not reconcile the download queue, error: {e}");
+        Vec::new()
+    });
+    println!("{} queued files are saved already", saved.len());
+    update_queue(move |queue| {
+        for file in queue.values_mut() {
+            if file.state == QueueState::Downloading {
+                file.state = QueueState::Queued;
+            }
+        }
+        for file_id in saved {
+            if let Some(file) = queue.get_mut(&file_id) {
+                file.state = QueueState::Verified;
+            }
+        }
+    })
+    .await;
+}
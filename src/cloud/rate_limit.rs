@@ -0,0 +1,148 @@
+use crate::BROADCAST_TX;
+use crate::config_manager::SafeSend;
+use crate::socket_utils::{ApiRateLimit, ServerMsg};
+use crate::time_stamp::TimeStamp;
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// requests per second when 115 doesn't complain
+pub const MAX_RATE: f64 = 1.0;
+/// the rate is never slowed down below this
+pub const MIN_RATE: f64 = 0.05;
+/// how many requests can be sent at once after being idle
+pub const BURST: f64 = 3.0;
+/// the rate is increased by `RECOVER_STEP` after no throttling for this long
+pub const RECOVER_INTERVAL: Duration = Duration::from_secs(30);
+pub const RECOVER_STEP: f64 = 0.1;
+
+/// every request to the 115 apis takes a token from it
+pub static API_LIMITER: Lazy<RateLimiter> = Lazy::new(|| RateLimiter::new(MAX_RATE, BURST));
+
+/// a token bucket whose rate is halved when 115 throttles us, and increased slowly
+/// when it doesn't
+pub struct RateLimiter {
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    rate: f64,
+    max_rate: f64,
+    burst: f64,
+    /// it is negative when some requests are waiting for their tokens
+    tokens: f64,
+    refilled_at: Instant,
+    /// when the rate is changed last time
+    changed_at: Instant,
+    throttled_count: u32,
+    last_throttled: Option<TimeStamp>,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.refilled_at = now;
+    }
+
+    fn state(&self) -> ApiRateLimit {
+        ApiRateLimit {
+            rate: self.rate,
+            max_rate: self.max_rate,
+            throttled_count: self.throttled_count,
+            last_throttled: self.last_throttled.map(Into::into),
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new(max_rate: f64, burst: f64) -> Self {
+        let now = Instant::now();
+        Self {
+            bucket: Mutex::new(Bucket {
+                rate: max_rate,
+                max_rate,
+                burst,
+                tokens: burst,
+                refilled_at: now,
+                changed_at: now,
+                throttled_count: 0,
+                last_throttled: None,
+            }),
+        }
+    }
+
+    fn bucket(&self) -> std::sync::MutexGuard<'_, Bucket> {
+        // the bucket is always valid, even if a thread panicked while holding it
+        self.bucket.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// wait for a token
+    pub async fn acquire(&self) {
+        let wait_time = self.reserve_at(Instant::now());
+        if !wait_time.is_zero() {
+            tokio::time::sleep(wait_time).await;
+        }
+    }
+
+    /// take a token, returns how long to wait before it is available
+    pub fn reserve_at(&self, now: Instant) -> Duration {
+        let mut bucket = self.bucket();
+        bucket.refill(now);
+        bucket.tokens -= 1.0;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / bucket.rate)
+        }
+    }
+
+    /// 115 says we are too fast
+    pub fn throttled(&self) {
+        let state = self.throttled_at(Instant::now());
+        eprintln!(
+            "115 api is throttled, slow down to {:.2} requests per second",
+            state.rate
+        );
+        BROADCAST_TX.send_msg(ServerMsg::RateLimit(state));
+    }
+
+    pub fn throttled_at(&self, now: Instant) -> ApiRateLimit {
+        let mut bucket = self.bucket();
+        bucket.refill(now);
+        bucket.rate = (bucket.rate / 2.0).max(MIN_RATE);
+        // pause for a while, the requests which are waiting are not affected
+        bucket.tokens = bucket.tokens.min(0.0);
+        bucket.changed_at = now;
+        bucket.throttled_count += 1;
+        bucket.last_throttled = Some(TimeStamp::now());
+        bucket.state()
+    }
+
+    /// a request is not throttled
+    pub fn succeeded(&self) {
+        if let Some(state) = self.succeeded_at(Instant::now()) {
+            BROADCAST_TX.send_msg(ServerMsg::RateLimit(state));
+        }
+    }
+
+    /// `Some` if the rate is increased
+    pub fn succeeded_at(&self, now: Instant) -> Option<ApiRateLimit> {
+        let mut bucket = self.bucket();
+        if bucket.rate >= bucket.max_rate
+            || now.saturating_duration_since(bucket.changed_at) < RECOVER_INTERVAL
+        {
+            return None;
+        }
+        bucket.refill(now);
+        bucket.rate = (bucket.rate + RECOVER_STEP).min(bucket.max_rate);
+        bucket.changed_at = now;
+        Some(bucket.state())
+    }
+
+    pub fn state(&self) -> ApiRateLimit {
+        self.bucket().state()
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// most file systems limit a name to 255 bytes
+pub const MAX_NAME_BYTES: usize = 255;
+/// longer extensions are truncated with the rest of the name
+const MAX_EXTENSION_BYTES: usize = 16;
+/// characters which Windows, SMB shares and 115 don't allow in a name, they are replaced with `_`
+pub const RESERVED_CHARS: [char; 9] = ['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+/// names which can't be created on Windows or SMB shares, whatever the extension is
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// make a title or a cloud file name a single safe name in a local folder,
+/// separators and reserved characters are replaced, so it can't be a path
+pub fn sanitize_name(name: &str) -> String {
+    let replaced = name
+        .chars()
+        .map(|c| match c {
+            c if RESERVED_CHARS.contains(&c) || c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    // trailing dots and spaces are dropped by Windows and SMB
+    let trimmed = replaced.trim_start().trim_end_matches([' ', '.']);
+    let mut name = match trimmed {
+        // `..` is trimmed to an empty name
+        "" => "_".to_string(),
+        name => name.to_string(),
+    };
+    let stem = name.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+    {
+        name.insert(0, '_');
+    }
+    truncate_name(&name, MAX_NAME_BYTES)
+}
+
+/// keep at most `max_bytes` of the name, on a grapheme boundary, and keep its extension
+pub fn truncate_name(name: &str, max_bytes: usize) -> String {
+    if name.len() <= max_bytes {
+        return name.to_string();
+    }
+    let (stem, ext) = match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot <= MAX_EXTENSION_BYTES + 1 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let mut truncated = truncate_graphemes(stem, max_bytes.saturating_sub(ext.len()));
+    truncated.push_str(ext);
+    truncated
+}
+
+/// the longest prefix of whole graphemes, which is at most `max_bytes`
+fn truncate_graphemes(text: &str, max_bytes: usize) -> String {
+    let mut truncated = String::new();
+    for grapheme in text.graphemes(true) {
+        if truncated.len() + grapheme.len() > max_bytes {
+            break;
+        }
+        truncated.push_str(grapheme);
+    }
+    truncated
+}
+
+/// sanitize every folder of a relative path, `..`, `.`, roots and prefixes are dropped,
+/// so the path stays in the folder which it is joined to
+pub fn sanitize_relative_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(sanitize_name(&name.to_string_lossy())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// gives every file a distinct path, names are compared case-insensitively, because the
+/// library can be on a case-insensitive file system.
+/// the first file keeps its path, the others get ` (2)`, ` (3)`, ... before the extension,
+/// so the files should be claimed in a stable order
+#[derive(Debug, Default)]
+pub struct UniquePaths {
+    used: HashSet<String>,
+}
+
+impl UniquePaths {
+    pub fn claim(&mut self, path: PathBuf) -> PathBuf {
+        let key = |path: &Path| path.to_string_lossy().to_lowercase();
+        if self.used.insert(key(&path)) {
+            return path;
+        }
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let (stem, ext) = match name.rfind('.') {
+            Some(dot) if dot > 0 => name.split_at(dot),
+            _ => (name.as_str(), ""),
+        };
+        let mut n = 2;
+        loop {
+            let suffix = format!(" ({n}){ext}");
+            let stem = truncate_graphemes(stem, MAX_NAME_BYTES.saturating_sub(suffix.len()));
+            let candidate = path.with_file_name(format!("{stem}{suffix}"));
+            if self.used.insert(key(&candidate)) {
+                return candidate;
+            }
+            n += 1;
+        }
+    }
+}
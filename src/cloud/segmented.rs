@@ -0,0 +1,151 @@
+use crate::cloud::bandwidth::{BANDWIDTH, ByteBucket};
+use crate::cloud::chunk_writer::ChunkWriter;
+use crate::cloud::control::ControlHandle;
+use crate::cloud::disk_space::SpaceWatcher;
+use crate::cloud_manager::check_download_status;
+use crate::config_manager::{CONFIG, Config, HostCapability, Message, SafeSend};
+use crate::errors::DownloadError;
+use crate::id::Id;
+use crate::socket_utils::{DownloadMsg, DownloadState, ServerMsg};
+use crate::{BROADCAST_TX, CLIENT_SEGMENT, TX};
+use bytes::Bytes;
+use futures::{StreamExt, stream};
+use reqwest::StatusCode;
+use reqwest::header::RANGE;
+use std::ops::Range;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// the segments are kept in memory until they are written in order, so a file takes
+/// `SEGMENT_SIZE * connections` bytes at most, they are read at the speed of the bandwidth limits
+pub const SEGMENT_SIZE: u64 = 8 * 1024 * 1024;
+pub const MAX_CONNECTIONS: usize = 16;
+/// an expired url is answered with 403, too, so a host is only marked `Unsupported` when it
+/// rejects a url which was resolved less than this ago
+pub const FRESH_URL: Duration = Duration::from_secs(60);
+
+pub enum Segmented {
+    /// the file is downloaded, or the writer is stopped by an error
+    Finished,
+    /// the host answered 403 to a segment or ignored its range, the rest of the file starts
+    /// from `written`
+    Rejected { written: u64, error: DownloadError },
+}
+
+/// - returns (host, connections) if the file is downloaded in segments
+pub fn segment_plan(url: &str) -> Option<(String, usize)> {
+    let host = url::Url::parse(url).ok()?.host_str()?.to_string();
+    let connections = CONFIG.load().segment_connections(&host)?;
+    Some((host, connections.min(MAX_CONNECTIONS)))
+}
+
+/// split `range` into segments of `segment_size` bytes, the last one may be shorter
+pub fn segment_ranges(range: Range<u64>, segment_size: u64) -> Vec<Range<u64>> {
+    (range.start..range.end)
+        .step_by(segment_size.max(1) as usize)
+        .map(|start| start..(start + segment_size).min(range.end))
+        .collect()
+}
+
+/// save the capability if it is changed, it is not probed again after a restart
+pub fn remember_capability(host: &str, capability: HostCapability) {
+    if CONFIG.load().segment_capability.get(host) == Some(&capability) {
+        return;
+    }
+    let Some(tx) = TX.load_full() else {
+        return;
+    };
+    println!("segmented download from {host} is {capability:?}");
+    let host = host.to_string();
+    let cmd = Box::new(move |config: &mut Config| {
+        config.segment_capability.insert(host, capability);
+    });
+    tx.send_msg(Message::new(cmd, None));
+}
+
+/// the chunks are throttled as they are read, so the segments in flight share the limits
+async fn fetch_segment(
+    url: &str,
+    range: Range<u64>,
+    id: Id,
+    task_bucket: &Mutex<ByteBucket>,
+) -> Result<Vec<Bytes>, DownloadError> {
+    let response = CLIENT_SEGMENT
+        .get(url)
+        .header(RANGE, format!("bytes={}-{}", range.start, range.end - 1))
+        .send()
+        .await?;
+    let mut response = check_download_status(response)?;
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(DownloadError::Resume(format!(
+            "range request is ignored, HTTP status {}",
+            response.status()
+        )));
+    }
+    let mut chunks = Vec::new();
+    let mut len = 0;
+    while let Some(chunk) = response.chunk().await? {
+        BANDWIDTH
+            .consume(id, &mut *task_bucket.lock().await, chunk.len() as u64)
+            .await;
+        len += chunk.len() as u64;
+        chunks.push(chunk);
+    }
+    if len != range.end - range.start {
+        return Err(DownloadError::ContentLength(format!(
+            "segment {range:?} has {len} bytes"
+        )));
+    }
+    Ok(chunks)
+}
+
+/// download `range` of the file with `connections` parallel range requests, the segments
+/// are written in order, so the file is still appended and hashed from the start
+/// - `writer`: if it is stopped by an error, the download stops and `writer.finish()`
+///   returns the error
+pub async fn download_segmented(
+    url: &str,
+    writer: &ChunkWriter,
+    watcher: &mut SpaceWatcher,
+    control: &mut ControlHandle,
+    id: Id,
+    range: Range<u64>,
+    connections: usize,
+) -> Result<Segmented, DownloadError> {
+    let transfer = async {
+        let task_bucket = Mutex::new(ByteBucket::default());
+        let mut written = range.start;
+        let mut segments = stream::iter(segment_ranges(range, SEGMENT_SIZE))
+            .map(|range| fetch_segment(url, range, id, &task_bucket))
+            .buffered(connections);
+        while let Some(segment) = segments.next().await {
+            let chunks = match segment {
+                Ok(chunks) => chunks,
+                // the segments in flight are dropped with the stream
+                Err(error @ (DownloadError::UrlExpired(403) | DownloadError::Resume(_))) => {
+                    return Ok(Segmented::Rejected { written, error });
+                }
+                Err(e) => return Err(e),
+            };
+            for chunk in chunks {
+                let len = chunk.len() as u64;
+                watcher.wait_for_space().await;
+                if !writer.write(chunk).await {
+                    return Ok(Segmented::Finished);
+                }
+                written += len;
+                let msg = ServerMsg::Download(DownloadMsg {
+                    id,
+                    state: DownloadState::Downloading(len),
+                });
+                BROADCAST_TX.send_msg(msg);
+            }
+        }
+        Ok(Segmented::Finished)
+    };
+    // the segments which are written are kept when the transfer is interrupted
+    tokio::select! {
+        result = transfer => result,
+        error = control.interrupted() => Err(error),
+    }
+}
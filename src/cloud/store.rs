@@ -0,0 +1,80 @@
+use arc_swap::{ArcSwap, Guard};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// data which changes often is saved to its own json file instead of config.json, the file is
+/// written to a temporary file and renamed, so a crash doesn't leave a half written file
+pub struct JsonStore<T> {
+    path: &'static str,
+    data: ArcSwap<T>,
+    /// the updates are applied and saved one by one
+    write_lock: Mutex<()>,
+}
+
+impl<T> JsonStore<T>
+where
+    T: Serialize + DeserializeOwned + Default + Clone,
+{
+    pub fn new(path: &'static str) -> Self {
+        Self {
+            path,
+            data: ArcSwap::from_pointee(T::default()),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn load(&self) -> Guard<Arc<T>> {
+        self.data.load()
+    }
+
+    /// read the file on startup, the data is empty if the file doesn't exist
+    pub fn initialize(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let path = Path::new(self.path);
+        if !path.exists() {
+            return Ok(());
+        }
+        let json = std::fs::read_to_string(path)
+            .map_err(|error| format!("Can not read {}\nError: {error}", self.path))?;
+        let data = serde_json::from_str(&json).map_err(|error| {
+            format!(
+                "Invalid json format, you may try to delete {}\nError: {error}",
+                self.path
+            )
+        })?;
+        self.data.store(Arc::new(data));
+        Ok(())
+    }
+
+    /// apply `cmd` and save the data, the data is still updated if it can't be saved
+    pub async fn update<F>(&self, cmd: F)
+    where
+        F: FnOnce(&mut T),
+    {
+        let _guard = self.write_lock.lock().await;
+        let mut data = T::clone(&self.data.load());
+        cmd(&mut data);
+        #[cfg(not(test))]
+        {
+            if let Err(e) = self.save(&data).await {
+                eprintln!("can not save {}, error: {e}", self.path);
+            }
+        }
+        self.data.store(Arc::new(data));
+    }
+
+    #[cfg(not(test))]
+    async fn save(&self, data: &T) -> std::io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let json = serde_json::to_string(data).map_err(std::io::Error::other)?;
+        let temp_path = format!("{}.tmp", self.path);
+        let mut file = tokio::fs::File::create(&temp_path).await?;
+        file.write_all(json.as_bytes()).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&temp_path, self.path).await
+    }
+}
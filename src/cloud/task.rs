@@ -0,0 +1,189 @@
+use crate::cloud_manager::Task;
+use crate::config_manager::CONFIG;
+use crate::time_stamp::TimeStamp;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// a downloading task is stalled if it has no peers and makes no progress for this long,
+/// it is shorter than `SLOW_QUEUE_TIMEOUT` by more than the longest refresh interval, so a
+/// stalled task is recovered before it is moved to the slow queue
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(900);
+/// a task which is still downloading on cloud after this long is moved to the slow queue
+pub const SLOW_QUEUE_TIMEOUT: Duration = Duration::from_secs(1800);
+/// how many times a stalled or failed task is re-added with the same magnet
+pub const MAX_RETRIES: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Queued,
+    Downloading,
+    /// downloaded on cloud, ready to be downloaded to local
+    Complete,
+    Failed,
+    /// downloading, but there are no peers and no progress for `STALL_TIMEOUT`
+    Stalled,
+}
+
+impl TaskState {
+    /// map the `status` and `percentDone` of 115 to a state, stalled tasks are detected by
+    /// `TaskTracker` because it depends on the history of the task
+    pub fn from_task(task: &Task) -> Self {
+        match task.status {
+            -1 => Self::Failed,
+            _ if task.percent_done >= 100 => Self::Complete,
+            2 => Self::Complete,
+            1 => Self::Downloading,
+            _ => Self::Queued,
+        }
+    }
+}
+
+impl fmt::Display for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            Self::Queued => "queued",
+            Self::Downloading => "downloading",
+            Self::Complete => "complete",
+            Self::Failed => "failed",
+            Self::Stalled => "stalled",
+        };
+        write!(f, "{state}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TaskTransition {
+    /// `None` means the task is seen for the first time
+    pub from: Option<TaskState>,
+    pub to: TaskState,
+    pub at: TimeStamp,
+}
+
+impl fmt::Display for TaskTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.from {
+            Some(from) => write!(f, "{from} -> {} at {}", self.to, self.at),
+            None => write!(f, "{} at {}", self.to, self.at),
+        }
+    }
+}
+
+/// what to do with a stalled or failed task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryAction {
+    /// re-add the task with the same magnet
+    Retry,
+    /// hand the magnet to the fallback backend (qBittorrent)
+    Fallback,
+    /// delete the task and report it
+    GiveUp,
+}
+
+/// it is saved in `Config::task_records`, so the retries are counted across the queues and
+/// restarts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackedTask {
+    pub state: TaskState,
+    pub transitions: Vec<TaskTransition>,
+    pub retries: u32,
+    percent_done: i32,
+    /// a restored task waits `STALL_TIMEOUT` again before it is stalled
+    #[serde(skip, default = "Instant::now")]
+    last_progress: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct TaskTracker {
+    tasks: HashMap<String, TrackedTask>,
+}
+
+impl TaskTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&TrackedTask> {
+        self.tasks.get(hash)
+    }
+
+    /// update the state of a task, returns the transition if the state changed, a task which
+    /// is seen for the first time is restored from `Config::task_records`
+    pub fn observe(&mut self, task: &Task) -> Option<TaskTransition> {
+        if !self.tasks.contains_key(&task.hash)
+            && let Some(tracked) = CONFIG.load().task_records.get(&task.hash)
+        {
+            self.restore(&task.hash, tracked.clone());
+        }
+        self.observe_at(task, Instant::now())
+    }
+
+    pub fn restore(&mut self, hash: &str, tracked: TrackedTask) {
+        self.tasks.insert(hash.to_string(), tracked);
+    }
+
+    pub fn observe_at(&mut self, task: &Task, now: Instant) -> Option<TaskTransition> {
+        let mut state = TaskState::from_task(task);
+        let Some(tracked) = self.tasks.get_mut(&task.hash) else {
+            let transition = TaskTransition {
+                from: None,
+                to: state,
+                at: TimeStamp::now(),
+            };
+            self.tasks.insert(
+                task.hash.clone(),
+                TrackedTask {
+                    state,
+                    transitions: vec![transition],
+                    retries: 0,
+                    percent_done: task.percent_done,
+                    last_progress: now,
+                },
+            );
+            return Some(transition);
+        };
+        if task.percent_done != tracked.percent_done {
+            tracked.percent_done = task.percent_done;
+            tracked.last_progress = now;
+        } else if state == TaskState::Downloading
+            && task.peers == 0
+            && now.duration_since(tracked.last_progress) >= STALL_TIMEOUT
+        {
+            state = TaskState::Stalled;
+        }
+        if state == tracked.state {
+            return None;
+        }
+        let transition = TaskTransition {
+            from: Some(tracked.state),
+            to: state,
+            at: TimeStamp::now(),
+        };
+        tracked.state = state;
+        tracked.transitions.push(transition);
+        Some(transition)
+    }
+
+    /// decide how to recover a stalled or failed task, and count the retry
+    pub fn recovery_action(&mut self, hash: &str, has_fallback: bool) -> RecoveryAction {
+        let Some(tracked) = self.tasks.get_mut(hash) else {
+            return RecoveryAction::GiveUp;
+        };
+        if tracked.retries < MAX_RETRIES {
+            tracked.retries += 1;
+            // give the re-added task a fresh start
+            tracked.last_progress = Instant::now();
+            RecoveryAction::Retry
+        } else if has_fallback {
+            RecoveryAction::Fallback
+        } else {
+            RecoveryAction::GiveUp
+        }
+    }
+
+    pub fn remove(&mut self, hash: &str) -> Option<TrackedTask> {
+        self.tasks.remove(hash)
+    }
+}
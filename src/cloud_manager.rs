@@ -1,34 +1,63 @@
-use crate::cloud::download::{DownloadInfo, FileDownloadUrl, get_download_link};
-use crate::config_manager::{CONFIG, SafeSend};
+use crate::cloud::bandwidth::{BANDWIDTH, ByteBucket};
+use crate::cloud::browser::clear_cloud_dir_cache;
+use crate::cloud::chunk_writer::ChunkWriter;
+use crate::cloud::client::{Pan115Client, is_account_login, set_account_login};
+use crate::cloud::control::{ControlHandle, ControlState, DOWNLOAD_CONTROL};
+use crate::cloud::disk_space::{SpaceWatcher, check_disk_space};
+use crate::cloud::download::{DownloadInfo, FileDownloadUrl};
+use crate::cloud::file_rules::{FileSelector, SkipReason};
+use crate::cloud::history::{DOWNLOAD_HISTORY, record_history};
+use crate::cloud::part_file::{PartFile, PartInfo};
+use crate::cloud::path_template::{LibraryLayout, PathTemplate};
+use crate::cloud::queue::{DOWNLOAD_QUEUE, dequeue, enqueue, reconcile_queue, set_queue_state};
+use crate::cloud::sanitize::{RESERVED_CHARS, UniquePaths, sanitize_name};
+use crate::cloud::segmented::{
+    FRESH_URL, Segmented, download_segmented, remember_capability, segment_plan,
+};
+use crate::config_manager::{
+    AccountPolicy, CONFIG, CloudRetention, Config, HistoryEntry, HostCapability, Message,
+    QueueState, QueuedFile, SafeSend,
+};
 use crate::drop_guard::DropGuard;
-use crate::errors::{CatError, CloudError, DownloadError};
+use crate::errors::{CatError, CloudError, DownloadError, FailedFiles};
 use crate::id::Id;
-use crate::login_with_qrcode::login_with_qrcode;
-use crate::socket_utils::{DownloadMsg, DownloadState, ServerMsg};
-use crate::{
-    BROADCAST_TX, CLIENT_DOWNLOAD, CLIENT_WITH_RETRY, CLIENT_WITH_RETRY_MOBILE, LOGIN_STATUS,
-};
+use crate::login_with_qrcode::{login_with_qrcode, login_with_session};
+use crate::recovery_signal::RECOVERY_SIGNAL;
+use crate::socket_utils::{CloudEntry, CloudQuota, DownloadMsg, DownloadState, ServerMsg};
+use crate::time_stamp::TimeStamp;
+use crate::{BROADCAST_TX, CLIENT_DOWNLOAD, CLOUD_QUOTA, LOGIN_STATUS, TX};
+use bitcode::{Decode, Encode};
 use futures::future::join_all;
+use once_cell::sync::Lazy;
 use regex::Regex;
-use reqwest::Client;
-use reqwest::header::{
-    CONNECTION, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HOST, HeaderMap, HeaderValue,
-};
+use reqwest::header::{ACCEPT_RANGES, CONTENT_LENGTH, RANGE};
+use reqwest::{Client, Response, StatusCode};
 use reqwest_middleware::ClientWithMiddleware;
 use serde::Deserialize;
-use serde_json::{Value, json};
-use std::collections::HashMap;
+use sha1::{Digest, Sha1};
+use std::collections::{HashMap, HashSet};
 use std::fs as sfs;
+use std::io::Read;
 use std::path::{Path, PathBuf};
-use std::str::FromStr;
-use std::sync::Arc;
-use std::sync::atomic::AtomicBool;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::fs;
-use tokio::sync::Semaphore;
+use tokio::sync::{Notify, Semaphore};
 use tokio_retry::Retry;
 use tokio_retry::strategy::FixedInterval;
 
+/// the next account of `AccountPolicy::RoundRobin`
+static NEXT_ACCOUNT: AtomicUsize = AtomicUsize::new(0);
+/// cids of the folders which are being downloaded
+static ACTIVE_FOLDERS: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+/// how many times a file is downloaded again after failing
+const FILE_RETRIES: u32 = 3;
+/// doubled after every retry
+const FILE_RETRY_BACKOFF: Duration = Duration::from_secs(5);
+/// files on the disk are hashed with a large buffer, most of them are multi-GB episodes
+const HASH_BUFFER_SIZE: usize = 1024 * 1024;
+
 pub const MOBILE_UA: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 18_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148 MicroMessenger/8.0.50(0x1800323d) NetType/WIFI Language/zh_CN";
 
 #[derive(Debug, Deserialize)]
@@ -51,6 +80,14 @@ pub struct Task {
     pub folder_id: String,
     #[serde(rename = "delete_file_id")]
     pub file_id: String,
+    /// the magnet link of the task
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub peers: i32,
+    /// the account which owns the task, `None` is the default account
+    #[serde(skip)]
+    pub account: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -66,6 +103,74 @@ pub struct CloudDownloadResult {
     pub url: String,
 }
 
+impl CloudDownloadResult {
+    const TASK_EXISTS: i32 = 10008;
+    const INVALID_LINK: i32 = 10004;
+    const QUOTA_EXCEEDED: i32 = 10010;
+
+    pub fn outcome(self) -> AddTaskOutcome {
+        let hash = self
+            .hash
+            .filter(|hash| !hash.is_empty())
+            .or_else(|| extract_magnet_hash(&self.url));
+        match (self.errcode, hash) {
+            (0, Some(hash)) => AddTaskOutcome::Added(hash),
+            (Self::TASK_EXISTS, Some(hash)) => AddTaskOutcome::AlreadyExists(hash),
+            // it may be added, the task is tracked after the link is added again
+            (0, None) => AddTaskOutcome::Unknown,
+            (Self::TASK_EXISTS | Self::INVALID_LINK, _) => AddTaskOutcome::InvalidLink,
+            (Self::QUOTA_EXCEEDED, _) => AddTaskOutcome::QuotaExceeded,
+            (errcode, _) => AddTaskOutcome::Failed(errcode),
+        }
+    }
+}
+
+/// the result of adding a single url to 115 offline download
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum AddTaskOutcome {
+    /// - task hash
+    Added(String),
+    /// the task was added before, it is tracked as well
+    /// - task hash
+    AlreadyExists(String),
+    /// the link is dropped
+    InvalidLink,
+    /// the link is kept for retry
+    QuotaExceeded,
+    /// the link is kept for retry
+    /// - errcode
+    Failed(i32),
+    /// 115 returned no hash or no result of the link, the link is kept for retry
+    Unknown,
+}
+
+impl AddTaskOutcome {
+    /// the hash of the task which should be tracked
+    pub fn hash(&self) -> Option<&str> {
+        match self {
+            Self::Added(hash) | Self::AlreadyExists(hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    pub fn should_retry(&self) -> bool {
+        matches!(self, Self::QuotaExceeded | Self::Failed(_) | Self::Unknown)
+    }
+}
+
+impl std::fmt::Display for AddTaskOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Added(_) => write!(f, "added"),
+            Self::AlreadyExists(_) => write!(f, "already queued"),
+            Self::InvalidLink => write!(f, "invalid link, dropped"),
+            Self::QuotaExceeded => write!(f, "offline quota exceeded, will retry"),
+            Self::Failed(errcode) => write!(f, "failed with errcode {errcode}, will retry"),
+            Self::Unknown => write!(f, "no result, will retry"),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct FileInfo {
     /// `folder_id` is the id of the folder itself or the id of the file's parent folder
@@ -126,9 +231,11 @@ pub async fn get_cloud_cookies() -> Result<String, CatError> {
             eprintln!("waiting for retry...");
         }
         try_times.store(true, std::sync::atomic::Ordering::Relaxed);
-        login_with_qrcode("tv").await.inspect_err(|e| {
-            eprintln!("Login with qrcode failed, error: {e}");
-        })
+        login_with_qrcode(CONFIG.load().login_app.as_str())
+            .await
+            .inspect_err(|e| {
+                eprintln!("Login with qrcode failed, error: {e}");
+            })
     })
     .await
     {
@@ -139,8 +246,22 @@ pub async fn get_cloud_cookies() -> Result<String, CatError> {
     }
 }
 
+/// 115's cdn answers 403 or 410 when the signed url is expired
+pub fn check_download_status(response: Response) -> Result<Response, DownloadError> {
+    let status = response.status();
+    if status == StatusCode::FORBIDDEN || status == StatusCode::GONE {
+        return Err(DownloadError::UrlExpired(status.as_u16()));
+    }
+    if status == StatusCode::RANGE_NOT_SATISFIABLE {
+        return Err(DownloadError::Resume(format!("HTTP status {status}")));
+    }
+    if !status.is_success() {
+        return Err(format!("download failed, HTTP status {status}").into());
+    }
+    Ok(response)
+}
+
 /// **NOTE:**
-/// We may need to reconsider multi-threaded (parallel range) downloads for a single file.
 /// Some CDN or object storage providers appear to enforce per-file session limits
 /// or anti-abuse policies that reject concurrent Range requests.
 ///
@@ -149,39 +270,105 @@ pub async fn get_cloud_cookies() -> Result<String, CatError> {
 /// ranges with HTTP 403 responses.
 ///
 /// Since this behavior depends on CDN implementation details and cannot be
-/// reliably detected or controlled client-side, it might be safer to fall back
-/// to a single-connection download instead of parallel chunk downloads for
-/// individual files.
+/// reliably detected client-side, a file is downloaded with a single connection
+/// unless its host is opted in by `segmented_hosts`, see `download_segmented`.
+/// A host which rejects a segment falls back to a single connection, and it is
+/// not downloaded in segments again.
 ///
-/// We may add resume support later...
+/// - `offset`: bytes which are already in the file, the rest is requested with `Range`
+/// - `writer`: if it is stopped by an error, the download stops and `writer.finish()`
+///   returns the error
 async fn download_single(
     url: &str,
     client: &Client,
-    file: &mut sfs::File,
+    writer: &ChunkWriter,
+    watcher: &mut SpaceWatcher,
+    control: &mut ControlHandle,
     id: Id,
+    offset: u64,
 ) -> Result<(), DownloadError> {
-    use std::io::Write;
-    let mut response = client.get(url).send().await?;
-    while let Some(chunk) = response.chunk().await? {
-        file.write_all(&chunk)?;
-        let msg = ServerMsg::Download(DownloadMsg {
-            id,
-            state: DownloadState::Downloading(chunk.len() as u64),
-        });
-        BROADCAST_TX.send_msg(msg);
+    let mut request = client.get(url);
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={offset}-"));
+    }
+    let mut response = check_download_status(request.send().await?)?;
+    if offset > 0 && response.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(DownloadError::Resume(format!(
+            "range request is ignored, HTTP status {}",
+            response.status()
+        )));
+    }
+    let transfer = async {
+        let mut task_bucket = ByteBucket::default();
+        while let Some(chunk) = response.chunk().await? {
+            let len = chunk.len() as u64;
+            watcher.wait_for_space().await;
+            BANDWIDTH.consume(id, &mut task_bucket, len).await;
+            // the chunk waits here if the disk is slow, so we stop reading from the network
+            if !writer.write(chunk).await {
+                break;
+            }
+            let msg = ServerMsg::Download(DownloadMsg {
+                id,
+                state: DownloadState::Downloading(len),
+            });
+            BROADCAST_TX.send_msg(msg);
+        }
+        Ok(())
+    };
+    // the chunks which are written are kept when the transfer is interrupted
+    tokio::select! {
+        result = transfer => result,
+        error = control.interrupted() => Err(error),
     }
-    Ok(())
 }
 
+/// run blocking file operations outside of the runtime
+async fn blocking<T, F>(f: F) -> std::io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> std::io::Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+/// - returns the part file, which is opened for appending, and how many bytes of it are kept
+fn open_part_file(
+    part_file: &PartFile,
+    part_info: &PartInfo,
+    accept_ranges: bool,
+) -> std::io::Result<(sfs::File, u64)> {
+    let mut offset = part_file.resume_offset(part_info)?;
+    if offset > 0 && !accept_ranges {
+        println!(
+            "server doesn't support range requests, download {:?} from the start",
+            part_file.part_path()
+        );
+        part_file.remove()?;
+        offset = 0;
+    }
+    part_file.save_info(part_info)?;
+    let file = sfs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(part_file.part_path())?;
+    Ok((file, offset))
+}
+
+/// - `url`: it should be resolved just before, see `FRESH_URL`
 pub async fn download_file(
     url: &str,
     path: &Path,
     id: Id,
     size: u64,
     mut hash: String,
+    control: &mut ControlHandle,
 ) -> Result<(), DownloadError> {
+    let resolved_at = Instant::now();
     let client = &CLIENT_DOWNLOAD;
-    let response = client.head(url).send().await?;
+    let response = check_download_status(client.head(url).send().await?)?;
     let content_length = response
         .headers()
         .get(CONTENT_LENGTH)
@@ -195,16 +382,19 @@ pub async fn download_file(
             "inconsistent content length".to_string(),
         ));
     }
+    let accept_ranges = response
+        .headers()
+        .get(ACCEPT_RANGES)
+        .is_some_and(|value| value.as_bytes() == b"bytes");
     fs::create_dir_all(path.parent().ok_or(DownloadError::Path(
         "path's parent folder is missing".to_string(),
     ))?)
     .await?;
+    // `sha1` is upper case, ensure `hash` is upper case, too.
+    hash.make_ascii_uppercase();
     // if the file exists, check the hash
-    if sfs::exists(path)? {
-        let sha1 = sha1_of_file(path)?;
-        // `sha1` is upper case, ensure `hash` is upper case, too.
-        hash.make_ascii_uppercase();
-        if sha1 == hash {
+    if let Some(len) = file_len(path).await? {
+        if len == size && sha1_hex(hash_file(path, len, id).await?) == hash {
             let msg = ServerMsg::Download(DownloadMsg {
                 id,
                 state: DownloadState::Finished,
@@ -214,24 +404,80 @@ pub async fn download_file(
             // return early
             return Ok(());
         } else {
-            sfs::remove_file(path)?;
+            fs::remove_file(path).await?;
         }
     }
-    let mut file = sfs::File::create(path)?;
-    download_single(url, client, &mut file, id).await?;
-    let sha1 = sha1_of_file(path)?;
-    // `sha1` is upper case, ensure `hash` is upper case, too.
-    hash.make_ascii_uppercase();
+    let part_file = PartFile::new(path);
+    let part_info = PartInfo {
+        url: url.to_string(),
+        size,
+        sha1: hash.clone(),
+    };
+    let (file, offset) = {
+        let part_file = part_file.clone();
+        blocking(move || open_part_file(&part_file, &part_info, accept_ranges)).await?
+    };
+    let mut hasher = Sha1::new();
+    if offset > 0 {
+        println!("resume {path:?} from {offset} bytes");
+        hasher = hash_file(part_file.part_path(), offset, id).await?;
+        BROADCAST_TX.send_msg(ServerMsg::Download(DownloadMsg {
+            id,
+            state: DownloadState::Downloading(offset),
+        }));
+    }
+    let writer = ChunkWriter::spawn(file, hasher);
+    let mut watcher = SpaceWatcher::new(part_file.part_path(), id);
+    // the capability is saved only if the file is verified
+    let mut capability = None;
+    let result = match segment_plan(url).filter(|_| accept_ranges) {
+        _ if offset >= size => Ok(()),
+        Some((host, connections)) => {
+            let range = offset..size;
+            match download_segmented(url, &writer, &mut watcher, control, id, range, connections)
+                .await
+            {
+                Ok(Segmented::Finished) => {
+                    capability = Some((host, HostCapability::Supported));
+                    Ok(())
+                }
+                Ok(Segmented::Rejected { written, error }) => {
+                    println!(
+                        "{host} rejected a segment, error: {error}, download the rest of {path:?} with a single connection"
+                    );
+                    if !matches!(error, DownloadError::UrlExpired(_))
+                        || resolved_at.elapsed() < FRESH_URL
+                    {
+                        remember_capability(&host, HostCapability::Unsupported);
+                    }
+                    download_single(url, client, &writer, &mut watcher, control, id, written).await
+                }
+                Err(e) => Err(e),
+            }
+        }
+        None => download_single(url, client, &writer, &mut watcher, control, id, offset).await,
+    };
+    // the part file is closed before it is removed or renamed
+    let written = writer.finish().await;
+    if let Err(e) = result {
+        if let DownloadError::Resume(_) | DownloadError::Cancelled = e {
+            let part_file = part_file.clone();
+            blocking(move || part_file.remove()).await?;
+        }
+        return Err(e);
+    }
+    let sha1 = sha1_hex(written?);
     if sha1 != hash {
+        blocking(move || part_file.remove()).await?;
         return Err(DownloadError::Hash {
             expected: hash,
             found: sha1,
         });
     }
-    // sync_all() is slow but ensures data is fully flushed to disk.
-    // It is only used together with hash verification to provide
-    // stronger integrity guarantees when needed.
-    file.sync_all()?;
+    if let Some((host, capability)) = capability {
+        remember_capability(&host, capability);
+    }
+    blocking(move || part_file.finish()).await?;
 
     let msg = ServerMsg::Download(DownloadMsg {
         id,
@@ -242,143 +488,275 @@ pub async fn download_file(
     Ok(())
 }
 
-pub fn sha1_of_file(path: &Path) -> std::io::Result<String> {
-    use sha1::{Digest, Sha1};
-    use std::io::Read;
+async fn file_len(path: &Path) -> std::io::Result<Option<u64>> {
+    match fs::metadata(path).await {
+        Ok(metadata) => Ok(Some(metadata.len())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
 
-    let mut file = sfs::File::open(path)?;
-    let mut hasher = Sha1::new();
-    let mut buffer = [0u8; 8192];
+/// hash the first `len` bytes of the file on a blocking thread,
+/// the progress is broadcasted as `DownloadState::Verifying`
+async fn hash_file(path: &Path, len: u64, id: Id) -> std::io::Result<Sha1> {
+    let path = path.to_path_buf();
+    blocking(move || {
+        let file = sfs::File::open(path)?;
+        hash_reader(file.take(len), |n| {
+            let msg = ServerMsg::Download(DownloadMsg {
+                id,
+                state: DownloadState::Verifying(n),
+            });
+            BROADCAST_TX.send_msg(msg);
+        })
+    })
+    .await
+}
 
+/// - `on_progress`: called with the size of every hashed block
+pub fn hash_reader(
+    mut reader: impl Read,
+    mut on_progress: impl FnMut(u64),
+) -> std::io::Result<Sha1> {
+    let mut hasher = Sha1::new();
+    let mut buffer = vec![0u8; HASH_BUFFER_SIZE];
     loop {
-        let n = file.read(&mut buffer)?;
-        if n == 0 {
-            break;
-        }
+        let n = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
         hasher.update(&buffer[..n]);
+        on_progress(n as u64);
     }
+    Ok(hasher)
+}
 
-    let result = hasher.finalize();
-    Ok(format!("{:X}", result))
+/// 115 uses upper case sha1
+pub fn sha1_hex(hasher: Sha1) -> String {
+    format!("{:X}", hasher.finalize())
 }
 
-pub async fn cloud_download(urls: &[String]) -> Result<Vec<String>, CloudError> {
-    let client = &CLIENT_WITH_RETRY_MOBILE;
-    let cookies = &CONFIG.load().cookies;
-    let mut headers = HeaderMap::new();
-    let insert_headers = |map: &mut HeaderMap| -> Result<(), <HeaderValue as FromStr>::Err> {
-        map.insert(HOST, "115.com".parse()?);
-        map.insert(CONNECTION, "keep-alive".parse()?);
-        map.insert(CONTENT_TYPE, "application/x-www-form-urlencoded".parse()?);
-        Ok(())
+/// 115 doesn't allow `RESERVED_CHARS` in file names
+pub fn cloud_folder_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if RESERVED_CHARS.contains(&c) { '_' } else { c })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// look up the folder of a bangumi under the `cloud_root` of the account, create it if it doesn't
+/// exist
+///
+/// returns `None` if `cloud_root` is not set
+pub async fn get_bangumi_folder(
+    ani_name: &str,
+    account: Option<&str>,
+) -> Result<Option<String>, CatError> {
+    let config = CONFIG.load();
+    let Some(root) = config.account_root(account) else {
+        return Ok(None);
     };
-    insert_headers(&mut headers).expect("headers should be valid!");
-    headers.insert(COOKIE, cookies.parse()?);
-    let params = serde_json::json!({
-        "ct": "lixian",
-        "ac": "add_task_urls",
-    });
-    let mut data = HashMap::new();
-    for (index, url) in urls.iter().enumerate() {
-        data.insert(format!("url[{index}]"), url);
-    }
-    let response = client
-        .post("https://115.com/web/lixian/")
-        .headers(headers)
-        .query(&params)
-        .form(&data)
-        .send()
+    let folder_key = cloud_folder_key(root, ani_name, account);
+    let client = Pan115Client::for_account(account)?;
+    if let Some(cid) = config.cloud_folders.get(&folder_key) {
+        // the folder may be deleted on cloud, then it is looked up or created again
+        match client.get_file_info(cid).await {
+            Ok(_) => return Ok(Some(cid.clone())),
+            Err(CloudError::Api(e)) => {
+                eprintln!("cloud folder {cid} of {ani_name} is rejected, error: {e}");
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let folder_name = cloud_folder_name(ani_name);
+    let existing = client
+        .list_all_files(root)
         .await?
-        .text()
-        .await?;
-    let response: CloudDownloadResponse = serde_json::from_str(&response)?;
-    let download_result = response.result;
-    let result = download_result
         .into_iter()
-        .map(|i| match i.hash {
-            Some(hash) => Ok(hash),
-            None => extract_magnet_hash(&i.url)
-                .ok_or(CloudError::Param("invalid magnet link".to_string())),
-        })
-        .collect::<Result<Vec<String>, CloudError>>()?;
-    Ok(result)
+        .find(|info| info.file_id.is_none() && info.name == folder_name);
+    let cid = match existing {
+        Some(info) => info.folder_id,
+        None => {
+            println!("creating cloud folder {folder_name}");
+            client.create_folder(root, &folder_name).await?
+        }
+    };
+    let tx = TX.load_full().ok_or(CatError::Exit)?;
+    let insert_value = cid.clone();
+    let cmd = Box::new(move |config: &mut Config| {
+        config.cloud_folders.insert(folder_key, insert_value);
+    });
+    tx.send_msg(Message::new(cmd, None));
+    Ok(Some(cid))
 }
 
-pub async fn del_cloud_task(hash: &str) -> Result<(), CloudError> {
-    let client = &CLIENT_WITH_RETRY;
-    let cookies = &CONFIG.load().cookies;
-    let mut headers = HeaderMap::new();
-    let insert_headers = |map: &mut HeaderMap| -> Result<(), <HeaderValue as FromStr>::Err> {
-        map.insert(HOST, "115.com".parse()?);
-        map.insert(CONNECTION, "keep-alive".parse()?);
-        map.insert(CONTENT_TYPE, "application/x-www-form-urlencoded".parse()?);
-        Ok(())
+/// the key of `cloud_folders`, the folders are cached again when the root is changed
+fn cloud_folder_key(root: &str, ani_name: &str, account: Option<&str>) -> String {
+    match account {
+        Some(name) => format!("{name}:{root}:{ani_name}"),
+        None => format!("{root}:{ani_name}"),
+    }
+}
+
+const DEFAULT_SPACE_RESERVE: u64 = 1 << 30;
+
+/// fetch the cloud space and the offline download quota of the default account, and broadcast
+/// them to the clients
+pub async fn refresh_cloud_quota() -> Result<CloudQuota, CloudError> {
+    let quota = get_cloud_quota(None).await?;
+    CLOUD_QUOTA.store(Some(Arc::new(quota.clone())));
+    BROADCAST_TX.send_msg(ServerMsg::CloudQuota(quota.clone()));
+    Ok(quota)
+}
+
+pub async fn get_cloud_quota(account: Option<&str>) -> Result<CloudQuota, CloudError> {
+    let client = Pan115Client::for_account(account)?;
+    let (space_total, space_used, space_remain) = client.space_info().await?;
+    let (offline_total, offline_used, offline_remain) = client.offline_quota().await?;
+    Ok(CloudQuota {
+        space_total,
+        space_used,
+        space_remain,
+        offline_total,
+        offline_used,
+        offline_remain,
+    })
+}
+
+/// make sure that `count` offline tasks can be added to the account, returns `CloudError::Quota`
+/// if they would fail
+pub async fn check_cloud_quota(account: Option<&str>, count: usize) -> Result<(), CloudError> {
+    let quota = match account {
+        None => refresh_cloud_quota().await?,
+        Some(_) => get_cloud_quota(account).await?,
     };
-    insert_headers(&mut headers).expect("headers should be valid!");
-    headers.insert(COOKIE, cookies.parse()?);
-    let params = serde_json::json!({
-        "ct": "lixian",
-        "ac": "task_del",
-    });
-    let uid = cookies
-        .split(";")
-        .next()
-        .and_then(|s| s.split("=").nth(1))
-        .and_then(|s| s.split("_").next())
-        .ok_or(CloudError::Cookies("invalid cookies!".to_string()))?;
-    let data = serde_json::json!({
-        "hash[0]": hash,
-        "uid": uid,
-    });
-    let response = client
-        .post("https://115.com/web/lixian/")
-        .headers(headers)
-        .query(&params)
-        .form(&data)
-        .send()
-        .await?
-        .text()
-        .await?;
-    let response_json: Value = serde_json::from_str(&response)?;
-    if response_json["state"] == true {
-        Ok(())
-    } else {
-        Err(response.into())
+    if quota.offline_remain < count as u64 {
+        return Err(CloudError::Quota(format!(
+            "offline download quota of {account:?} is not enough, remain: {}, required: {count}",
+            quota.offline_remain
+        )));
+    }
+    let reserve = CONFIG
+        .load()
+        .cloud_space_reserve
+        .unwrap_or(DEFAULT_SPACE_RESERVE);
+    if quota.space_remain < reserve {
+        return Err(CloudError::Quota(format!(
+            "cloud space of {account:?} is not enough, remain: {} bytes, reserve: {reserve} bytes",
+            quota.space_remain
+        )));
     }
+    Ok(())
 }
 
+/// choose the account for new magnets of a subscription according to `account_policy`, and make
+/// sure that it has enough quota for `count` offline tasks
+pub async fn select_account(bangumi_id: &str, count: usize) -> Result<Option<String>, CloudError> {
+    let config = CONFIG.load();
+    let candidates = config
+        .account_names()
+        .into_iter()
+        .filter(|account| is_account_login(account.as_deref()))
+        .collect::<Vec<_>>();
+    match config.account_policy {
+        AccountPolicy::Fixed => {
+            let account = config.subscription_accounts.get(bangumi_id).cloned();
+            check_cloud_quota(account.as_deref(), count).await?;
+            Ok(account)
+        }
+        AccountPolicy::RoundRobin => {
+            if candidates.is_empty() {
+                return Err(CloudError::Quota("no account is logged in".to_string()));
+            }
+            let index = NEXT_ACCOUNT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let account = candidates[index % candidates.len()].clone();
+            check_cloud_quota(account.as_deref(), count).await?;
+            Ok(account)
+        }
+        AccountPolicy::Failover => {
+            let mut last_error = CloudError::Quota("no account is logged in".to_string());
+            for account in candidates {
+                match check_cloud_quota(account.as_deref(), count).await {
+                    Ok(()) => return Ok(account),
+                    Err(e) => {
+                        eprintln!("account {account:?} is skipped, error: {e}");
+                        last_error = e;
+                    }
+                }
+            }
+            Err(last_error)
+        }
+    }
+}
+
+/// - `folder_id`: the folder to save the tasks to, 115's default folder is used when it is `None`
+pub async fn cloud_download(
+    urls: &[String],
+    folder_id: Option<&str>,
+    account: Option<&str>,
+) -> Result<Vec<(String, AddTaskOutcome)>, CloudError> {
+    Pan115Client::for_account(account)?
+        .add_task_urls(urls, folder_id)
+        .await
+}
+
+/// the task is deleted with the session of the account which owns it
+pub async fn del_cloud_task(hash: &str) -> Result<(), CloudError> {
+    let account = CONFIG
+        .load()
+        .task_account(hash)
+        .map(|name| name.to_string());
+    Pan115Client::for_account(account.as_deref())?
+        .del_task(hash)
+        .await
+}
+
+/// tasks are listed with the session of the account which owns them
 pub async fn get_tasks_list(hash_list: Vec<&String>) -> Result<Vec<Task>, CloudError> {
-    let client = &CLIENT_WITH_RETRY;
-    let cookies = &CONFIG.load().cookies;
-    let mut headers = HeaderMap::new();
-    let insert_headers = |map: &mut HeaderMap| -> Result<(), <HeaderValue as FromStr>::Err> {
-        map.insert(HOST, "115.com".parse()?);
-        map.insert(CONNECTION, "keep-alive".parse()?);
-        Ok(())
-    };
-    insert_headers(&mut headers).expect("headers should be valid!");
-    headers.insert(COOKIE, cookies.parse()?);
-    let mut params = serde_json::json!({
-        "ct": "lixian",
-        "ac": "task_lists",
-        "page": 1,
-    });
-    let response = client
-        .post("https://115.com/web/lixian/")
-        .headers(headers.clone())
-        .query(&params)
-        .send()
-        .await?
-        .text()
-        .await?;
-    let tasks_response: TasksResponse = match serde_json::from_str(&response) {
-        Ok(value) => value,
-        Err(_) => {
-            return Err(CloudError::Api(format!(
-                "Can not get valid tasks list! Error response: {response}"
-            )));
+    let config = CONFIG.load();
+    let mut account_hashes: HashMap<Option<&str>, Vec<&String>> = HashMap::new();
+    for hash in hash_list {
+        account_hashes
+            .entry(config.task_account(hash))
+            .or_default()
+            .push(hash);
+    }
+    let mut tasks = Vec::new();
+    for (account, hash_list) in account_hashes {
+        // the client marks the account expired when its session is expired
+        let was_login = is_account_login(account);
+        match get_account_tasks_list(account, &hash_list).await {
+            Ok(mut list) => tasks.append(&mut list),
+            // don't let an expired extra account block the others
+            Err(e) if let Some(name) = account => {
+                eprintln!("Can not get tasks list of account {name}, error: {e}");
+                // an expired account is only reported when it is found expired
+                let expired = matches!(e, CloudError::SessionExpired(_));
+                if !expired || was_login {
+                    BROADCAST_TX.send_msg(ServerMsg::Error(Box::new((
+                        format!("Failed to get the tasks of account {name}"),
+                        e.to_string(),
+                    ))));
+                }
+                if expired {
+                    set_account_login(account, false);
+                }
+            }
+            Err(e) => return Err(e),
         }
-    };
+    }
+    Ok(tasks)
+}
+
+async fn get_account_tasks_list(
+    account: Option<&str>,
+    hash_list: &[&String],
+) -> Result<Vec<Task>, CloudError> {
+    let client = Pan115Client::for_account(account)?;
+    let tasks_response = client.task_lists(1).await?;
     let mut pages = tasks_response.page_count;
     let mut current_tasks = tasks_response
         .tasks
@@ -390,23 +768,7 @@ pub async fn get_tasks_list(hash_list: Vec<&String>) -> Result<Vec<Task>, CloudE
     while current_tasks.len() < hash_list.len() && page < pages {
         page += 1;
         println!("page: {}", page);
-        params["page"] = page.into();
-        let response = client
-            .post("https://115.com/web/lixian/")
-            .headers(headers.clone())
-            .query(&params)
-            .send()
-            .await?
-            .text()
-            .await?;
-        let tasks_response: TasksResponse = match serde_json::from_str(&response) {
-            Ok(value) => value,
-            Err(_) => {
-                return Err(CloudError::Api(format!(
-                    "Can not get valid tasks list! Error response: {response}"
-                )));
-            }
-        };
+        let tasks_response = client.task_lists(page).await?;
         pages = tasks_response.page_count;
         let mut left_tasks = tasks_response
             .tasks
@@ -415,6 +777,9 @@ pub async fn get_tasks_list(hash_list: Vec<&String>) -> Result<Vec<Task>, CloudE
             .collect::<Vec<_>>();
         current_tasks.append(&mut left_tasks);
     }
+    for task in &mut current_tasks {
+        task.account = account.map(|name| name.to_string());
+    }
     Ok(current_tasks)
 }
 
@@ -424,239 +789,652 @@ pub async fn list_files(
     offset: i32,
     limit: i32,
 ) -> Result<FileListResponse, CloudError> {
-    let params = json!(
-        {
-            "aid": "1",
-            "cid": folder_id,
-            // Order
-            "o": "file_name",
-            // is ascend order?
-            "asc": "1",
-            "offset": offset,
-            "show_dir": "1",
-            "limit": limit,
-            "code": "",
-            "scid": "",
-            "snap": "0",
-            "natsort": "0",
-            "record_open_time": "1",
-            "count_folders": "1",
-            "type": "",
-            "source": "",
-            "format": "json",
-            "fc_mix": "0",
-        }
-    );
-    let cookies = &CONFIG.load().cookies;
-    let mut headers = HeaderMap::new();
-    headers.insert(COOKIE, cookies.parse()?);
-    let response = client
-        .get("https://webapi.115.com/files")
-        .headers(headers)
-        .query(&params)
-        .send()
-        .await?
-        .text()
-        .await?;
-    let result = serde_json::from_str::<FileListResponse>(&response).map_err(|e| {
-        let mut errors = format!("list_files: can not serialize list file response, error: {e}\n");
-        match serde_json::from_str::<Errors>(&response) {
-            Ok(error) => {
-                errors.push_str(&format!(
-                    "list_files: Error No: {}, Error message: {}",
-                    error.error_no, error.msg
-                ));
-            }
-            Err(e) => errors.push_str(&format!("list_files: can not get errors, error: {}", e)),
-        }
-        errors
-    })?;
-
-    Ok(result)
+    Pan115Client::new()?
+        .with_client(client)
+        .list_files(folder_id, offset, limit)
+        .await
 }
 
 pub async fn list_all_files(
     client: &ClientWithMiddleware,
     folder_id: &str,
 ) -> Result<Vec<FileInfo>, CloudError> {
-    let response = list_files(client, folder_id, 0, 20).await?;
-    let file_count = response.count;
-    let mut current = response.files.len() as i32;
-    let mut files = response.files;
-    while current < file_count {
-        let mut response = list_files(client, folder_id, current, file_count - current).await?;
-        current += response.files.len() as i32;
-        files.append(&mut response.files);
-    }
-    Ok(files)
+    Pan115Client::new()?
+        .with_client(client)
+        .list_all_files(folder_id)
+        .await
 }
 
 pub async fn download_a_folder(folder_id: &str, ani_name: Option<&str>) -> Result<(), CloudError> {
-    let client = &CLIENT_WITH_RETRY;
-    let mut storge_path = PathBuf::new();
-    storge_path.push("downloads/115");
-    match ani_name {
-        Some(name) => storge_path.push(name),
-        None => {
-            let result = get_file_info(client, folder_id).await?;
-            storge_path.push(result.name);
+    download_account_folder(folder_id, ani_name, None).await
+}
+
+/// download a folder of an account, `None` is the default account
+pub async fn download_account_folder(
+    folder_id: &str,
+    ani_name: Option<&str>,
+    account: Option<&str>,
+) -> Result<(), CloudError> {
+    let client = Pan115Client::for_account(account)?;
+    // the folder of a task is not changed, resume its queued files instead of listing it
+    // again, folders downloaded by the clients are always listed again
+    if ani_name.is_some() {
+        let queued = DOWNLOAD_QUEUE.load().queued_files(folder_id);
+        if !queued.is_empty() {
+            println!("resume {} queued files of {folder_id}", queued.len());
+            return download_queued(&client, queued).await;
         }
     }
-    let mut files = list_all_files(client, folder_id)
+    let title = match ani_name {
+        Some(name) => name.to_string(),
+        None => client.get_file_info(folder_id).await?.name,
+    };
+    let layout = library_layout(title, ani_name)?;
+    let selector = match CONFIG.load().file_rules(ani_name) {
+        Some(rules) => FileSelector::new(rules)?,
+        None => FileSelector::default(),
+    };
+    let mut skipped_files = Vec::new();
+    let mut select = |file: FileWithPath| match selector.check(&file.info) {
+        Ok(()) => Some(file),
+        Err(reason) => {
+            skipped_files.push((file.path.join(&file.info.name), reason));
+            None
+        }
+    };
+    let mut files = client
+        .list_all_files(folder_id)
         .await?
         .into_iter()
-        .map(|info| FileWithPath {
-            info,
-            path: PathBuf::new(),
+        .filter_map(|info| {
+            select(FileWithPath {
+                info,
+                path: PathBuf::new(),
+            })
         })
         .collect::<Vec<_>>();
     println!("get all files success!");
     let mut files_to_download = Vec::new();
     while let Some(file) = files.pop() {
         match file.info.file_id {
-            Some(_) => {
-                let id = Id::generate();
-                let msg = ServerMsg::Download(DownloadMsg {
-                    id,
-                    state: DownloadState::Start(Box::new((
-                        file.info.name.clone(),
-                        file.info.size.unwrap(),
-                    ))),
-                });
-                BROADCAST_TX.send_msg(msg);
-                let bar_guard = DropGuard::new(id, |id| {
-                    let msg = ServerMsg::Download(DownloadMsg {
-                        id,
-                        state: DownloadState::Failed,
-                    });
-                    BROADCAST_TX.send_msg(msg);
-                });
-                files_to_download.push((bar_guard, file));
-            }
+            Some(_) => files_to_download.push(file),
             None => {
-                // wait for a while to avoid getting banned
-                tokio::time::sleep(Duration::from_secs(1)).await;
-                let mut new_files = list_all_files(client, &file.info.folder_id)
+                let mut new_files = client
+                    .list_all_files(&file.info.folder_id)
                     .await?
                     .into_iter()
-                    .map(|info| {
+                    .filter_map(|info| {
                         let mut file_with_path = FileWithPath {
                             info,
                             path: PathBuf::new(),
                         };
                         file_with_path.path.push(&file.path);
-                        file_with_path.path.push(&file.info.name);
-                        file_with_path
+                        // a folder name can't add more folders or leave the cloud folder
+                        file_with_path.path.push(sanitize_name(&file.info.name));
+                        select(file_with_path)
                     })
                     .collect::<Vec<_>>();
                 files.append(&mut new_files);
             }
         }
     }
+    report_skipped_files(&layout.title, &skipped_files);
+    download_files(
+        &client,
+        &layout,
+        files_to_download,
+        account,
+        folder_id,
+        ani_name.is_none(),
+    )
+    .await
+}
+
+/// where the files of a bangumi, or of a folder downloaded by the clients, are saved
+/// - `ani_name`: `None` for the folders downloaded by the clients
+fn library_layout(title: String, ani_name: Option<&str>) -> Result<LibraryLayout, CloudError> {
+    let config = CONFIG.load();
+    let template = match config.path_template(ani_name) {
+        Some(template) => PathTemplate::parse(template)?,
+        None => PathTemplate::default(),
+    };
+    Ok(LibraryLayout {
+        root: config.library_root(),
+        title,
+        template,
+    })
+}
+
+/// download the files to the paths given by `layout`, they are queued first, so they
+/// are resumed after a restart
+/// - `cid`: the cloud folder which is downloaded
+/// - `manual`: the files are downloaded by the clients
+pub async fn download_files(
+    client: &Pan115Client,
+    layout: &LibraryLayout,
+    mut files: Vec<FileWithPath>,
+    account: Option<&str>,
+    cid: &str,
+    manual: bool,
+) -> Result<(), CloudError> {
+    // colliding files are renamed in order, sort them so the names don't change between runs
+    files.sort_by(|a, b| {
+        (&a.path, &a.info.name, &a.info.file_id).cmp(&(&b.path, &b.info.name, &b.info.file_id))
+    });
+    let mut unique_paths = UniquePaths::default();
+    let mut files = files
+        .into_iter()
+        .filter_map(|file| {
+            let target = unique_paths.claim(layout.file_path(&file.path, &file.info.name));
+            Some(QueuedFile {
+                cid: cid.to_string(),
+                file_id: file.info.file_id?,
+                pick_code: file.info.pick_code,
+                name: file.path.join(&file.info.name),
+                target,
+                size: file.info.size.unwrap_or_default(),
+                sha1: file.info.sha1.unwrap_or_default(),
+                title: layout.title.clone(),
+                account: account.map(|name| name.to_string()),
+                manual,
+                state: QueueState::Queued,
+            })
+        })
+        .collect::<Vec<_>>();
+    // the files which are verified on startup are not hashed again
+    {
+        let queue = DOWNLOAD_QUEUE.load();
+        for file in files.iter_mut().filter(|file| queue.is_verified(file)) {
+            file.state = QueueState::Verified;
+        }
+    }
+    download_queued(client, files).await
+}
+
+/// download the queued files of a cloud folder, and clean up the verified files on the cloud,
+/// `client` should be of the account of the files
+async fn download_queued(client: &Pan115Client, files: Vec<QueuedFile>) -> Result<(), CloudError> {
+    let Some(first) = files.first() else {
+        return Ok(());
+    };
+    let (cid, title, account) = (
+        first.cid.clone(),
+        first.title.clone(),
+        first.account.clone(),
+    );
+    // the queue may be resumed on startup while the clients download the same folder
+    if !ACTIVE_FOLDERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(cid.clone())
+    {
+        return Err(CloudError::Param(format!(
+            "{title} is being downloaded already"
+        )));
+    }
+    let _active_guard = DropGuard::new(cid, |cid| {
+        ACTIVE_FOLDERS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&cid);
+    });
+    // the files which were verified before a restart only need to be cleaned up
+    let (verified, files): (Vec<_>, Vec<_>) = files
+        .into_iter()
+        .partition(|file| file.state == QueueState::Verified);
+    let mut verified_files = verified;
+    if let Some(file) = files.first() {
+        let sizes = files
+            .iter()
+            .map(|file| (file.target.clone(), file.size))
+            .collect();
+        check_disk_space(&file.target, sizes).await?;
+    }
+    enqueue(files.clone()).await;
+    let files_to_download = files
+        .into_iter()
+        .map(|file| {
+            let id = Id::generate();
+            let msg = ServerMsg::Download(DownloadMsg {
+                id,
+                state: DownloadState::Start(Box::new((file.file_name(), file.size))),
+            });
+            BROADCAST_TX.send_msg(msg);
+            let bar_guard = DropGuard::new(id, |id| {
+                let msg = ServerMsg::Download(DownloadMsg {
+                    id,
+                    state: DownloadState::Failed,
+                });
+                BROADCAST_TX.send_msg(msg);
+            });
+            (bar_guard, file)
+        })
+        .collect::<Vec<_>>();
     // restrict parallel downloading tasks
     let sema = Arc::new(Semaphore::new(5));
     let mut download_handles = Vec::new();
     for (id_guard, file) in files_to_download {
-        // wait for a while to avoid getting banned
-        tokio::time::sleep(Duration::from_secs(1)).await;
-        let DownloadInfo {
-            file_name,
-            url: FileDownloadUrl { url, .. },
-            ..
-        } = get_download_link(client, file.info.pick_code).await?;
-        let mut path = storge_path.clone();
-        path.push(file.path);
-        path.push(&file_name);
         let sema = sema.clone();
-        // require the permission first, to avoid holding the
-        // download url for a long time
-        let permit = sema
-            .acquire_owned()
-            .await
-            .expect("semaphore is not closed here");
+        let client = client.clone();
         download_handles.push(tokio::spawn(async move {
-            let _permit = permit;
             let id = *id_guard.inner();
-            (
-                id_guard,
-                download_file(
-                    &url,
-                    &path,
-                    id,
-                    file.info.size.unwrap(),
-                    file.info.sha1.unwrap(),
-                )
-                .await,
-            )
+            let mut control = DOWNLOAD_CONTROL.register(id);
+            // the waiting time for a permit is not a part of the duration
+            let mut started = None;
+            let result = loop {
+                if control.state() == ControlState::Paused {
+                    send_download_state(id, DownloadState::Paused);
+                    if control.wait_while_paused().await == ControlState::Running {
+                        send_download_state(id, DownloadState::Resumed);
+                    }
+                }
+                if control.state() == ControlState::Cancelled {
+                    break Err(CloudError::Download(DownloadError::Cancelled));
+                }
+                // a paused download gives its permit to the others
+                let _permit = sema.acquire().await.expect("semaphore is not closed here");
+                // it may be paused or cancelled while waiting for the permit
+                if control.state() != ControlState::Running {
+                    continue;
+                }
+                started.get_or_insert_with(Instant::now);
+                set_queue_state(vec![file.file_id.clone()], QueueState::Downloading).await;
+                match download_cloud_file(&client, &file, id, &mut control).await {
+                    Err(CloudError::Download(DownloadError::Paused)) => continue,
+                    result => break result,
+                }
+            };
+            match result {
+                Ok(()) => set_queue_state(vec![file.file_id.clone()], QueueState::Verified).await,
+                // the clients are told, and they can download the folder again
+                Err(_) if file.manual => dequeue(vec![file.file_id.clone()]).await,
+                // the file is still on the cloud, it is downloaded when the task is refreshed
+                Err(CloudError::Download(DownloadError::Cancelled)) => {
+                    set_queue_state(vec![file.file_id.clone()], QueueState::Queued).await
+                }
+                // the task is refreshed again later
+                Err(_) => set_queue_state(vec![file.file_id.clone()], QueueState::Failed).await,
+            }
+            // cancelled files are not in the history
+            let cancelled = matches!(result, Err(CloudError::Download(DownloadError::Cancelled)));
+            if let Some(started) = started
+                && !cancelled
+            {
+                record_history(HistoryEntry {
+                    file: file.clone(),
+                    duration: started.elapsed().as_millis() as u64,
+                    finished_at: TimeStamp::now(),
+                    error: result.as_ref().err().map(|e| e.to_string()),
+                })
+                .await;
+            }
+            (id_guard, file, result)
         }));
     }
-    let download_errors = join_all(download_handles)
+    let mut cancelled_files = Vec::new();
+    let mut failed_files = join_all(download_handles)
         .await
         .into_iter()
         .filter_map(|result| {
-            let (id, res) = result.expect("task is not cancelled or panicked");
+            let (id, file, res) = result.expect("task is not cancelled or panicked");
             match res {
                 Ok(()) => {
                     id.into_inner();
+                    verified_files.push(file);
                     None
                 }
-                Err(e) => Some((id.into_inner(), e)),
+                // cancelled files are reported apart from the failed ones
+                Err(CloudError::Download(DownloadError::Cancelled)) => {
+                    send_download_state(id.into_inner(), DownloadState::Cancelled);
+                    cancelled_files.push(file);
+                    None
+                }
+                Err(e) => Some((file.name.to_string_lossy().into_owned(), e)),
             }
         })
         .collect::<Vec<_>>();
-    if !download_errors.is_empty() {
-        return Err(CloudError::DownloadErrors(download_errors));
+    if !cancelled_files.is_empty() {
+        let files = cancelled_files
+            .iter()
+            .map(|file| file.name.display().to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        println!("files of {title} are cancelled:\n{files}");
+        let info = format!(
+            "{} file(s) of {title} are cancelled\n{files}",
+            cancelled_files.len(),
+        );
+        BROADCAST_TX.send_msg(ServerMsg::Info(info.into_boxed_str()));
+    }
+    // the folder is cleaned up only if every file is verified, otherwise the verified files
+    // are kept in the queue until the failed or cancelled files are downloaded again
+    let finished = failed_files.is_empty() && cancelled_files.is_empty();
+    if finished {
+        clean_cloud_files(&verified_files, account.as_deref()).await;
+    }
+    let verified_files = verified_files
+        .into_iter()
+        .filter(|file| finished || file.manual)
+        .map(|file| file.file_id)
+        .collect();
+    dequeue(verified_files).await;
+    // the task is not deleted while a file is cancelled
+    failed_files.extend(cancelled_files.into_iter().map(|file| {
+        (
+            file.name.to_string_lossy().into_owned(),
+            CloudError::Download(DownloadError::Cancelled),
+        )
+    }));
+    if !failed_files.is_empty() {
+        return Err(CloudError::DownloadErrors(FailedFiles(failed_files)));
     }
     Ok(())
 }
 
-pub async fn check_cookies() -> Result<(), CatError> {
-    match list_files(&CLIENT_WITH_RETRY, "0", 0, 1).await {
-        Ok(_) => {
-            LOGIN_STATUS.store(true, std::sync::atomic::Ordering::Relaxed);
+/// verify the queued files with the disk, and resume the folders downloaded by the clients,
+/// the other folders are resumed when their tasks are refreshed
+pub async fn resume_download_queue() {
+    reconcile_queue().await;
+    let batches = DOWNLOAD_QUEUE.load().manual_queue();
+    for files in batches {
+        let Some(first) = files.first() else {
+            continue;
+        };
+        let title = first.title.clone();
+        println!("resume {} queued files of {title}", files.len());
+        let result = match Pan115Client::for_account(first.account.as_deref()) {
+            Ok(client) => download_queued(&client, files).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => {
+                let info = format!("Successfully downloaded the queued files of {title}");
+                BROADCAST_TX.send_msg(ServerMsg::Ok(info.into_boxed_str()));
+            }
+            Err(e) => {
+                eprintln!("can not resume the queued files of {title}, error: {e}");
+                BROADCAST_TX.send_msg(ServerMsg::Error(Box::new((
+                    format!("Failed to resume the queued files of {title}"),
+                    e.to_string(),
+                ))));
+            }
+        }
+    }
+}
+
+/// download a file of the history again, it is saved to the same path
+pub async fn redownload_file(file_id: &str) -> Result<(), CloudError> {
+    let entry = DOWNLOAD_HISTORY
+        .load()
+        .history_of(file_id)
+        .cloned()
+        .ok_or_else(|| CloudError::Param(format!("{file_id} is not in the download history")))?;
+    let client = Pan115Client::for_account(entry.file.account.as_deref())?;
+    let file = QueuedFile {
+        manual: true,
+        state: QueueState::Queued,
+        ..entry.file
+    };
+    download_queued(&client, vec![file]).await
+}
+
+/// download a file, its url is resolved after getting the permit because the signed url
+/// expires after a while, and it is resolved again before every retry
+async fn download_cloud_file(
+    client: &Pan115Client,
+    file: &QueuedFile,
+    id: Id,
+    control: &mut ControlHandle,
+) -> Result<(), CloudError> {
+    let _bandwidth_guard = DropGuard::new(id, |id| BANDWIDTH.remove_task(id));
+    let mut retries = 0;
+    loop {
+        let result = async {
+            let DownloadInfo {
+                url: FileDownloadUrl { url, .. },
+                ..
+            } = client.download_info(&file.pick_code).await?;
+            download_file(
+                &url,
+                &file.target,
+                id,
+                file.size,
+                file.sha1.clone(),
+                control,
+            )
+            .await?;
+            Ok::<(), CloudError>(())
+        }
+        .await;
+        match result {
+            Err(CloudError::Download(e)) if retries < FILE_RETRIES && is_retryable(&e) => {
+                retries += 1;
+                let wait_time = FILE_RETRY_BACKOFF * 2u32.pow(retries - 1);
+                eprintln!(
+                    "can not download {:?}, error: {e}, retry {retries}/{FILE_RETRIES} after {wait_time:?}",
+                    file.name
+                );
+                tokio::time::sleep(wait_time).await;
+                // start the progress bar over again
+                BROADCAST_TX.send_msg(ServerMsg::Download(DownloadMsg {
+                    id,
+                    state: DownloadState::Failed,
+                }));
+                BROADCAST_TX.send_msg(ServerMsg::Download(DownloadMsg {
+                    id,
+                    state: DownloadState::Start(Box::new((file.file_name(), file.size))),
+                }));
+            }
+            result => return result,
+        }
+    }
+}
+
+fn send_download_state(id: Id, state: DownloadState) {
+    BROADCAST_TX.send_msg(ServerMsg::Download(DownloadMsg { id, state }));
+}
+
+/// io and path errors are not fixed by retrying, and paused or cancelled downloads
+/// are handled by `download_files`
+pub fn is_retryable(error: &DownloadError) -> bool {
+    match error {
+        DownloadError::Request(_)
+        | DownloadError::UrlExpired(_)
+        | DownloadError::Hash { .. }
+        | DownloadError::ContentLength(_)
+        | DownloadError::Resume(_) => true,
+        DownloadError::IO(_)
+        | DownloadError::Path(_)
+        | DownloadError::Paused
+        | DownloadError::Cancelled => false,
+    }
+}
+
+/// download files picked in the cloud browser, they are not checked by the file rules
+pub async fn download_cloud_files(
+    folder_name: &str,
+    files: Vec<CloudEntry>,
+) -> Result<(), CloudError> {
+    let client = Pan115Client::new()?;
+    let layout = library_layout(folder_name.to_string(), None)?;
+    let cid = files
+        .first()
+        .map(|file| file.folder_id.clone())
+        .unwrap_or_default();
+    // the files are queued under the cid, a batch must not mix folders
+    if files.iter().any(|file| file.folder_id != cid) {
+        return Err(CloudError::Param(format!(
+            "the files of {folder_name} are in different cloud folders"
+        )));
+    }
+    let files = files
+        .into_iter()
+        .filter(|file| !file.is_folder())
+        .map(|file| FileWithPath {
+            info: file.into(),
+            path: PathBuf::new(),
+        })
+        .collect();
+    download_files(&client, &layout, files, None, &cid, true).await
+}
+
+fn report_skipped_files(title: &str, skipped_files: &[(PathBuf, SkipReason)]) {
+    if skipped_files.is_empty() {
+        return;
+    }
+    let skipped = skipped_files
+        .iter()
+        .map(|(path, reason)| format!("{}: {reason}", path.display()))
+        .collect::<Vec<_>>();
+    for file in &skipped {
+        println!("skip {file}");
+    }
+    let info = format!(
+        "Skipped {} files of {} by the file rules\n{}",
+        skipped.len(),
+        title,
+        skipped.join("\n")
+    );
+    BROADCAST_TX.send_msg(ServerMsg::Info(info.into_boxed_str()));
+}
+
+/// apply `cloud_retention` to the files which are downloaded and verified, the files downloaded
+/// by the clients are always kept
+pub async fn clean_cloud_files(files: &[QueuedFile], account: Option<&str>) {
+    let retention = CONFIG.load().cloud_retention;
+    if retention == CloudRetention::Keep || files.iter().all(|file| file.manual) {
+        return;
+    }
+    clear_cloud_dir_cache();
+    match Pan115Client::for_account(account) {
+        Ok(client) => clean_up_files(&client, retention, files).await,
+        Err(e) => eprintln!("can not clean up cloud files of account {account:?}, error: {e}"),
+    }
+}
+
+pub async fn clean_up_files(
+    client: &Pan115Client,
+    retention: CloudRetention,
+    files: &[QueuedFile],
+) {
+    for file in files.iter().filter(|file| !file.manual) {
+        let result = match retention {
+            CloudRetention::Keep => return,
+            CloudRetention::RecycleBin => client.recycle_file(&file.file_id).await,
+            CloudRetention::Delete => match client.recycle_file(&file.file_id).await {
+                Ok(()) => client.clean_recycled_file(&file.pick_code).await,
+                error => error,
+            },
+        };
+        match result {
+            Ok(()) => println!(
+                "cloud file {} is cleaned up ({retention:?})",
+                file.name.display()
+            ),
+            Err(e) => {
+                eprintln!(
+                    "can not clean up cloud file {}, error: {e}",
+                    file.name.display()
+                );
+                BROADCAST_TX.send_msg(ServerMsg::Error(Box::new((
+                    format!("Failed to clean up cloud file {}", file.name.display()),
+                    e.to_string(),
+                ))));
+            }
         }
-        Err(CloudError::Api(_)) => {
-            println!("Cookies is expired, please login again");
-            LOGIN_STATUS.store(false, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+/// `Ok(false)` means the cookies are expired or invalid
+pub async fn is_cookies_valid(config: &Config, cookies: &str) -> Result<bool, CloudError> {
+    // the cookies may be imported while the current session is still valid
+    let client = match Pan115Client::from_cookies(config, cookies) {
+        Ok(client) => client.detached(),
+        // e.g. the pasted cookies have a line break
+        Err(CloudError::CookiesParse(_)) => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    match client.list_files("0", 0, 1).await {
+        Ok(_) => Ok(true),
+        Err(CloudError::SessionExpired(_) | CloudError::Api(_)) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn check_cookies() -> Result<(), CatError> {
+    let config = CONFIG.load_full();
+    if is_cookies_valid(&config, &config.cookies).await? {
+        LOGIN_STATUS.store(true, std::sync::atomic::Ordering::Relaxed);
+    } else if let Some(secondary_cookies) = &config.secondary_cookies {
+        println!("Cookies is expired, refreshing it with the secondary session");
+        match login_with_session(secondary_cookies, config.login_app.as_str()).await {
+            Ok(cookies) => save_cookies(cookies).await?,
+            Err(e) => {
+                println!("Can not refresh cookies, error: {e}, please login again");
+                LOGIN_STATUS.store(false, std::sync::atomic::Ordering::Relaxed);
+            }
         }
-        error => {
-            error?;
+    } else {
+        println!("Cookies is expired, please login again");
+        LOGIN_STATUS.store(false, std::sync::atomic::Ordering::Relaxed);
+    }
+    for name in config.accounts.keys() {
+        let result = match Pan115Client::for_account(Some(name)) {
+            Ok(client) => client.list_files("0", 0, 1).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(_) => set_account_login(Some(name), true),
+            Err(e) => {
+                println!("Cookies of account {name} are expired, error: {e}");
+                set_account_login(Some(name), false);
+            }
         }
     }
     Ok(())
 }
 
+/// store the cookies of the default account and mark it logged in,
+/// then login `secondary_app` with them if it is set
+pub async fn save_cookies(cookies: String) -> Result<(), CatError> {
+    let tx = TX.load_full().ok_or(CatError::Exit)?;
+    let secondary_app = CONFIG.load().secondary_app;
+    let login_app = CONFIG.load().login_app;
+    let secondary_cookies = match secondary_app {
+        Some(app) if app == login_app => {
+            eprintln!("secondary_app is the same as login_app, it would kick out the main session");
+            None
+        }
+        Some(app) => match login_with_session(&cookies, app.as_str()).await {
+            Ok(secondary_cookies) => Some(secondary_cookies),
+            Err(e) => {
+                eprintln!("Can not login secondary app {app:?}, error: {e}");
+                None
+            }
+        },
+        None => None,
+    };
+    let cmd = Box::new(move |config: &mut Config| {
+        config.cookies = cookies;
+        if secondary_cookies.is_some() {
+            config.secondary_cookies = secondary_cookies;
+        }
+    });
+    let notify = Arc::new(Notify::new());
+    tx.send_msg(Message::new(cmd, Some(notify.clone())));
+    notify.notified().await;
+    LOGIN_STATUS.store(true, std::sync::atomic::Ordering::Relaxed);
+    RECOVERY_SIGNAL.recover();
+    BROADCAST_TX.send_msg(ServerMsg::IsLogin(true));
+    Ok(())
+}
+
 pub async fn get_file_info(
     client: &ClientWithMiddleware,
     folder_id: &str,
 ) -> Result<FileInfoResponse, CloudError> {
-    let params = json!({
-        "cid": folder_id
-    });
-    let cookies = &CONFIG.load().cookies;
-    let mut headers = HeaderMap::new();
-    headers.insert(COOKIE, cookies.parse()?);
-    let response = client
-        .get("https://webapi.115.com/category/get")
-        .headers(headers)
-        .query(&params)
-        .send()
-        .await?
-        .text()
-        .await?;
-    let result = serde_json::from_str::<FileInfoResponse>(&response).map_err(|e| {
-        let mut errors =
-            format!("get file info: can not serialize list file response, error: {e}\n");
-        match serde_json::from_str::<Errors>(&response) {
-            Ok(error) => {
-                errors.push_str(&format!(
-                    "get file info: Error No: {}, Error message: {}",
-                    error.error_no, error.msg
-                ));
-            }
-            Err(e) => errors.push_str(&format!("get file info: can not get errors, error: {}", e)),
-        }
-        errors
-    })?;
-    Ok(result)
+    Pan115Client::new()?
+        .with_client(client)
+        .get_file_info(folder_id)
+        .await
 }
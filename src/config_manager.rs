@@ -1,11 +1,14 @@
+use crate::cloud::path_template::DEFAULT_LIBRARY_ROOT;
+use crate::cloud::task::TrackedTask;
 use crate::time_stamp::TimeStamp;
 use arc_swap::ArcSwap;
 use bitcode::{Decode, Encode};
+use chrono::NaiveTime;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::error::Error;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::{Notify, mpsc};
@@ -33,6 +36,415 @@ pub struct Config {
     /// - `key`: bangumi ID
     /// - `value`: (bangumi name, rss link)
     pub rss_links: HashMap<String, (String, String)>,
+    /// cid of the cloud folder which holds a sub folder for every bangumi,
+    /// offline tasks are saved to 115's default folder when it is `None`
+    #[serde(default)]
+    pub cloud_root: Option<String>,
+    /// - `key`: `<root cid>:<bangumi name>`, prefixed by `<account>:` for the other accounts
+    /// - `value`: cid of its folder under the root
+    #[serde(default)]
+    pub cloud_folders: HashMap<String, String>,
+    /// what to do with the cloud files after they are downloaded and verified
+    #[serde(default)]
+    pub cloud_retention: CloudRetention,
+    /// magnets are handed to qBittorrent instead of 115 offline download when it is set
+    #[serde(default)]
+    pub qbittorrent: Option<QbitConfig>,
+    /// - `key`: torrent hash (lower case hex)
+    /// - `value`: anime name
+    #[serde(default)]
+    pub hash_ani_qbit: HashMap<String, String>,
+    /// offline tasks are deferred when the free cloud space is less than this (in bytes),
+    /// defaults to 1 GiB when it is `None`
+    #[serde(default)]
+    pub cloud_space_reserve: Option<u64>,
+    /// override the base urls of the 115 apis, e.g. to use a local mock server
+    #[serde(default)]
+    pub api_urls: Option<ApiUrls>,
+    /// extra 115 accounts, `cookies` is the default account
+    /// - `key`: account name
+    /// - `value`: Account
+    #[serde(default)]
+    pub accounts: HashMap<String, Account>,
+    /// how to choose the account for new magnets
+    #[serde(default)]
+    pub account_policy: AccountPolicy,
+    /// subscriptions which are not in it use the default account, see `AccountPolicy::Fixed`
+    /// - `key`: bangumi ID
+    /// - `value`: account name
+    #[serde(default)]
+    pub subscription_accounts: HashMap<String, String>,
+    /// tasks of the default account are not in it
+    /// - `key`: task hash
+    /// - `value`: account name
+    #[serde(default)]
+    pub task_accounts: HashMap<String, String>,
+    /// the app profile to login with, logging in with a profile kicks out its other sessions
+    #[serde(default)]
+    pub login_app: LoginApp,
+    /// another app profile which is logged in from the main session after logging in,
+    /// its session is used to refresh `cookies` when they are expired
+    #[serde(default)]
+    pub secondary_app: Option<LoginApp>,
+    /// cookies of `secondary_app`
+    #[serde(default)]
+    pub secondary_cookies: Option<String>,
+    /// which files of a cloud folder are downloaded, every file is downloaded when there
+    /// are no rules for the bangumi and no `default` rules
+    /// - `key`: bangumi ID or `default`
+    /// - `value`: FileRules
+    #[serde(default)]
+    pub file_rules: HashMap<String, FileRules>,
+    /// bandwidth limits of local downloads, they can be overridden by the clients until restart
+    #[serde(default)]
+    pub bandwidth: BandwidthConfig,
+    /// the folder which downloaded files are saved to, `downloads/115` in the working
+    /// directory when it is `None`
+    #[serde(default)]
+    pub library_root: Option<PathBuf>,
+    /// where a downloaded file is saved under `library_root`, see `PathTemplate`,
+    /// the layout of the cloud folder is kept when there is no template
+    /// - `key`: bangumi ID, `default`, or `manual` for folders downloaded by the clients
+    /// - `value`: template, e.g. `{title}/Season {season}/{title} - S{season}E{episode}.{ext}`
+    #[serde(default)]
+    pub path_templates: HashMap<String, String>,
+    /// folders are deferred, and running downloads are paused, when the free space of
+    /// `library_root` would be less than this (in bytes), defaults to 1 GiB when it is `None`
+    #[serde(default)]
+    pub disk_space_reserve: Option<u64>,
+    /// hosts which allow parallel range requests of a file, a file from them is downloaded
+    /// in segments, a key matches the host and its sub domains
+    /// - `key`: host, e.g. `cdnfhnfile.115cdn.net` or `115cdn.net`
+    /// - `value`: connections of a file
+    #[serde(default)]
+    pub segmented_hosts: HashMap<String, usize>,
+    /// what the hosts in `segmented_hosts` did with the segments, the hosts which rejected
+    /// a segment are not downloaded in segments again
+    /// - `key`: host of the download url
+    /// - `value`: HostCapability
+    #[serde(default)]
+    pub segment_capability: HashMap<String, HostCapability>,
+    /// states, transitions and retries of the cloud tasks, they are kept when a task is moved
+    /// to the slow queue or the daemon is restarted
+    /// - `key`: task hash
+    /// - `value`: TrackedTask
+    #[serde(default)]
+    pub task_records: HashMap<String, TrackedTask>,
+}
+
+impl Config {
+    /// `None` is the default account
+    pub fn account_cookies(&self, account: Option<&str>) -> Option<&str> {
+        match account {
+            None => Some(&self.cookies),
+            Some(name) => self.accounts.get(name).map(|a| a.cookies.as_str()),
+        }
+    }
+
+    pub fn account_root(&self, account: Option<&str>) -> Option<&str> {
+        match account {
+            None => self.cloud_root.as_deref(),
+            Some(name) => self.accounts.get(name)?.cloud_root.as_deref(),
+        }
+    }
+
+    /// the default account first, then the others sorted by name
+    pub fn account_names(&self) -> Vec<Option<String>> {
+        let mut names = self.accounts.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        std::iter::once(None)
+            .chain(names.into_iter().map(Some))
+            .collect()
+    }
+
+    pub fn task_account(&self, task_hash: &str) -> Option<&str> {
+        self.task_accounts.get(task_hash).map(|name| name.as_str())
+    }
+
+    /// the rules of the bangumi, or the `default` rules
+    pub fn file_rules(&self, ani_name: Option<&str>) -> Option<&FileRules> {
+        ani_name
+            .and_then(|name| {
+                self.rss_links
+                    .iter()
+                    .find(|(_, (ani, _))| ani == name)
+                    .and_then(|(id, _)| self.file_rules.get(id))
+            })
+            .or_else(|| self.file_rules.get("default"))
+    }
+
+    pub fn library_root(&self) -> PathBuf {
+        self.library_root
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_LIBRARY_ROOT))
+    }
+
+    /// the template of the bangumi, or the `default` template, folders which are
+    /// downloaded by the clients (`ani_name` is `None`) only use the `manual` template
+    pub fn path_template(&self, ani_name: Option<&str>) -> Option<&str> {
+        match ani_name {
+            Some(name) => self
+                .rss_links
+                .iter()
+                .find(|(_, (ani, _))| ani == name)
+                .and_then(|(id, _)| self.path_templates.get(id))
+                .or_else(|| self.path_templates.get("default")),
+            None => self.path_templates.get("manual"),
+        }
+        .map(|template| template.as_str())
+    }
+
+    /// connections of a file from `host`, `None` if it is not downloaded in segments
+    pub fn segment_connections(&self, host: &str) -> Option<usize> {
+        if self.segment_capability.get(host) == Some(&HostCapability::Unsupported) {
+            return None;
+        }
+        self.segmented_hosts
+            .iter()
+            .filter(|(key, _)| {
+                host == key.as_str()
+                    || host
+                        .strip_suffix(key.as_str())
+                        .is_some_and(|sub| sub.ends_with('.'))
+            })
+            // the longest key is the most specific one
+            .max_by_key(|(key, _)| key.len())
+            .map(|(_, connections)| *connections)
+            .filter(|connections| *connections > 1)
+    }
+}
+
+/// a file of the local download queue
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct QueuedFile {
+    /// the cloud folder which is downloaded
+    pub cid: String,
+    pub file_id: String,
+    pub pick_code: String,
+    /// the path in the cloud folder, it is shown to the clients
+    pub name: PathBuf,
+    /// where the file is saved
+    pub target: PathBuf,
+    pub size: u64,
+    pub sha1: String,
+    /// the bangumi name, or the folder name for folders downloaded by the clients
+    pub title: String,
+    /// `None` is the default account
+    #[serde(default)]
+    pub account: Option<String>,
+    /// downloaded by the clients, it is resumed on startup, the others are resumed
+    /// when their tasks are refreshed
+    #[serde(default)]
+    pub manual: bool,
+    #[serde(default)]
+    pub state: QueueState,
+}
+
+impl QueuedFile {
+    /// the cloud name of the file, it is shown on its progress bar
+    pub fn file_name(&self) -> String {
+        self.name
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HostCapability {
+    /// a file is downloaded in segments from it
+    Supported,
+    /// it answered 403 to a segment
+    Unsupported,
+}
+
+/// a file in the download history
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// it is downloaded again from it
+    pub file: QueuedFile,
+    /// from the start of the transfer to the end of the hash check, in milliseconds
+    pub duration: u64,
+    pub finished_at: TimeStamp,
+    /// `None` if the file is downloaded and verified
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueState {
+    #[default]
+    Queued,
+    Downloading,
+    /// it is saved and verified, but the cloud file is not cleaned up yet
+    Verified,
+    /// it is downloaded again when the folder is downloaded next time
+    Failed,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct Account {
+    pub cookies: String,
+    /// same as `Config.cloud_root`, but for this account
+    #[serde(default)]
+    pub cloud_root: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginApp {
+    Web,
+    Android,
+    Ios,
+    Linux,
+    Mac,
+    Windows,
+    #[default]
+    Tv,
+    Alipaymini,
+    Wechatmini,
+    Qandroid,
+}
+
+impl LoginApp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Web => "web",
+            Self::Android => "android",
+            Self::Ios => "ios",
+            Self::Linux => "linux",
+            Self::Mac => "mac",
+            Self::Windows => "windows",
+            Self::Tv => "tv",
+            Self::Alipaymini => "alipaymini",
+            Self::Wechatmini => "wechatmini",
+            Self::Qandroid => "qandroid",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountPolicy {
+    /// use the account in `subscription_accounts`, or the default account
+    #[default]
+    Fixed,
+    /// use the logged in accounts in turn
+    RoundRobin,
+    /// use the first logged in account which has enough quota
+    Failover,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CloudRetention {
+    #[default]
+    Keep,
+    /// delete permanently
+    Delete,
+    RecycleBin,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QbitConfig {
+    /// e.g. `http://127.0.0.1:8080`
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    /// every torrent added by us is put into this category
+    #[serde(default = "QbitConfig::default_category")]
+    pub category: String,
+    /// torrents of a bangumi are saved to `<save_path>/<bangumi name>`
+    pub save_path: String,
+}
+
+impl QbitConfig {
+    fn default_category() -> String {
+        "bangumi".to_string()
+    }
+}
+
+/// limits are in bytes per second, unlimited when they are `None`
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Eq)]
+pub struct BandwidthConfig {
+    /// shared by all downloads
+    #[serde(default)]
+    pub global: Option<u64>,
+    /// of each download
+    #[serde(default)]
+    pub per_task: Option<u64>,
+    /// the first profile which covers the local time is used instead of `global`
+    #[serde(default)]
+    pub profiles: Vec<BandwidthProfile>,
+}
+
+impl BandwidthConfig {
+    pub fn global_limit_at(&self, time: NaiveTime) -> Option<u64> {
+        match self.profiles.iter().find(|profile| profile.covers(time)) {
+            Some(profile) => profile.limit,
+            None => self.global,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BandwidthProfile {
+    /// e.g. `09:00`
+    pub start: NaiveTime,
+    /// it is on the next day if it is not later than `start`, e.g. `23:00` to `07:00`
+    pub end: NaiveTime,
+    #[serde(default)]
+    pub limit: Option<u64>,
+}
+
+impl BandwidthProfile {
+    pub fn covers(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+/// every rule must be passed for a file to be downloaded
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Eq)]
+pub struct FileRules {
+    /// e.g. `mkv`, case insensitive, every extension is allowed when it is empty
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// in bytes
+    #[serde(default)]
+    pub min_size: u64,
+    /// regexes, the file name must match one of them when it is not empty
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// regexes, files and folders whose names match one of them are skipped
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// base urls of the 115 apis, without the trailing `/`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ApiUrls {
+    /// e.g. offline download apis
+    pub web: String,
+    /// e.g. file apis
+    pub webapi: String,
+    /// e.g. download url apis
+    pub proapi: String,
+}
+
+impl Default for ApiUrls {
+    fn default() -> Self {
+        Self {
+            web: "https://115.com".to_string(),
+            webapi: "https://webapi.115.com".to_string(),
+            proapi: "http://proapi.115.com".to_string(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone, Encode, Decode)]
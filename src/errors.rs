@@ -1,6 +1,6 @@
-use crate::id::Id;
 use base64::DecodeError;
 use reqwest::header::InvalidHeaderValue;
+use std::fmt;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -43,8 +43,19 @@ pub enum CloudError {
     Download(#[from] DownloadError),
     #[error("Param error: {0}")]
     Param(String),
-    #[error("Download errors: {0:?}")]
-    DownloadErrors(Vec<(Id, DownloadError)>),
+    #[error("Download errors: {0}")]
+    DownloadErrors(FailedFiles),
+    #[error("qBittorrent error: {0}")]
+    Qbit(String),
+    #[error("Quota error: {0}")]
+    Quota(String),
+    #[error("Session expired: {0}")]
+    SessionExpired(String),
+    #[error("Throttled: {0}")]
+    Throttled(String),
+    /// the download is deferred until there is enough space
+    #[error("Disk space error: {0}")]
+    DiskSpace(String),
 }
 
 #[derive(Error, Debug)]
@@ -59,6 +70,32 @@ pub enum DownloadError {
     ContentLength(String),
     #[error("Hash verify failed, expected: {expected}, found: {found}")]
     Hash { expected: String, found: String },
+    /// the signed download url is expired or rejected, it should be resolved again
+    #[error("Download url expired: HTTP status {0}")]
+    UrlExpired(u16),
+    /// the part file can not be resumed, it is removed and downloaded from the start
+    #[error("Resume error: {0}")]
+    Resume(String),
+    /// the part file is kept, the download goes on after it is resumed
+    #[error("Download is paused")]
+    Paused,
+    #[error("Download is cancelled")]
+    Cancelled,
+}
+
+/// the files which are still failed after retrying
+/// - (path of the file in the cloud folder, error)
+#[derive(Debug)]
+pub struct FailedFiles(pub Vec<(String, CloudError)>);
+
+impl fmt::Display for FailedFiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} file(s) failed", self.0.len())?;
+        for (path, error) in &self.0 {
+            write!(f, "\n{path}: {error}")?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Error, Debug)]
@@ -8,6 +8,7 @@ pub mod errors;
 pub mod id;
 pub mod login_with_qrcode;
 pub mod main_proc;
+pub mod qbittorrent;
 pub mod recovery_signal;
 pub mod socket_utils;
 pub mod time_stamp;
@@ -19,8 +20,8 @@ pub mod tests;
 
 use crate::cas_guard::CASGuard;
 use crate::errors::CatError;
-use crate::socket_utils::ServerMsg;
-use arc_swap::ArcSwapOption;
+use crate::socket_utils::{CloudQuota, ServerMsg};
+use arc_swap::{ArcSwap, ArcSwapOption};
 use chrono::FixedOffset;
 use cloud_manager::MOBILE_UA;
 use config_manager::Message;
@@ -29,6 +30,7 @@ use reqwest::Proxy;
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_retry::RetryTransientMiddleware;
 use reqwest_retry::policies::ExponentialBackoff;
+use std::collections::HashSet;
 use std::mem::ManuallyDrop;
 use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::time::Duration;
@@ -51,6 +53,14 @@ pub static CLIENT_DOWNLOAD: Lazy<reqwest::Client> = Lazy::new(|| {
         .build()
         .unwrap()
 });
+/// segments of a file are sent over separate connections, HTTP/2 would share one
+pub static CLIENT_SEGMENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .user_agent(PC_UA)
+        .http1_only()
+        .build()
+        .unwrap()
+});
 pub static CLIENT_WITH_RETRY: Lazy<ClientWithMiddleware> = Lazy::new(|| {
     ClientBuilder::new(
         reqwest::Client::builder()
@@ -65,20 +75,6 @@ pub static CLIENT_WITH_RETRY: Lazy<ClientWithMiddleware> = Lazy::new(|| {
     ))
     .build()
 });
-pub static CLIENT_WITH_RETRY_MOBILE: Lazy<ClientWithMiddleware> = Lazy::new(|| {
-    ClientBuilder::new(
-        reqwest::Client::builder()
-            .user_agent(MOBILE_UA)
-            .connect_timeout(Duration::from_secs(10))
-            .timeout(std::time::Duration::from_secs(30))
-            .build()
-            .unwrap(),
-    )
-    .with(RetryTransientMiddleware::new_with_policy(
-        ExponentialBackoff::builder().build_with_max_retries(5),
-    ))
-    .build()
-});
 pub static CLIENT_PROXY: Lazy<ClientWithMiddleware> = Lazy::new(|| {
     ClientBuilder::new(
         reqwest::Client::builder()
@@ -108,6 +104,7 @@ type DownloadHandle = Option<JoinHandle<Result<(), CatError>>>;
 // pub static REFRESH_DOWNLOAD: Lazy<DownloadHandle> = Lazy::new(|| Mutex::new(None));
 pub static REFRESH_DOWNLOAD: CASGuard<DownloadHandle> = CASGuard::new(None);
 pub static REFRESH_DOWNLOAD_SLOW: CASGuard<DownloadHandle> = CASGuard::new(None);
+pub static REFRESH_QBIT_DOWNLOAD: CASGuard<DownloadHandle> = CASGuard::new(None);
 pub static REFRESH_NOTIFY: Lazy<Semaphore> = Lazy::new(|| Semaphore::new(0));
 pub static END_NOTIFY: Lazy<Notify> = Lazy::new(Notify::new);
 pub static READY_TO_EXIT: AtomicBool = AtomicBool::new(false);
@@ -115,3 +112,6 @@ pub static UTC_8: FixedOffset = FixedOffset::east_opt(8 * 3600).unwrap();
 pub static RSS_DATA_PERMIT: Lazy<Semaphore> = Lazy::new(|| Semaphore::new(1));
 pub static LOGIN_STATUS: AtomicBool = AtomicBool::new(false);
 pub static CLIENT_COUNT: AtomicUsize = AtomicUsize::new(0);
+pub static CLOUD_QUOTA: ArcSwapOption<CloudQuota> = ArcSwapOption::const_empty();
+/// names of the extra 115 accounts whose cookies are expired
+pub static EXPIRED_ACCOUNTS: Lazy<ArcSwap<HashSet<String>>> = Lazy::new(ArcSwap::default);
@@ -1,9 +1,11 @@
 use crate::config_manager::SafeSend;
 use crate::errors::CloudError;
 use crate::{BROADCAST_TX, CLIENT_DOWNLOAD};
+use qrcode::{Color as QColor, QrCode};
+use reqwest::header::COOKIE;
 use serde::{Deserialize, Serialize};
-
-// const DEVICE: [&str; 11] = ["AppEnum", "web", "android", "ios", "linux", "mac", "windows", "tv", "alipaymini", "wechatmini", "qandroid"];
+use serde_json::Value;
+use std::fmt;
 
 #[derive(Deserialize)]
 pub struct Response<T> {
@@ -87,6 +89,26 @@ async fn get_qrcode_status(client: &reqwest::Client, query: &Query) -> Result<St
 }
 
 pub async fn login_with_qrcode(app: &str) -> Result<String, CloudError> {
+    login_with_qrcode_then(app, |qrcode| {
+        BROADCAST_TX.send_msg(crate::socket_utils::ServerMsg::LoginUrl(qrcode.into()));
+    })
+    .await
+}
+
+/// print the qrcode to stdout instead of sending it to the clients
+pub async fn login_with_qrcode_headless(app: &str) -> Result<String, CloudError> {
+    login_with_qrcode_then(app, |qrcode| match qrcode_to_half_blocks(qrcode) {
+        Ok(output) => println!("{output}"),
+        Err(e) => eprintln!("{e}"),
+    })
+    .await
+}
+
+/// - `show_qrcode`: show the url of the qrcode to the user
+async fn login_with_qrcode_then(
+    app: &str,
+    show_qrcode: impl FnOnce(&str),
+) -> Result<String, CloudError> {
     let client = CLIENT_DOWNLOAD.clone();
     let qrcode_token = get_qrcode_token(&client).await?;
     let Token {
@@ -101,41 +123,7 @@ pub async fn login_with_qrcode(app: &str) -> Result<String, CloudError> {
         sign,
     };
     println!("get qrcode url: {qrcode}");
-    BROADCAST_TX.send_msg(crate::socket_utils::ServerMsg::LoginUrl(
-        qrcode.into_boxed_str(),
-    ));
-    // println!("{}", qrcode);
-    // let code = QrCode::new(qrcode.as_bytes()).map_err(|e| format!("{e}"))?;
-    // let width = code.width();
-    // let mut output = String::new();
-
-    // // 每两个行压缩为一个字符（上下两个像素 -> ▀、▄、█、空格）
-    // for y in (0..width).step_by(2) {
-    //     for x in 0..width {
-    //         let top = code[(x, y)] == Color::Dark;
-    //         let bottom = if y + 1 < width {
-    //             code[(x, y + 1)] == Color::Dark
-    //         } else {
-    //             false
-    //         };
-    //         let ch = match (top, bottom) {
-    //             (true, true) => '█',
-    //             (true, false) => '▀',
-    //             (false, true) => '▄',
-    //             (false, false) => ' ',
-    //         };
-    //         output.push(ch);
-    //     }
-    //     output.push('\n');
-    // }
-    // let code_string = code
-    //     .render::<char>()
-    //     // .dark_color(Rgb([0, 0, 128]))
-    //     // .light_color(Rgb([224, 224, 224])) // adjust colors
-    //     .quiet_zone(false) // disable quiet zone (white border)
-    //     .min_dimensions(1, 1)
-    //     .build();
-    // println!("{}", output);
+    show_qrcode(&qrcode);
     const STATUS: [&str; 5] = [
         "[status=-2] qrcode: canceled",
         "[status=-1] qrcode: expired",
@@ -176,16 +164,120 @@ pub async fn login_with_qrcode(app: &str) -> Result<String, CloudError> {
     }
     println!("try to get cookies");
     let cookies = post_qrcode_result(&client, &uid, app).await?;
-    let cookie_list = vec![
-        ("UID", cookies.UID),
-        ("CID", cookies.CID),
-        ("SEID", cookies.SEID),
-        ("KID", cookies.KID),
-    ];
-    let cookie_str = cookie_list
-        .into_iter()
-        .map(|(key, value)| format!("{}={}", key, value))
-        .collect::<Vec<_>>()
-        .join("; ");
-    Ok(cookie_str)
+    Ok(cookies.to_string())
+}
+
+/// render the qrcode with unicode half blocks, two rows of modules per line,
+/// light modules are drawn so that it can be scanned on a dark terminal
+pub fn qrcode_to_half_blocks(url: &str) -> Result<String, CloudError> {
+    let code = QrCode::new(url.as_bytes())
+        .map_err(|e| CloudError::Param(format!("can not encode the qrcode: {e}")))?;
+    let width = code.width();
+    let mut output = String::new();
+    let quiet_zone = 1;
+    let actual_size = width + 2 * quiet_zone;
+    for y in (0..actual_size).step_by(2) {
+        for x in 0..actual_size {
+            let top = if x >= quiet_zone
+                && y >= quiet_zone
+                && x < width + quiet_zone
+                && y < width + quiet_zone
+            {
+                code[(x - quiet_zone, y - quiet_zone)] == QColor::Light
+            } else {
+                true
+            };
+            let bottom = if x >= quiet_zone
+                && y + 1 >= quiet_zone
+                && x < width + quiet_zone
+                && y + 1 < width + quiet_zone
+            {
+                code[(x - quiet_zone, y + 1 - quiet_zone)] == QColor::Light
+            }
+            // if y is already the last line, don't draw its bottom line
+            else {
+                y != actual_size - 1
+            };
+            let ch = match (top, bottom) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            };
+            output.push(ch);
+        }
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+/// the qrcode apis answer `state` as a bool or a number
+async fn qrcode_action(
+    client: &reqwest::Client,
+    url: &str,
+    query: &[(&str, &str)],
+    cookies: &str,
+) -> Result<(), CloudError> {
+    let response = client
+        .get(url)
+        .query(query)
+        .header(COOKIE, cookies)
+        .send()
+        .await?
+        .text()
+        .await?;
+    let value: Value = serde_json::from_str(&response)?;
+    if value["state"] == true || value["state"] == 1 {
+        Ok(())
+    } else {
+        Err(CloudError::Api(format!(
+            "qrcode action failed, url: {url}, response: {response}"
+        )))
+    }
+}
+
+/// login to `app` with an existing session, the session scans and confirms the qrcode by itself,
+/// so no one needs to scan it
+pub async fn login_with_session(cookies: &str, app: &str) -> Result<String, CloudError> {
+    let client = CLIENT_DOWNLOAD.clone();
+    let Token {
+        uid, time, sign, ..
+    } = get_qrcode_token(&client).await?;
+    qrcode_action(
+        &client,
+        "https://qrcodeapi.115.com/api/2.0/prompt.php",
+        &[("uid", &uid)],
+        cookies,
+    )
+    .await?;
+    qrcode_action(
+        &client,
+        "https://qrcodeapi.115.com/api/2.0/slogin.php",
+        &[("key", &uid), ("uid", &uid), ("client", "0")],
+        cookies,
+    )
+    .await?;
+    let query = Query {
+        uid: uid.clone(),
+        time,
+        sign,
+    };
+    let status = get_qrcode_status(&client, &query).await?.status;
+    if status != 2 {
+        return Err(CloudError::Api(format!(
+            "qrcode is not signed in, status: {status}"
+        )));
+    }
+    let cookies = post_qrcode_result(&client, &uid, app).await?;
+    Ok(cookies.to_string())
+}
+
+impl fmt::Display for Cookies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "UID={}; CID={}; SEID={}; KID={}",
+            self.UID, self.CID, self.SEID, self.KID
+        )
+    }
 }
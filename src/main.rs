@@ -1,6 +1,6 @@
 use bangumi_download::config_manager::SafeSend;
 use bangumi_download::id::Id;
-use bangumi_download::main_proc::initialize;
+use bangumi_download::main_proc::{initialize, login_headless};
 use bangumi_download::socket_utils::{
     ClientMsg, ServerMsg, SocketPath, SocketState, SocketStateDetect,
 };
@@ -16,6 +16,18 @@ use tokio::sync::mpsc::unbounded_channel;
 #[tokio::main]
 async fn main() -> ExitCode {
     let socket_path = SocketPath::new("bangumi_download.socket");
+    if std::env::args().nth(1).as_deref() == Some("login") {
+        return match login_headless(&socket_path).await {
+            Ok(()) => {
+                println!("Successfully logged in");
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("Login failed, error: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
     if let SocketState::Working = socket_path.try_connect() {
         let terminal = ratatui::init();
         let (mut app, rx, handles) = App::initialize(terminal, socket_path);
@@ -1,18 +1,31 @@
-use crate::cloud_manager::{check_cookies, del_cloud_task, download_a_folder, get_tasks_list};
-use crate::config_manager::{Bangumi, CONFIG, Config, Message, SafeSend, modify_config};
+use crate::cloud::history::DOWNLOAD_HISTORY;
+use crate::cloud::queue::DOWNLOAD_QUEUE;
+use crate::cloud::task::{RecoveryAction, SLOW_QUEUE_TIMEOUT, TaskState, TaskTracker};
+use crate::cloud_manager::{
+    Task, check_cookies, cloud_download, del_cloud_task, download_account_folder,
+    get_bangumi_folder, get_tasks_list, refresh_cloud_quota, resume_download_queue, save_cookies,
+};
+use crate::config_manager::{
+    Bangumi, CONFIG, Config, Message, QbitConfig, SafeSend, modify_config,
+};
+use crate::drop_guard::DropGuard;
 use crate::errors::{CatError, CloudError, DownloadError};
 use crate::id::Id;
+use crate::login_with_qrcode::{login_with_qrcode_headless, qrcode_to_half_blocks};
+use crate::qbittorrent::{QbitClient, QbitTorrent, bangumi_save_path, qbit_download};
 use crate::recovery_signal::RECOVERY_SIGNAL;
 use crate::socket_utils::{
-    AnimeCoder, AsyncReadSocketMsg, AsyncWriteSocketMsg, ClientMsg, ServerMsg,
+    AnimeCoder, AsyncReadSocketMsg, AsyncWriteSocketMsg, ClientMsg, DownloadMsg, DownloadState,
+    ServerMsg, SocketPath, SocketState, SocketStateDetect,
 };
 use crate::update_rss::start_rss_receive;
 use crate::{
     BROADCAST_TX, CLIENT_COUNT, END_NOTIFY, LOGIN_STATUS, REFRESH_DOWNLOAD, REFRESH_DOWNLOAD_SLOW,
-    REFRESH_NOTIFY, TX,
+    REFRESH_NOTIFY, REFRESH_QBIT_DOWNLOAD, TX,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
@@ -64,6 +77,12 @@ pub async fn initialize() -> Result<JoinHandle<()>, Box<dyn std::error::Error +
     Config::initial_config()
         .await
         .inspect_err(|error| eprintln!("can not initialize config\n{error}"))?;
+    DOWNLOAD_QUEUE
+        .initialize()
+        .inspect_err(|error| eprintln!("can not initialize the download queue\n{error}"))?;
+    DOWNLOAD_HISTORY
+        .initialize()
+        .inspect_err(|error| eprintln!("can not initialize the download history\n{error}"))?;
     // launch config write thread
     let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
     TX.swap(Some(Arc::new(tx)));
@@ -77,15 +96,70 @@ pub async fn initialize() -> Result<JoinHandle<()>, Box<dyn std::error::Error +
     Ok(config_manager)
 }
 
+/// `bangumi_download login`, login without the tui and exit,
+/// the daemon does the login if it is running, so that its session is refreshed
+pub async fn login_headless(socket_path: &SocketPath) -> Result<(), CatError> {
+    if let SocketState::Working = socket_path.try_connect() {
+        return login_through_daemon(socket_path).await;
+    }
+    Config::initial_config()
+        .await
+        .map_err(|e| CatError::GetCookie(format!("can not initialize config, error: {e}")))?;
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
+    TX.swap(Some(Arc::new(tx)));
+    let config_manager = tokio::spawn(modify_config(rx));
+    let result = async {
+        let cookies = login_with_qrcode_headless(CONFIG.load().login_app.as_str()).await?;
+        save_cookies(cookies).await
+    }
+    .await;
+    drop(TX.swap(None));
+    let _ = config_manager.await;
+    result
+}
+
+async fn login_through_daemon(socket_path: &SocketPath) -> Result<(), CatError> {
+    let mut stream = socket_path.to_stream().await?;
+    stream.write_msg(ClientMsg::LoginReq).await?;
+    // the daemon may report the login state of its current session before the qrcode
+    let mut qrcode_shown = false;
+    loop {
+        match stream.read_msg().await? {
+            ServerMsg::LoginUrl(url) => {
+                println!("{}", qrcode_to_half_blocks(&url)?);
+                qrcode_shown = true;
+            }
+            ServerMsg::LoginState(state) => println!("{state}"),
+            ServerMsg::IsLogin(true) if qrcode_shown => return Ok(()),
+            ServerMsg::QrcodeExpired => {
+                return Err(CatError::GetCookie("qrcode expired".to_string()));
+            }
+            // errors of the other tasks of the daemon are broadcasted, too
+            ServerMsg::LoginFailed(error) => {
+                return Err(CatError::GetCookie(error.into_string()));
+            }
+            ServerMsg::Exit => {
+                return Err(CatError::GetCookie("the daemon is exiting".to_string()));
+            }
+            _ => (),
+        }
+    }
+}
+
 pub async fn refresh_rss() {
     let waiter = RECOVERY_SIGNAL.get_waiter(crate::recovery_signal::WaiterKind::RefreshRss);
-    while !LOGIN_STATUS.load(std::sync::atomic::Ordering::Relaxed) {
+    // qBittorrent backend doesn't need a 115 session
+    while !LOGIN_STATUS.load(std::sync::atomic::Ordering::Relaxed)
+        && CONFIG.load().qbittorrent.is_none()
+    {
         eprintln!("not logged in, waiting...");
         waiter.wait().await;
     }
     // TODO: add actual error handling instead of just printing it
     restart_refresh_download().await.unwrap();
     restart_refresh_download_slow().await.unwrap();
+    restart_refresh_qbit_download().await.unwrap();
+    tokio::spawn(resume_download_queue());
     loop {
         println!("\nChecking updates...\n");
         BROADCAST_TX.send_msg(ServerMsg::Loading);
@@ -102,6 +176,11 @@ pub async fn refresh_rss() {
             // break;
         } else {
             println!("\nCheck finished!\n");
+            if CONFIG.load().qbittorrent.is_none()
+                && let Err(e) = refresh_cloud_quota().await
+            {
+                eprintln!("refresh cloud quota error: {e}");
+            }
             // to avoid cloning data when no client is connected
             if CLIENT_COUNT.load(std::sync::atomic::Ordering::Relaxed) > 0 {
                 let config = CONFIG.load();
@@ -158,7 +237,9 @@ pub async fn refresh_download() -> Result<(), CatError> {
     ];
     let mut wait_time = StatusIter::new(&WAIT_TIME_LIST);
     let mut error_task = HashMap::new();
+    let mut deferred_tasks = HashSet::new();
     let mut task_download_time: HashMap<String, Instant> = HashMap::new();
+    let mut tracker = TaskTracker::new();
     'outer: loop {
         println!("running refresh download");
         let hash_ani = {
@@ -179,17 +260,38 @@ pub async fn refresh_download() -> Result<(), CatError> {
         };
         let tx = TX.load_full().ok_or(CatError::Exit)?;
         for task in tasks_list {
-            // download task failed, delete it
             let task_hash = &task.hash;
-            if task.status == -1 {
-                del_a_task::<HashAni>(&tx, task_hash).await?;
+            if let Some(transition) = tracker.observe(&task) {
+                println!("Task {}: {transition}", task.name);
+                save_task_record(&tx, &tracker, task_hash);
             }
-            if task.percent_done == 100 {
+            let state = tracker.get(task_hash).map(|tracked| tracked.state);
+            if matches!(state, Some(TaskState::Failed | TaskState::Stalled)) {
+                task_download_time.remove(task_hash);
+                if let Err(error) =
+                    recover_task::<HashAni>(&mut tracker, &tx, &task, &hash_ani[task_hash]).await
+                {
+                    eprintln!("Can not recover task {}, error: {error}", task.name);
+                }
+                continue;
+            }
+            if state == Some(TaskState::Complete) {
                 // download file
                 let ani_name = hash_ani[task_hash].to_owned();
                 println!("Downloading task {}", task.name);
                 // TODO: parallelize downloading folders
-                if let Err(error) = download_a_folder(&task.folder_id, Some(&ani_name)).await {
+                let result = download_account_folder(
+                    &task.folder_id,
+                    Some(&ani_name),
+                    task.account.as_deref(),
+                )
+                .await;
+                if let Err(CloudError::DiskSpace(reason)) = &result {
+                    defer_for_disk_space(&mut deferred_tasks, task_hash, &ani_name, reason);
+                    continue;
+                }
+                deferred_tasks.remove(task_hash);
+                if let Err(error) = result {
                     eprintln!("Can not download a task, error: {}", error);
                     error_task
                         .entry(task_hash.to_string())
@@ -205,6 +307,7 @@ pub async fn refresh_download() -> Result<(), CatError> {
                 }
                 // after download
                 del_a_task::<HashAni>(&tx, task_hash).await?;
+                tracker.remove(task_hash);
                 println!("Task {} is finished and deleted!", task.name);
             } else {
                 println!(
@@ -213,7 +316,7 @@ pub async fn refresh_download() -> Result<(), CatError> {
                 );
                 match task_download_time.get(task_hash) {
                     Some(instant) => {
-                        if instant.elapsed().as_secs() > 1800 {
+                        if instant.elapsed() > SLOW_QUEUE_TIMEOUT {
                             // move to slow queue
                             let insert_key = task_hash.to_string();
                             let insert_value = hash_ani[task_hash].to_string();
@@ -231,6 +334,7 @@ pub async fn refresh_download() -> Result<(), CatError> {
                             tx.send_msg(msg);
                             notify.notified().await;
                             task_download_time.remove(task_hash);
+                            tracker.remove(task_hash);
                             restart_refresh_download_slow().await?;
                         }
                     }
@@ -329,6 +433,8 @@ pub async fn refresh_download_slow() -> Result<(), CatError> {
     println!("refresh download slow is started");
     let wait_time = Duration::from_mins(60);
     let mut error_task = HashMap::new();
+    let mut deferred_tasks = HashSet::new();
+    let mut tracker = TaskTracker::new();
     'outer: loop {
         let hash_ani = {
             let config = CONFIG.load();
@@ -348,16 +454,37 @@ pub async fn refresh_download_slow() -> Result<(), CatError> {
         };
         let tx = TX.load_full().ok_or(CatError::Exit)?;
         for task in tasks_list {
-            // download task failed, delete it
             let task_hash = &task.hash;
-            if task.status == -1 {
-                del_a_task::<HashAniSlow>(&tx, task_hash).await?;
+            if let Some(transition) = tracker.observe(&task) {
+                println!("Task {}: {transition}", task.name);
+                save_task_record(&tx, &tracker, task_hash);
             }
-            if task.percent_done == 100 {
+            let state = tracker.get(task_hash).map(|tracked| tracked.state);
+            if matches!(state, Some(TaskState::Failed | TaskState::Stalled)) {
+                if let Err(error) =
+                    recover_task::<HashAniSlow>(&mut tracker, &tx, &task, &hash_ani[task_hash])
+                        .await
+                {
+                    eprintln!("Can not recover task {}, error: {error}", task.name);
+                }
+                continue;
+            }
+            if state == Some(TaskState::Complete) {
                 // download file
                 let ani_name = hash_ani[task_hash].clone();
                 println!("Downloading task {}", task.name);
-                if let Err(error) = download_a_folder(&task.folder_id, Some(&ani_name)).await {
+                let result = download_account_folder(
+                    &task.folder_id,
+                    Some(&ani_name),
+                    task.account.as_deref(),
+                )
+                .await;
+                if let Err(CloudError::DiskSpace(reason)) = &result {
+                    defer_for_disk_space(&mut deferred_tasks, task_hash, &ani_name, reason);
+                    continue;
+                }
+                deferred_tasks.remove(task_hash);
+                if let Err(error) = result {
                     eprintln!("Can not download a task, error: {}", error);
                     error_task
                         .entry(task_hash.to_string())
@@ -371,6 +498,7 @@ pub async fn refresh_download_slow() -> Result<(), CatError> {
                 }
                 // after download
                 del_a_task::<HashAniSlow>(&tx, task_hash).await?;
+                tracker.remove(task_hash);
                 println!("Task {} is finished and deleted!", task.name);
             }
         }
@@ -379,6 +507,206 @@ pub async fn refresh_download_slow() -> Result<(), CatError> {
     println!("refresh download slow is finished");
     Ok(())
 }
+/// the task stays in the queue, and it is downloaded in a later refresh when there is enough
+/// space, the clients are notified when it is deferred for the first time
+fn defer_for_disk_space(
+    deferred_tasks: &mut HashSet<String>,
+    task_hash: &str,
+    ani_name: &str,
+    reason: &str,
+) {
+    eprintln!("Download of {ani_name} is deferred, {reason}");
+    if deferred_tasks.insert(task_hash.to_string()) {
+        BROADCAST_TX.send_msg(ServerMsg::Error(Box::new((
+            format!("Download of {ani_name} is deferred, not enough disk space"),
+            reason.to_string(),
+        ))));
+    }
+}
+
+pub async fn restart_refresh_qbit_download() -> Result<(), CatError> {
+    let mut handle_guard = match REFRESH_QBIT_DOWNLOAD.lock() {
+        Some(h) => h,
+        None => return Ok(()),
+    };
+    let handle = &mut *handle_guard;
+    let restart = |handle: &mut Option<JoinHandle<Result<(), CatError>>>| {
+        let download_handle = tokio::spawn(refresh_qbit_download());
+        *handle = Some(download_handle);
+    };
+    match handle {
+        Some(h) if h.is_finished() => match h.await? {
+            Ok(()) => {
+                println!("restart refresh qbit download");
+                restart(handle);
+            }
+            Err(CatError::Cloud(CloudError::Download(DownloadError::Request(e)))) => {
+                eprintln!("{}", e);
+                restart(handle);
+            }
+            Err(e) => Err(e)?,
+        },
+        None => {
+            println!("start refresh qbit download for the first time");
+            restart(handle);
+        }
+        _ => println!("refresh qbit download is running"),
+    }
+    Ok(())
+}
+
+fn fail_progress_bar(id: Id) {
+    let msg = ServerMsg::Download(DownloadMsg {
+        id,
+        state: DownloadState::Failed,
+    });
+    BROADCAST_TX.send_msg(msg);
+}
+
+type BarGuard = DropGuard<fn(Id), Id>;
+
+/// the session is reused between polls, it logs in again if the session is expired
+async fn fetch_torrents(
+    session: &mut Option<QbitClient>,
+    config: &QbitConfig,
+    hashes: &[&String],
+) -> Result<Vec<QbitTorrent>, CloudError> {
+    if let Some(client) = session {
+        match client.torrents_info(&config.category, hashes).await {
+            Ok(torrents) => return Ok(torrents),
+            Err(error) => eprintln!("qBittorrent session may be expired, login again: {error}"),
+        }
+    }
+    *session = None;
+    let client = session.insert(QbitClient::login(config).await?);
+    client.torrents_info(&config.category, hashes).await
+}
+
+/// poll the torrents we added to qBittorrent, feed their progress to clients,
+/// and move finished torrents to their bangumi folder before removing the tasks
+///
+/// a torrent which can not be moved or removed is tried again in the next poll
+pub async fn refresh_qbit_download() -> Result<(), CatError> {
+    println!("refresh qbit download is started");
+    let wait_time = Duration::from_secs(30);
+    // - `key`: torrent hash
+    // - `value`: (progress bar guard, completed size)
+    let mut progresses: HashMap<String, (BarGuard, u64)> = HashMap::new();
+    let mut session = None;
+    loop {
+        let config = CONFIG.load_full();
+        let Some(qbit_config) = &config.qbittorrent else {
+            break;
+        };
+        let hash_ani = &config.hash_ani_qbit;
+        if hash_ani.is_empty() {
+            break;
+        }
+        let hashes = hash_ani.keys().collect::<Vec<_>>();
+        let torrents = match fetch_torrents(&mut session, qbit_config, &hashes).await {
+            Ok(torrents) => torrents,
+            Err(error) => {
+                eprintln!("Error occurred when attempting to obtain the torrents list: {error}");
+                println!("qBittorrent download refresh is stopped!");
+                break;
+            }
+        };
+        let client = session.as_ref().expect("it is logged in by fetch_torrents");
+        let tx = TX.load_full().ok_or(CatError::Exit)?;
+        // torrents removed from qBittorrent by others will never be finished
+        let missing = hash_ani
+            .keys()
+            .filter(|hash| !torrents.iter().any(|t| t.hash == **hash))
+            .cloned()
+            .collect::<Vec<_>>();
+        for task_hash in missing {
+            eprintln!("torrent {task_hash} is missing in qBittorrent, forget it");
+            progresses.remove(&task_hash);
+            let cmd = Box::new(move |config: &mut Config| {
+                config.hash_ani_qbit.remove(&task_hash);
+            });
+            tx.send_msg(Message::new(cmd, None));
+        }
+        for torrent in torrents {
+            let task_hash = torrent.hash.clone();
+            if !progresses.contains_key(&task_hash) && torrent.size > 0 {
+                let id = Id::generate();
+                let msg = ServerMsg::Download(DownloadMsg {
+                    id,
+                    state: DownloadState::Start(Box::new((torrent.name.clone(), torrent.size))),
+                });
+                BROADCAST_TX.send_msg(msg);
+                let guard: BarGuard = DropGuard::new(id, fail_progress_bar);
+                progresses.insert(task_hash.clone(), (guard, 0));
+            }
+            if let Some((guard, completed)) = progresses.get_mut(&task_hash)
+                && torrent.completed > *completed
+            {
+                let msg = ServerMsg::Download(DownloadMsg {
+                    id: *guard.inner(),
+                    state: DownloadState::Downloading(torrent.completed - *completed),
+                });
+                BROADCAST_TX.send_msg(msg);
+                *completed = torrent.completed;
+            }
+            if torrent.is_failed() {
+                eprintln!(
+                    "torrent {} failed with state: {}",
+                    torrent.name, torrent.state
+                );
+                if let Err(error) = client.delete(&task_hash).await {
+                    eprintln!("can not delete torrent {}, error: {error}", torrent.name);
+                    continue;
+                }
+                // dropping the guard marks the progress bar as failed
+                progresses.remove(&task_hash);
+                let cmd = Box::new(move |config: &mut Config| {
+                    config.hash_ani_qbit.remove(&task_hash);
+                });
+                tx.send_msg(Message::new(cmd, None));
+            } else if torrent.is_complete() {
+                let target = bangumi_save_path(qbit_config, &hash_ani[&task_hash]);
+                // `setLocation` returns before the files are moved, so the torrent is deleted
+                // after its save path is changed in a later poll
+                if Path::new(&torrent.save_path) != target {
+                    if torrent.is_moving() {
+                        continue;
+                    }
+                    println!("moving {} to {:?}", torrent.name, target);
+                    if let Err(error) = client.set_location(&task_hash, &target).await {
+                        eprintln!("can not move torrent {}, error: {error}", torrent.name);
+                    }
+                    continue;
+                }
+                if let Err(error) = client.delete(&task_hash).await {
+                    eprintln!("can not delete torrent {}, error: {error}", torrent.name);
+                    continue;
+                }
+                if let Some((guard, _)) = progresses.remove(&task_hash) {
+                    let msg = ServerMsg::Download(DownloadMsg {
+                        id: guard.into_inner(),
+                        state: DownloadState::Finished,
+                    });
+                    BROADCAST_TX.send_msg(msg);
+                }
+                let cmd = Box::new(move |config: &mut Config| {
+                    config.hash_ani_qbit.remove(&task_hash);
+                });
+                tx.send_msg(Message::new(cmd, None));
+                println!("Torrent {} is finished and deleted!", torrent.name);
+            }
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(wait_time) => {}
+            _ = END_NOTIFY.notified() => {
+                break;
+            }
+        }
+    }
+    println!("refresh qbit download is finished");
+    Ok(())
+}
+
 struct HashAni;
 struct HashAniSlow;
 trait DeleteTask {
@@ -388,6 +716,8 @@ impl DeleteTask for HashAni {
     fn del_a_task(task_hash: String) -> Box<impl Fn(&mut Config) + Send + Sync> {
         Box::new(move |config: &mut Config| {
             config.hash_ani.remove(&task_hash);
+            config.task_accounts.remove(&task_hash);
+            config.task_records.remove(&task_hash);
         })
     }
 }
@@ -395,6 +725,8 @@ impl DeleteTask for HashAniSlow {
     fn del_a_task(task_hash: String) -> Box<impl Fn(&mut Config) + Send + Sync> {
         Box::new(move |config: &mut Config| {
             config.hash_ani_slow.remove(&task_hash);
+            config.task_accounts.remove(&task_hash);
+            config.task_records.remove(&task_hash);
         })
     }
 }
@@ -409,6 +741,112 @@ async fn del_a_task<T: DeleteTask + 'static>(
     Ok(())
 }
 
+/// save the state and retries of a task with it, see `Config::task_records`
+fn save_task_record(tx: &UnboundedSender<Message>, tracker: &TaskTracker, task_hash: &str) {
+    let Some(tracked) = tracker.get(task_hash).cloned() else {
+        return;
+    };
+    let task_hash = task_hash.to_string();
+    let cmd = Box::new(move |config: &mut Config| {
+        config.task_records.insert(task_hash, tracked);
+    });
+    tx.send_msg(Message::new(cmd, None));
+}
+
+/// stop tracking a task which is deleted on cloud, and report it
+fn give_up_task<T: DeleteTask + 'static>(
+    tracker: &mut TaskTracker,
+    tx: &UnboundedSender<Message>,
+    task: &Task,
+    ani_name: &str,
+    reason: String,
+) {
+    let cmd = T::del_a_task(task.hash.clone());
+    tx.send_msg(Message::new(cmd, None));
+    tracker.remove(&task.hash);
+    BROADCAST_TX.send_msg(ServerMsg::Error(Box::new((
+        format!("Task {} of {ani_name} is given up", task.name),
+        reason,
+    ))));
+}
+
+/// retry a stalled or failed task with the same magnet, hand it to qBittorrent when retries
+/// are used up, or give up and report it
+///
+/// the task is kept if it returns an error, and it is recovered again in the next refresh
+async fn recover_task<T: DeleteTask + 'static>(
+    tracker: &mut TaskTracker,
+    tx: &UnboundedSender<Message>,
+    task: &Task,
+    ani_name: &str,
+) -> Result<(), CatError> {
+    let qbit_config = CONFIG.load().qbittorrent.clone();
+    let action = if task.url.is_empty() {
+        RecoveryAction::GiveUp
+    } else {
+        tracker.recovery_action(&task.hash, qbit_config.is_some())
+    };
+    save_task_record(tx, tracker, &task.hash);
+    let links = [task.url.clone()];
+    match (action, qbit_config) {
+        (RecoveryAction::Retry, _) => {
+            println!("Retry task {} with the same magnet", task.name);
+            let account = task.account.as_deref();
+            // the folder is resolved before the task is deleted, so the task is kept if it fails
+            let folder_id = get_bangumi_folder(ani_name, account).await?;
+            del_cloud_task(&task.hash).await?;
+            // the task is deleted on cloud, so it is not tracked any more if it is not re-added
+            let error = match cloud_download(&links, folder_id.as_deref(), account).await {
+                Ok(outcomes) => outcomes
+                    .iter()
+                    .find(|(_, outcome)| outcome.hash().is_none())
+                    .map(|(_, outcome)| format!("can not re-add the task: {outcome}")),
+                Err(error) => Some(format!("can not re-add the task: {error}")),
+            };
+            if let Some(error) = error {
+                give_up_task::<T>(tracker, tx, task, ani_name, error);
+            }
+        }
+        (RecoveryAction::Fallback, Some(qbit_config)) => {
+            println!("Hand task {} over to qBittorrent", task.name);
+            let hash_list = qbit_download(&qbit_config, ani_name, &links).await?;
+            let ani_name = ani_name.to_string();
+            let cmd = Box::new(move |config: &mut Config| {
+                for hash in hash_list {
+                    config.hash_ani_qbit.insert(hash, ani_name.clone());
+                }
+            });
+            let notify = Arc::new(Notify::new());
+            tx.send_msg(Message::new(cmd, Some(notify.clone())));
+            notify.notified().await;
+            if let Err(error) = restart_refresh_qbit_download().await {
+                eprintln!("can not restart refresh qbit download, error: {error}");
+            }
+            del_a_task::<T>(tx, &task.hash).await?;
+            tracker.remove(&task.hash);
+        }
+        _ => {
+            del_a_task::<T>(tx, &task.hash).await?;
+            let transitions = tracker
+                .remove(&task.hash)
+                .map(|tracked| {
+                    tracked
+                        .transitions
+                        .iter()
+                        .map(|t| t.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                })
+                .unwrap_or_default();
+            BROADCAST_TX.send_msg(ServerMsg::Error(Box::new((
+                format!("Task {} of {ani_name} is given up", task.name),
+                format!("history: {transitions}"),
+            ))));
+        }
+    }
+    Ok(())
+}
+
 pub async fn write_socket(
     mut rx: UnboundedReceiver<ServerMsg>,
     mut write: OwnedWriteHalf,
@@ -0,0 +1,239 @@
+use crate::CLIENT;
+use crate::cloud::sanitize::sanitize_name;
+use crate::cloud_manager::extract_magnet_hash;
+use crate::config_manager::QbitConfig;
+use crate::errors::CloudError;
+use reqwest::StatusCode;
+use reqwest::header::{COOKIE, SET_COOKIE};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize)]
+pub struct QbitTorrent {
+    /// lower case hex info hash
+    pub hash: String,
+    pub name: String,
+    /// 0.0 ~ 1.0
+    pub progress: f64,
+    /// size of the selected files
+    pub size: u64,
+    /// downloaded size of the selected files
+    pub completed: u64,
+    pub state: String,
+    pub save_path: String,
+}
+
+impl QbitTorrent {
+    pub fn is_complete(&self) -> bool {
+        self.progress >= 1.0
+    }
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self.state.as_str(), "error" | "missingFiles")
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.state == "moving"
+    }
+}
+
+pub struct QbitClient {
+    base_url: String,
+    /// the `SID` cookie returned by `/api/v2/auth/login`
+    sid: String,
+}
+
+impl QbitClient {
+    pub async fn login(config: &QbitConfig) -> Result<Self, CloudError> {
+        let base_url = config.url.trim_end_matches('/').to_string();
+        let response = CLIENT
+            .post(format!("{base_url}/api/v2/auth/login"))
+            .form(&[
+                ("username", config.username.as_str()),
+                ("password", config.password.as_str()),
+            ])
+            .send()
+            .await?;
+        if response.status() == StatusCode::FORBIDDEN {
+            return Err(CloudError::Qbit(
+                "login is banned because of too many failed attempts".to_string(),
+            ));
+        }
+        let sid = response
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find_map(|cookie| {
+                cookie
+                    .split(';')
+                    .next()
+                    .and_then(|pair| pair.trim().strip_prefix("SID="))
+                    .map(|sid| sid.to_string())
+            });
+        let text = response.text().await?;
+        match sid {
+            Some(sid) if text.trim() == "Ok." => Ok(Self { base_url, sid }),
+            _ => Err(CloudError::Qbit(format!(
+                "can not login to qBittorrent, response: {text}"
+            ))),
+        }
+    }
+
+    async fn post(&self, api: &str, form: &[(&str, &str)]) -> Result<String, CloudError> {
+        let response = CLIENT
+            .post(format!("{}/api/v2/{api}", self.base_url))
+            .header(COOKIE, format!("SID={}", self.sid))
+            .form(form)
+            .send()
+            .await?;
+        let status = response.status();
+        let text = response.text().await?;
+        if status.is_success() {
+            Ok(text)
+        } else {
+            Err(CloudError::Qbit(format!(
+                "{api} failed with status: {status}, response: {text}"
+            )))
+        }
+    }
+
+    /// create the category if it doesn't exist, qBittorrent responds 409 when it exists
+    pub async fn ensure_category(&self, category: &str) -> Result<(), CloudError> {
+        match self
+            .post("torrents/createCategory", &[("category", category)])
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(CloudError::Qbit(e)) if e.contains("409") => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn add_magnets(
+        &self,
+        urls: &[String],
+        category: &str,
+        save_path: &Path,
+    ) -> Result<(), CloudError> {
+        let urls = urls.join("\n");
+        let save_path = save_path.to_string_lossy();
+        let text = self
+            .post(
+                "torrents/add",
+                &[
+                    ("urls", urls.as_str()),
+                    ("category", category),
+                    ("savepath", &save_path),
+                    ("autoTMM", "false"),
+                ],
+            )
+            .await?;
+        if text.trim() == "Fails." {
+            return Err(CloudError::Qbit(
+                "qBittorrent refused to add the magnets".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn torrents_info(
+        &self,
+        category: &str,
+        hashes: &[&String],
+    ) -> Result<Vec<QbitTorrent>, CloudError> {
+        let hashes = hashes
+            .iter()
+            .map(|hash| hash.as_str())
+            .collect::<Vec<_>>()
+            .join("|");
+        let text = self
+            .post(
+                "torrents/info",
+                &[("category", category), ("hashes", &hashes)],
+            )
+            .await?;
+        serde_json::from_str::<Vec<QbitTorrent>>(&text).map_err(|e| {
+            CloudError::Qbit(format!(
+                "can not parse torrents info, error: {e}, response: {text}"
+            ))
+        })
+    }
+
+    pub async fn set_location(&self, hash: &str, location: &Path) -> Result<(), CloudError> {
+        self.post(
+            "torrents/setLocation",
+            &[("hashes", hash), ("location", &location.to_string_lossy())],
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// remove the task only, downloaded files are kept
+    pub async fn delete(&self, hash: &str) -> Result<(), CloudError> {
+        self.post(
+            "torrents/delete",
+            &[("hashes", hash), ("deleteFiles", "false")],
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// the title is sanitized in the same way as the cloud downloads, so it is a single folder
+pub fn bangumi_save_path(config: &QbitConfig, ani_name: &str) -> PathBuf {
+    let mut path = PathBuf::from(&config.save_path);
+    path.push(sanitize_name(ani_name));
+    path
+}
+
+/// qBittorrent always reports info hashes as lower case hex, but magnet links may use base32
+pub fn normalize_info_hash(hash: &str) -> Option<String> {
+    match hash.len() {
+        40 => Some(hash.to_ascii_lowercase()),
+        32 => {
+            const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+            let mut bits = 0u64;
+            let mut bit_count = 0;
+            let mut output = String::with_capacity(40);
+            for ch in hash.bytes() {
+                let value = ALPHABET
+                    .iter()
+                    .position(|c| *c == ch.to_ascii_uppercase())?;
+                bits = (bits << 5) | value as u64;
+                bit_count += 5;
+                while bit_count >= 8 {
+                    bit_count -= 8;
+                    output.push_str(&format!("{:02x}", (bits >> bit_count) & 0xff));
+                }
+            }
+            Some(output)
+        }
+        _ => None,
+    }
+}
+
+pub fn magnet_info_hash(link: &str) -> Option<String> {
+    extract_magnet_hash(link).and_then(|hash| normalize_info_hash(&hash))
+}
+
+/// add magnets to qBittorrent, and return their info hashes
+pub async fn qbit_download(
+    config: &QbitConfig,
+    ani_name: &str,
+    urls: &[String],
+) -> Result<Vec<String>, CloudError> {
+    // nothing is added if any of the links is invalid
+    let hashes = urls
+        .iter()
+        .map(|url| {
+            magnet_info_hash(url).ok_or(CloudError::Param(format!("invalid magnet link: {url}")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let client = QbitClient::login(config).await?;
+    client.ensure_category(&config.category).await?;
+    client
+        .add_magnets(urls, &config.category, &bangumi_save_path(config, ani_name))
+        .await?;
+    Ok(hashes)
+}
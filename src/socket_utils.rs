@@ -1,16 +1,26 @@
-use crate::cloud_manager::{download_a_folder, get_cloud_cookies};
-use crate::config_manager::{Bangumi, CONFIG, Config, Message, SafeSend, SubGroup};
+use crate::cloud::bandwidth::BANDWIDTH;
+use crate::cloud::browser::{CLOUD_DIR_PAGE_SIZE, list_cloud_dir};
+use crate::cloud::control::DOWNLOAD_CONTROL;
+use crate::cloud::history::{DOWNLOAD_HISTORY, clear_history, history_msg};
+use crate::cloud::rate_limit::API_LIMITER;
+use crate::cloud_manager::{
+    AddTaskOutcome, FileInfo, download_a_folder, download_cloud_files, get_cloud_cookies,
+    is_cookies_valid, redownload_file, save_cookies,
+};
+use crate::config_manager::{Bangumi, CONFIG, Config, HistoryEntry, Message, SafeSend, SubGroup};
 use crate::errors::{CatError, SocketError};
 use crate::id::Id;
 use crate::main_proc::{
-    read_socket, restart_refresh_download, restart_refresh_download_slow, write_socket,
+    read_socket, restart_refresh_download, restart_refresh_download_slow,
+    restart_refresh_qbit_download, write_socket,
 };
 use crate::recovery_signal::{RECOVERY_SIGNAL, Waiting};
 use crate::time_stamp::TimeStampCoder;
 use crate::tui::progress_bar::{Inc, ProgressBar, ProgressState, ProgressSuit, SimpleBar};
 use crate::update_rss::{check_rss_link, rss_receive, start_rss_receive};
 use crate::{
-    BROADCAST_TX, CLIENT_COUNT, CLIENT_WITH_RETRY, END_NOTIFY, LOGIN_STATUS, RSS_DATA_PERMIT, TX,
+    BROADCAST_TX, CLIENT_COUNT, CLIENT_WITH_RETRY, CLOUD_QUOTA, END_NOTIFY, LOGIN_STATUS,
+    RSS_DATA_PERMIT, TX,
 };
 use bitcode::{Decode, DecodeOwned, Encode};
 use std::collections::HashMap;
@@ -244,8 +254,16 @@ impl SocketListener {
                     self.state.add(download_msg.id, bar);
                     Some(msg)
                 }
+                DownloadState::Verifying(delta) => {
+                    if let Some(bar) = self.state.get_bar_mut(download_msg.id) {
+                        bar.set_verifying(true);
+                        bar.inc(delta);
+                    }
+                    None
+                }
                 DownloadState::Downloading(delta) => {
                     if let Some(bar) = self.state.get_bar_mut(download_msg.id) {
+                        bar.set_verifying(false);
                         bar.inc(delta);
                         // if bar.is_finished() {
                         //     println!("remove the bar as it is finished");
@@ -266,7 +284,12 @@ impl SocketListener {
                         None
                     }
                 }
-                DownloadState::Failed => {
+                DownloadState::Paused | DownloadState::Resumed => {
+                    let bar = self.state.get_bar_mut(download_msg.id)?;
+                    bar.set_paused(matches!(download_msg.state, DownloadState::Paused));
+                    Some(msg)
+                }
+                DownloadState::Failed | DownloadState::Cancelled => {
                     if self.state.remove(download_msg.id).is_some() {
                         Some(msg)
                     } else {
@@ -316,6 +339,12 @@ impl SocketListener {
                     tx.send_msg(ServerMsg::IsLogin(
                         LOGIN_STATUS.load(std::sync::atomic::Ordering::Relaxed),
                     ));
+                    if let Some(quota) = CLOUD_QUOTA.load_full() {
+                        tx.send_msg(ServerMsg::CloudQuota(quota.as_ref().clone()));
+                    }
+                    tx.send_msg(ServerMsg::RateLimit(API_LIMITER.state()));
+                    tx.send_msg(ServerMsg::Bandwidth(BANDWIDTH.state()));
+                    tx.send_msg(history_msg());
                 } else {
                     eprintln!("stream write tx is closed");
                 };
@@ -335,6 +364,90 @@ impl SocketListener {
                     }
                 });
             }
+            ClientMsg::ClearHistory => {
+                tokio::spawn(clear_history());
+            }
+            ClientMsg::RedownloadFile(file_id) => {
+                let name = DOWNLOAD_HISTORY
+                    .load()
+                    .history_of(&file_id)
+                    .map_or_else(|| file_id.to_string(), |entry| entry.file.file_name());
+                tokio::spawn(async move {
+                    if let Err(e) = redownload_file(&file_id).await {
+                        eprintln!("download {name} again error: {e}");
+                        BROADCAST_TX.send_msg(ServerMsg::Error(Box::new((
+                            format!("Failed to download {name} again"),
+                            e.to_string(),
+                        ))));
+                    } else {
+                        let info = format!("Successfully downloaded {name} again");
+                        BROADCAST_TX.send_msg(ServerMsg::Ok(info.into_boxed_str()));
+                    }
+                });
+            }
+            ClientMsg::ListCloudDir(ptr) => {
+                let Some(tx) = self.stream_write_txs.get(&msg_id).cloned() else {
+                    eprintln!("stream write tx is closed");
+                    return;
+                };
+                tokio::spawn(async move {
+                    let (cid, page, refresh) = *ptr;
+                    match list_cloud_dir(&cid, page, refresh).await {
+                        Ok(dir) => tx.send_msg(ServerMsg::CloudDir(Box::new(dir))),
+                        Err(e) => {
+                            eprintln!("can not list cloud folder {cid}, error: {e}");
+                            tx.send_msg(ServerMsg::Error(Box::new((
+                                "Failed to list the cloud folder".into(),
+                                e.to_string(),
+                            ))));
+                        }
+                    }
+                });
+            }
+            ClientMsg::SetBandwidth(ptr) => {
+                let (id, limit) = *ptr;
+                println!("set bandwidth limit of {id:?} to {limit:?}");
+                let state = BANDWIDTH.set(id, limit);
+                BROADCAST_TX.send_msg(ServerMsg::Bandwidth(state));
+            }
+            ClientMsg::PauseDownload(id) => {
+                if !DOWNLOAD_CONTROL.pause(id) {
+                    eprintln!("can not pause download {id}, it is not running");
+                }
+            }
+            ClientMsg::ResumeDownload(id) => {
+                if !DOWNLOAD_CONTROL.resume(id) {
+                    eprintln!("can not resume download {id}, it is not paused");
+                }
+            }
+            ClientMsg::CancelDownload(id) => {
+                if !DOWNLOAD_CONTROL.cancel(id) {
+                    eprintln!("can not cancel download {id}, it is not found");
+                }
+            }
+            ClientMsg::PauseAllDownloads => {
+                println!("pause all downloads");
+                DOWNLOAD_CONTROL.pause_all();
+            }
+            ClientMsg::ResumeAllDownloads => {
+                println!("resume all downloads");
+                DOWNLOAD_CONTROL.resume_all();
+            }
+            ClientMsg::DownloadCloudFiles(ptr) => {
+                tokio::spawn(async move {
+                    let (folder_name, files) = *ptr;
+                    if let Err(e) = download_cloud_files(&folder_name, files).await {
+                        eprintln!("download cloud files error: {e}");
+                        BROADCAST_TX.send_msg(ServerMsg::Error(Box::new((
+                            format!("Failed to download files of {folder_name}"),
+                            e.to_string(),
+                        ))));
+                    } else {
+                        let info = format!("Successfully downloaded the files of {folder_name}");
+                        BROADCAST_TX.send_msg(ServerMsg::Ok(info.into_boxed_str()));
+                    }
+                });
+            }
             ClientMsg::LoginReq => {
                 if self
                     .handles
@@ -345,23 +458,13 @@ impl SocketListener {
                 } else {
                     let handle = tokio::spawn(async {
                         match get_cloud_cookies().await {
-                            Ok(cookies) => match TX.load().as_ref() {
-                                Some(tx) => {
-                                    let cmd = Box::new(|config: &mut Config| {
-                                        config.cookies = cookies;
-                                    });
-                                    let notify = Arc::new(Notify::new());
-                                    let msg = Message::new(cmd, Some(notify.clone()));
-                                    tx.send_msg(msg);
-                                    notify.notified().await;
-                                    BROADCAST_TX
-                                        .send_msg(ServerMsg::Ok("Successfully logged in".into()));
-                                    LOGIN_STATUS.store(true, std::sync::atomic::Ordering::Relaxed);
-                                    RECOVERY_SIGNAL.recover();
-                                    BROADCAST_TX.send_msg(ServerMsg::IsLogin(true));
-                                }
-                                None => {
-                                    eprintln!("Can not store cookies, error: {}", CatError::Exit);
+                            Ok(cookies) => match save_cookies(cookies).await {
+                                Ok(()) => BROADCAST_TX
+                                    .send_msg(ServerMsg::Ok("Successfully logged in".into())),
+                                Err(e) => {
+                                    eprintln!("Can not store cookies, error: {e}");
+                                    let error = format!("can not store cookies, error: {e}");
+                                    BROADCAST_TX.send_msg(ServerMsg::LoginFailed(error.into()));
                                 }
                             },
                             Err(e) => {
@@ -370,6 +473,8 @@ impl SocketListener {
                                     "Failed to get cloud cookies".into(),
                                     e.to_string(),
                                 ))));
+                                let error = format!("can not get cloud cookies, error: {e}");
+                                BROADCAST_TX.send_msg(ServerMsg::LoginFailed(error.into()));
                                 BROADCAST_TX.send_msg(ServerMsg::QrcodeExpired);
                             }
                         }
@@ -384,6 +489,25 @@ impl SocketListener {
                     }
                 }
             }
+            ClientMsg::ImportCookies(cookies) => {
+                tokio::spawn(async move {
+                    let cookies = cookies.trim().to_string();
+                    let result = match is_cookies_valid(&CONFIG.load_full(), &cookies).await {
+                        Ok(true) => save_cookies(cookies).await,
+                        Ok(false) => Err(CatError::GetCookie("cookies are invalid".to_string())),
+                        Err(e) => Err(e.into()),
+                    };
+                    match result {
+                        Ok(()) => {
+                            BROADCAST_TX.send_msg(ServerMsg::Ok("Cookies are imported".into()))
+                        }
+                        Err(e) => BROADCAST_TX.send_msg(ServerMsg::Error(Box::new((
+                            "Failed to import cookies".into(),
+                            e.to_string(),
+                        )))),
+                    }
+                });
+            }
             ClientMsg::DeleteAnime(id) => match TX.load().as_ref() {
                 Some(tx) => {
                     let permit = RSS_DATA_PERMIT.acquire().await.unwrap();
@@ -413,6 +537,7 @@ impl SocketListener {
                             rss_receive(tx, &rss_link, &old_config, &CLIENT_WITH_RETRY).await?;
                             restart_refresh_download().await?;
                             restart_refresh_download_slow().await?;
+                            restart_refresh_qbit_download().await?;
                             Ok(())
                         };
                         if let Err(e) = rss_update().await {
@@ -759,6 +884,9 @@ pub enum ServerMsg {
     LoginState(Box<str>),
     IsLogin(bool),
     QrcodeExpired,
+    /// the login which is requested by `ClientMsg::LoginReq` failed, other errors are
+    /// reported by `Error`
+    LoginFailed(Box<str>),
     Ok(Box<str>),
     Info(Box<str>),
     RSSData(Box<[AnimeCoder]>),
@@ -769,6 +897,15 @@ pub enum ServerMsg {
     Error(Box<(String, String)>),
     DownloadSync(Box<[ProgressState]>),
     SyncResp(Box<SyncInfo>),
+    CloudQuota(CloudQuota),
+    RateLimit(ApiRateLimit),
+    Bandwidth(BandwidthState),
+    /// only sent to the client which lists the folder
+    CloudDir(Box<CloudDir>),
+    /// - (bangumi name, magnet link, outcome)
+    AddTask(Box<(String, String, AddTaskOutcome)>),
+    /// the whole download history, it is sent after every change
+    History(Box<[HistoryCoder]>),
     Exit,
 }
 
@@ -777,6 +914,24 @@ pub enum ClientMsg {
     /// - cid
     DownloadFolder(Box<str>),
     LoginReq,
+    /// - cookies, they are validated before saving
+    ImportCookies(Box<str>),
+    /// - (cid, page, ignore the cache)
+    ListCloudDir(Box<(String, u32, bool)>),
+    /// - (download id, limit), the limit of all downloads when the id is `None`
+    SetBandwidth(Box<(Option<Id>, BandwidthLimit)>),
+    /// - download id
+    PauseDownload(Id),
+    /// - download id
+    ResumeDownload(Id),
+    /// - download id
+    CancelDownload(Id),
+    /// downloads which are started later are paused, too
+    PauseAllDownloads,
+    ResumeAllDownloads,
+    /// - (folder name, files), files are saved with the `manual` path template,
+    ///   the folder name is its `{title}`
+    DownloadCloudFiles(Box<(String, Vec<CloudEntry>)>),
     GetFilters,
     GetWaitingState,
     Recover,
@@ -789,6 +944,9 @@ pub enum ClientMsg {
     AddRSS(Box<str>),
     RefreshRSS,
     SyncQuery,
+    ClearHistory,
+    /// - file id, the latest history entry of the file is downloaded again
+    RedownloadFile(Box<str>),
     Exit,
 }
 
@@ -821,8 +979,209 @@ pub enum DownloadState {
     Start(Box<(String, u64)>),
     /// - increment size
     Downloading(u64),
+    /// - increment size of the hashed data
+    Verifying(u64),
+    Paused,
+    Resumed,
     Finished,
     Failed,
+    /// the download is stopped by a client, and its part file is removed
+    Cancelled,
+}
+
+/// sizes are in bytes, offline quotas are counts of tasks
+#[derive(Encode, Decode, Debug, Clone, Default)]
+pub struct CloudQuota {
+    pub space_total: u64,
+    pub space_used: u64,
+    pub space_remain: u64,
+    pub offline_total: u64,
+    pub offline_used: u64,
+    pub offline_remain: u64,
+}
+
+/// a bandwidth limit set by the clients
+#[derive(Encode, Decode, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BandwidthLimit {
+    /// follow the config
+    #[default]
+    Auto,
+    Unlimited,
+    /// bytes per second
+    PerSecond(u64),
+}
+
+impl BandwidthLimit {
+    /// - `auto`: follow the config
+    pub fn or_else(self, auto: impl FnOnce() -> Option<u64>) -> Option<u64> {
+        match self {
+            Self::Auto => auto(),
+            Self::Unlimited => None,
+            Self::PerSecond(bytes) => Some(bytes),
+        }
+    }
+
+    /// e.g. `auto`, `unlimited`, `0` (unlimited), `500K`, `5M`, `1.5M`, `1G`, `1048576`
+    pub fn parse(input: &str) -> Option<Self> {
+        let input = input.trim().to_ascii_lowercase();
+        match input.as_str() {
+            "auto" => return Some(Self::Auto),
+            "unlimited" | "0" => return Some(Self::Unlimited),
+            _ => (),
+        }
+        let input = input.trim_end_matches("/s").trim_end_matches(['b', 'i']);
+        let (number, unit) = match input.char_indices().last()? {
+            (i, 'k') => (&input[..i], 1024.0),
+            (i, 'm') => (&input[..i], 1024.0 * 1024.0),
+            (i, 'g') => (&input[..i], 1024.0 * 1024.0 * 1024.0),
+            _ => (input, 1.0),
+        };
+        let bytes = number.trim().parse::<f64>().ok()? * unit;
+        (bytes.is_finite() && bytes >= 1.0).then_some(Self::PerSecond(bytes as u64))
+    }
+}
+
+/// bandwidth limits which are in use, in bytes per second
+#[derive(Encode, Decode, Debug, Clone, Default)]
+pub struct BandwidthState {
+    /// unlimited when it is `None`
+    pub global: Option<u64>,
+    pub global_override: BandwidthLimit,
+    /// the default limit of each task
+    pub per_task: Option<u64>,
+}
+
+/// state of the limiter of the 115 apis
+#[derive(Encode, Decode, Debug, Clone, Default)]
+pub struct ApiRateLimit {
+    /// requests per second
+    pub rate: f64,
+    pub max_rate: f64,
+    pub throttled_count: u32,
+    pub last_throttled: Option<TimeStampCoder>,
+}
+
+/// a file in the download history
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct HistoryCoder {
+    pub file_id: String,
+    /// the file name in the cloud
+    pub name: String,
+    /// the bangumi name, or the folder name for folders downloaded by the clients
+    pub title: String,
+    pub size: u64,
+    /// in milliseconds
+    pub duration: u64,
+    /// where the file is saved
+    pub path: String,
+    pub finished_at: TimeStampCoder,
+    /// `None` if the file is downloaded and verified
+    pub error: Option<String>,
+}
+
+impl From<&HistoryEntry> for HistoryCoder {
+    fn from(entry: &HistoryEntry) -> Self {
+        Self {
+            file_id: entry.file.file_id.clone(),
+            name: entry.file.file_name(),
+            title: entry.file.title.clone(),
+            size: entry.file.size,
+            duration: entry.duration,
+            path: entry.file.target.to_string_lossy().into_owned(),
+            finished_at: entry.finished_at.into(),
+            error: entry.error.clone(),
+        }
+    }
+}
+
+impl HistoryCoder {
+    /// average speed in bytes per second
+    pub fn speed(&self) -> u64 {
+        self.size.saturating_mul(1000) / self.duration.max(1)
+    }
+}
+
+/// a file or folder in the cloud browser
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct CloudEntry {
+    /// the id of the folder itself or the id of the file's parent folder
+    pub folder_id: String,
+    /// only file has `file_id`
+    pub file_id: Option<String>,
+    pub name: String,
+    pub sha1: Option<String>,
+    pub size: Option<u64>,
+    pub pick_code: String,
+}
+
+impl CloudEntry {
+    pub fn is_folder(&self) -> bool {
+        self.file_id.is_none()
+    }
+
+    /// `file_id` of a file, or `folder_id` of a folder
+    pub fn id(&self) -> &str {
+        self.file_id.as_deref().unwrap_or(&self.folder_id)
+    }
+}
+
+impl From<FileInfo> for CloudEntry {
+    fn from(value: FileInfo) -> Self {
+        let FileInfo {
+            folder_id,
+            file_id,
+            name,
+            sha1,
+            size,
+            pick_code,
+        } = value;
+        Self {
+            folder_id,
+            file_id,
+            name,
+            sha1,
+            size,
+            pick_code,
+        }
+    }
+}
+
+impl From<CloudEntry> for FileInfo {
+    fn from(value: CloudEntry) -> Self {
+        let CloudEntry {
+            folder_id,
+            file_id,
+            name,
+            sha1,
+            size,
+            pick_code,
+        } = value;
+        Self {
+            folder_id,
+            file_id,
+            name,
+            sha1,
+            size,
+            pick_code,
+        }
+    }
+}
+
+/// a page of a cloud folder
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct CloudDir {
+    pub cid: String,
+    /// starts from 0
+    pub page: u32,
+    /// count of all files and folders in the folder
+    pub count: u32,
+    pub entries: Vec<CloudEntry>,
+}
+
+impl CloudDir {
+    pub fn page_count(&self) -> u32 {
+        self.count.div_ceil(CLOUD_DIR_PAGE_SIZE).max(1)
+    }
 }
 
 #[derive(Encode, Decode, Debug, Clone)]
@@ -165,6 +165,867 @@ fn test_extract_magnet_hash() {
     }
 }
 
+#[cfg(not(miri))]
+#[test]
+fn test_add_task_outcome() {
+    use crate::cloud_manager::{AddTaskOutcome, CloudDownloadResult};
+    let link = "magnet:?xt=urn:btih:40882fa906a4fe9da7b57fa53a7bd880ad3244ce";
+    let hash = "40882fa906a4fe9da7b57fa53a7bd880ad3244ce".to_string();
+    let result = |errcode, hash: Option<&str>, url: &str| CloudDownloadResult {
+        errcode,
+        hash: hash.map(|h| h.to_string()),
+        url: url.to_string(),
+    };
+    let cases = [
+        (
+            result(0, Some(&hash), link),
+            AddTaskOutcome::Added(hash.clone()),
+        ),
+        // fall back to the hash in the link
+        (
+            result(0, Some(""), link),
+            AddTaskOutcome::Added(hash.clone()),
+        ),
+        (
+            result(10008, None, link),
+            AddTaskOutcome::AlreadyExists(hash.clone()),
+        ),
+        (result(10004, None, link), AddTaskOutcome::InvalidLink),
+        // 115 may add it, so it is retried
+        (
+            result(0, None, "magnet:?xt=urn:btih:INVALIDHASH"),
+            AddTaskOutcome::Unknown,
+        ),
+        (
+            result(10008, None, "magnet:?xt=urn:btih:INVALIDHASH"),
+            AddTaskOutcome::InvalidLink,
+        ),
+        (result(10010, None, link), AddTaskOutcome::QuotaExceeded),
+        (result(911, None, link), AddTaskOutcome::Failed(911)),
+    ];
+    for (result, expected) in cases {
+        let outcome = result.outcome();
+        assert_eq!(
+            outcome.should_retry(),
+            outcome.hash().is_none() && outcome != AddTaskOutcome::InvalidLink
+        );
+        assert_eq!(outcome, expected);
+    }
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_task_tracker() {
+    use crate::cloud::task::{
+        RecoveryAction, SLOW_QUEUE_TIMEOUT, STALL_TIMEOUT, TaskState, TaskTracker,
+    };
+    use crate::cloud_manager::Task;
+    use std::time::{Duration, Instant};
+    let task = |status, percent_done, peers| Task {
+        hash: "hash".to_string(),
+        percent_done,
+        name: "name".to_string(),
+        status,
+        folder_id: String::new(),
+        file_id: String::new(),
+        url: "magnet:?xt=urn:btih:hash".to_string(),
+        peers,
+        account: None,
+    };
+    let mut tracker = TaskTracker::new();
+    let start = Instant::now();
+    let first = tracker.observe_at(&task(0, 0, 0), start).unwrap();
+    assert_eq!((first.from, first.to), (None, TaskState::Queued));
+    let next = tracker.observe_at(&task(1, 0, 0), start).unwrap();
+    assert_eq!(next.from, Some(TaskState::Queued));
+    assert_eq!(next.to, TaskState::Downloading);
+    // no progress, but there are peers
+    let later = start + STALL_TIMEOUT;
+    assert!(tracker.observe_at(&task(1, 0, 3), later).is_none());
+    let stalled = tracker.observe_at(&task(1, 0, 0), later).unwrap();
+    assert_eq!(stalled.to, TaskState::Stalled);
+    // progress resumes
+    let resumed = tracker
+        .observe_at(&task(1, 10, 0), later + Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(resumed.to, TaskState::Downloading);
+    assert_eq!(tracker.get("hash").unwrap().transitions.len(), 4);
+    let failed = tracker.observe_at(&task(-1, 10, 0), later).unwrap();
+    assert_eq!(failed.to, TaskState::Failed);
+    assert_eq!(tracker.recovery_action("hash", true), RecoveryAction::Retry);
+    assert_eq!(tracker.recovery_action("hash", true), RecoveryAction::Retry);
+    assert_eq!(
+        tracker.recovery_action("hash", true),
+        RecoveryAction::Fallback
+    );
+    assert_eq!(
+        tracker.recovery_action("hash", false),
+        RecoveryAction::GiveUp
+    );
+    let complete = tracker.observe_at(&task(2, 100, 0), later).unwrap();
+    assert_eq!(complete.to, TaskState::Complete);
+    // the retries are kept in the config, e.g. when the task is moved to the slow queue
+    let mut config = Config::default();
+    config
+        .task_records
+        .insert("hash".to_string(), tracker.get("hash").unwrap().clone());
+    let json = serde_json::to_string(&config).unwrap();
+    let mut config: Config = serde_json::from_str(&json).unwrap();
+    let mut restored = TaskTracker::new();
+    restored.restore("hash", config.task_records.remove("hash").unwrap());
+    assert_eq!(restored.get("hash").unwrap().transitions.len(), 6);
+    assert_eq!(
+        restored.recovery_action("hash", false),
+        RecoveryAction::GiveUp
+    );
+    // a stalled task is recovered before it is moved to the slow queue
+    assert!(STALL_TIMEOUT + Duration::from_secs(600) < SLOW_QUEUE_TIMEOUT);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_pan115_response() {
+    use crate::cloud::client::{
+        check_response, is_account_login, parse_user_id, set_account_login,
+    };
+    use crate::errors::CloudError;
+    assert_eq!(
+        parse_user_id("UID=123456_A1_1700000000; CID=abc; SEID=def; KID=ghi"),
+        Some("123456".to_string())
+    );
+    assert_eq!(parse_user_id("CID=abc; SEID=def"), None);
+    assert!(check_response("ok", r#"{"state":true,"data":[]}"#).is_ok());
+    // some apis don't have `state`
+    assert!(check_response("ok", r#"{"count":5,"used":1,"surplus":4}"#).is_ok());
+    assert!(matches!(
+        check_response("err", r#"{"state":false,"error":"参数错误","errNo":20001}"#),
+        Err(CloudError::Api(msg)) if msg.contains("20001") && msg.contains("参数错误")
+    ));
+    assert!(matches!(
+        check_response(
+            "expired",
+            r#"{"state":false,"error_msg":"请重新登录","errno":99}"#
+        ),
+        Err(CloudError::SessionExpired(_))
+    ));
+    assert!(matches!(
+        check_response(
+            "throttled",
+            r#"{"state":false,"error":"请求过于频繁","errno":0}"#
+        ),
+        Err(CloudError::Throttled(_))
+    ));
+    set_account_login(Some("second"), false);
+    assert!(!is_account_login(Some("second")));
+    set_account_login(Some("second"), true);
+    assert!(is_account_login(Some("second")));
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_rate_limiter() {
+    use crate::cloud::rate_limit::{MIN_RATE, RECOVER_INTERVAL, RECOVER_STEP, RateLimiter};
+    use std::time::{Duration, Instant};
+    let limiter = RateLimiter::new(1.0, 2.0);
+    let now = Instant::now();
+    // burst, then one request per second
+    assert_eq!(limiter.reserve_at(now), Duration::ZERO);
+    assert_eq!(limiter.reserve_at(now), Duration::ZERO);
+    assert_eq!(limiter.reserve_at(now), Duration::from_secs(1));
+    assert_eq!(limiter.reserve_at(now), Duration::from_secs(2));
+    // the waiting requests are not affected, the next one waits at the halved rate
+    let now = now + Duration::from_secs(2);
+    let state = limiter.throttled_at(now);
+    assert_eq!(state.rate, 0.5);
+    assert_eq!(state.throttled_count, 1);
+    assert!(state.last_throttled.is_some());
+    assert_eq!(limiter.reserve_at(now), Duration::from_secs(2));
+    for _ in 0..10 {
+        limiter.throttled_at(now);
+    }
+    assert_eq!(limiter.state().rate, MIN_RATE);
+    // it recovers step by step
+    assert!(limiter.succeeded_at(now + Duration::from_secs(1)).is_none());
+    let now = now + RECOVER_INTERVAL;
+    let state = limiter.succeeded_at(now).unwrap();
+    assert!((state.rate - (MIN_RATE + RECOVER_STEP)).abs() < 1e-9);
+    assert!(limiter.succeeded_at(now).is_none());
+    let now = now + RECOVER_INTERVAL * 20;
+    for i in 0..20 {
+        limiter.succeeded_at(now + RECOVER_INTERVAL * i);
+    }
+    assert_eq!(limiter.state().rate, 1.0);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_path_sanitize() {
+    use crate::cloud::path_template::{LibraryLayout, PathTemplate};
+    use crate::cloud::sanitize::{
+        MAX_NAME_BYTES, UniquePaths, sanitize_name, sanitize_relative_path, truncate_name,
+    };
+    use crate::cloud_manager::cloud_folder_name;
+    use std::path::{Path, PathBuf};
+
+    assert_eq!(
+        sanitize_name("[喵萌奶茶屋] 时光流逝，饭菜依旧美味 / Hibi wa Sugiredo Meshi Umashi"),
+        "[喵萌奶茶屋] 时光流逝，饭菜依旧美味 _ Hibi wa Sugiredo Meshi Umashi"
+    );
+    assert_eq!(sanitize_name(".."), "_");
+    assert_eq!(sanitize_name("."), "_");
+    assert_eq!(sanitize_name("   "), "_");
+    assert_eq!(sanitize_name("../../etc/passwd"), ".._.._etc_passwd");
+    assert_eq!(sanitize_name("C:\\Windows"), "C__Windows");
+    assert_eq!(sanitize_name("a\0b\nc\u{7f}"), "a_b_c_");
+    assert_eq!(sanitize_name("What?<>|*\"end\". "), "What______end_");
+    assert_eq!(sanitize_name("con.mkv"), "_con.mkv");
+    // the cloud folders share the reserved characters, but keep the other names
+    assert_eq!(cloud_folder_name(" What?<>|*\"end\". "), "What______end_.");
+    assert_eq!(sanitize_name("LPT1"), "_LPT1");
+    assert_eq!(sanitize_name("Console.mkv"), "Console.mkv");
+
+    // 3 bytes per char, and combining marks which can't be split
+    let long = format!("{}.mkv", "葬".repeat(100));
+    let truncated = sanitize_name(&long);
+    assert!(truncated.len() <= MAX_NAME_BYTES);
+    assert!(truncated.ends_with("葬.mkv"));
+    assert_eq!(truncated.len(), 83 * 3 + 4);
+    let combined = "e\u{301}".repeat(100);
+    let truncated = truncate_name(&combined, 10);
+    assert_eq!(truncated, "e\u{301}".repeat(3));
+    // a very long "extension" is not kept
+    let name = format!("a.{}", "b".repeat(300));
+    assert_eq!(sanitize_name(&name).len(), MAX_NAME_BYTES);
+
+    assert_eq!(
+        sanitize_relative_path(Path::new("/abs/../a:b/./c")),
+        PathBuf::from("abs/a_b/c")
+    );
+
+    let layout = LibraryLayout {
+        root: PathBuf::from("library"),
+        title: "..".to_string(),
+        template: PathTemplate::default(),
+    };
+    assert_eq!(
+        layout.file_path(Path::new("../../x"), "../y.mkv"),
+        PathBuf::from("library/_/x/.._y.mkv")
+    );
+    let qbit_config = QbitConfig {
+        url: String::new(),
+        username: String::new(),
+        password: String::new(),
+        category: String::new(),
+        save_path: "library".to_string(),
+    };
+    assert_eq!(
+        crate::qbittorrent::bangumi_save_path(&qbit_config, "../a/b"),
+        PathBuf::from("library/.._a_b")
+    );
+    assert!(PathTemplate::parse("../{original_name}").is_err());
+    assert!(PathTemplate::parse("{title}/../{original_name}").is_err());
+    assert!(PathTemplate::parse("a}{original_name}").is_err());
+    let template = PathTemplate::parse("/{title}/{original_name}").unwrap();
+    assert_eq!(
+        template.render("t", Path::new(""), "a.mkv"),
+        Some(PathBuf::from("t/a.mkv"))
+    );
+
+    let mut unique_paths = UniquePaths::default();
+    let claim =
+        |unique_paths: &mut UniquePaths, path: &str| unique_paths.claim(PathBuf::from(path));
+    assert_eq!(
+        claim(&mut unique_paths, "t/a.mkv"),
+        PathBuf::from("t/a.mkv")
+    );
+    assert_eq!(
+        claim(&mut unique_paths, "t/A.mkv"),
+        PathBuf::from("t/A (2).mkv")
+    );
+    assert_eq!(
+        claim(&mut unique_paths, "t/a.mkv"),
+        PathBuf::from("t/a (3).mkv")
+    );
+    assert_eq!(
+        claim(&mut unique_paths, "t/a (2).mkv"),
+        PathBuf::from("t/a (2) (2).mkv")
+    );
+    assert_eq!(
+        claim(&mut unique_paths, "u/a.mkv"),
+        PathBuf::from("u/a.mkv")
+    );
+    let long = format!("t/{}.mkv", "葬".repeat(83));
+    claim(&mut unique_paths, &long);
+    let renamed = claim(&mut unique_paths, &long);
+    let name = renamed.file_name().unwrap().to_str().unwrap();
+    assert!(name.len() <= MAX_NAME_BYTES && name.ends_with(" (2).mkv"));
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_path_template() {
+    use crate::cloud::path_template::{LibraryLayout, NameInfo, PathTemplate};
+    use std::path::{Path, PathBuf};
+
+    let info =
+        NameInfo::parse("[LoliHouse] Sousou no Frieren - 05 [WebRip 1080p HEVC-10bit AAC].mkv");
+    assert_eq!(info.subgroup.as_deref(), Some("LoliHouse"));
+    assert_eq!(info.episode.as_deref(), Some("05"));
+    assert_eq!(info.season, None);
+    assert_eq!(info.resolution.as_deref(), Some("1080p"));
+    let info =
+        NameInfo::parse("[Nekomoe kissaten][Kusuriya no Hitorigoto S2][13][1920x1080][JPSC].mp4");
+    assert_eq!(info.subgroup.as_deref(), Some("Nekomoe kissaten"));
+    assert_eq!(info.season.as_deref(), Some("02"));
+    assert_eq!(info.episode.as_deref(), Some("13"));
+    assert_eq!(info.resolution.as_deref(), Some("1080p"));
+    let info = NameInfo::parse("Dungeon Meshi S01E7 4K.mkv");
+    assert_eq!(info.season.as_deref(), Some("01"));
+    assert_eq!(info.episode.as_deref(), Some("07"));
+    assert_eq!(info.resolution.as_deref(), Some("2160p"));
+    let info =
+        NameInfo::parse("【喵萌奶茶屋】★04月新番★[葬送的芙莉莲 第2季][第12.5话][简日双语].mp4");
+    assert_eq!(info.subgroup.as_deref(), Some("喵萌奶茶屋"));
+    assert_eq!(info.season.as_deref(), Some("02"));
+    assert_eq!(info.episode.as_deref(), Some("12.5"));
+    assert_eq!(NameInfo::parse("[ANi] NCOP.mp4").episode, None);
+
+    assert!(PathTemplate::parse("{title}/{episode").is_err());
+    assert!(PathTemplate::parse("{title}/{name}").is_err());
+    assert!(PathTemplate::parse("{title}/{ext}").is_err());
+    let template = PathTemplate::parse(
+        "{title}/Season {season}/{title} - S{season}E{episode} [{subgroup}].{ext}",
+    )
+    .unwrap();
+    let name = "[LoliHouse] Frieren - 05 [1080p].mkv";
+    assert_eq!(
+        template.render("Frieren", Path::new("sub"), name),
+        Some(PathBuf::from(
+            "Frieren/Season 01/Frieren - S01E05 [LoliHouse].mkv"
+        ))
+    );
+    // no episode in the name, fall back to the layout of the cloud folder
+    assert_eq!(template.render("Frieren", Path::new(""), "NCOP.mkv"), None);
+    let layout = LibraryLayout {
+        root: PathBuf::from("/media/anime"),
+        title: "A/B".to_string(),
+        template,
+    };
+    assert_eq!(
+        layout.file_path(Path::new("extra/menu"), "NCOP.mkv"),
+        PathBuf::from("/media/anime/A_B/extra/menu/NCOP.mkv")
+    );
+    assert_eq!(
+        PathTemplate::default().render("T", Path::new(""), "01.mkv"),
+        Some(PathBuf::from("T/01.mkv"))
+    );
+
+    let mut config = Config::default();
+    config
+        .rss_links
+        .insert("1".to_string(), ("Frieren".to_string(), String::new()));
+    config
+        .path_templates
+        .insert("default".to_string(), "{title}/{episode}.{ext}".to_string());
+    config
+        .path_templates
+        .insert("1".to_string(), "{title}/E{episode}.{ext}".to_string());
+    assert_eq!(
+        config.path_template(Some("Frieren")),
+        Some("{title}/E{episode}.{ext}")
+    );
+    assert_eq!(
+        config.path_template(Some("Other")),
+        Some("{title}/{episode}.{ext}")
+    );
+    assert_eq!(config.path_template(None), None);
+    assert_eq!(config.library_root(), PathBuf::from("downloads/115"));
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_bandwidth() {
+    use crate::cloud::bandwidth::ByteBucket;
+    use crate::socket_utils::BandwidthLimit;
+    use chrono::NaiveTime;
+    use std::time::{Duration, Instant};
+    assert_eq!(BandwidthLimit::parse("auto"), Some(BandwidthLimit::Auto));
+    assert_eq!(BandwidthLimit::parse("0"), Some(BandwidthLimit::Unlimited));
+    assert_eq!(
+        BandwidthLimit::parse("5M"),
+        Some(BandwidthLimit::PerSecond(5 * 1024 * 1024))
+    );
+    assert_eq!(
+        BandwidthLimit::parse("1.5 MiB/s"),
+        Some(BandwidthLimit::PerSecond(1536 * 1024))
+    );
+    assert_eq!(
+        BandwidthLimit::parse("500k"),
+        Some(BandwidthLimit::PerSecond(500 * 1024))
+    );
+    assert_eq!(
+        BandwidthLimit::parse("2048"),
+        Some(BandwidthLimit::PerSecond(2048))
+    );
+    assert_eq!(BandwidthLimit::parse("fast"), None);
+    assert_eq!(BandwidthLimit::parse("-1M"), None);
+    assert_eq!(BandwidthLimit::PerSecond(1).or_else(|| Some(2)), Some(1));
+    assert_eq!(BandwidthLimit::Unlimited.or_else(|| Some(2)), None);
+    assert_eq!(BandwidthLimit::Auto.or_else(|| Some(2)), Some(2));
+
+    let config: BandwidthConfig = serde_json::from_str(
+        r#"{
+            "global": 100,
+            "profiles": [
+                {"start": "09:00:00", "end": "19:00:00", "limit": 5242880},
+                {"start": "23:00:00", "end": "01:00:00", "limit": 10}
+            ]
+        }"#,
+    )
+    .unwrap();
+    let time = |hour| NaiveTime::from_hms_opt(hour, 0, 0).unwrap();
+    assert_eq!(config.global_limit_at(time(9)), Some(5242880));
+    assert_eq!(config.global_limit_at(time(19)), Some(100));
+    assert_eq!(config.global_limit_at(time(23)), Some(10));
+    assert_eq!(config.global_limit_at(time(0)), Some(10));
+    assert_eq!(config.per_task, None);
+
+    let mut bucket = ByteBucket::default();
+    let now = Instant::now();
+    assert_eq!(bucket.reserve_at(None, 1 << 30, now), Duration::ZERO);
+    // a bucket starts empty, and it is refilled for at most a second
+    assert_eq!(
+        bucket.reserve_at(Some(100), 50, now),
+        Duration::from_millis(500)
+    );
+    let now = now + Duration::from_secs(10);
+    assert_eq!(bucket.reserve_at(Some(100), 100, now), Duration::ZERO);
+    assert_eq!(
+        bucket.reserve_at(Some(100), 200, now),
+        Duration::from_secs(2)
+    );
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_config_accounts() {
+    let config: Config = serde_json::from_str(
+        r#"{
+            "bangumi": {}, "cookies": "UID=1_A1", "filter": {}, "hash_ani": {},
+            "hash_ani_slow": {}, "magnets": {}, "rss_links": {},
+            "cloud_root": "100",
+            "accounts": {
+                "b": {"cookies": "UID=3_A1"},
+                "a": {"cookies": "UID=2_A1", "cloud_root": "200"}
+            },
+            "account_policy": "round_robin",
+            "task_accounts": {"hash": "a"}
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(config.account_policy, AccountPolicy::RoundRobin);
+    assert_eq!(
+        config.account_names(),
+        [None, Some("a".to_string()), Some("b".to_string())]
+    );
+    assert_eq!(config.account_cookies(None), Some("UID=1_A1"));
+    assert_eq!(config.account_cookies(Some("b")), Some("UID=3_A1"));
+    assert_eq!(config.account_cookies(Some("c")), None);
+    assert_eq!(config.account_root(None), Some("100"));
+    assert_eq!(config.account_root(Some("a")), Some("200"));
+    assert_eq!(config.account_root(Some("b")), None);
+    assert_eq!(config.task_account("hash"), Some("a"));
+    assert_eq!(config.task_account("other"), None);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_login_app() {
+    use crate::login_with_qrcode::Cookies;
+    let config: Config = serde_json::from_str(
+        r#"{
+            "bangumi": {}, "cookies": "", "filter": {}, "hash_ani": {},
+            "hash_ani_slow": {}, "magnets": {}, "rss_links": {},
+            "login_app": "alipaymini", "secondary_app": "wechatmini"
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(config.login_app.as_str(), "alipaymini");
+    assert_eq!(config.secondary_app, Some(LoginApp::Wechatmini));
+    assert_eq!(Config::default().login_app, LoginApp::Tv);
+    let cookies = Cookies {
+        UID: "1_A1".to_string(),
+        CID: "2".to_string(),
+        SEID: "3".to_string(),
+        KID: "4".to_string(),
+    };
+    assert_eq!(cookies.to_string(), "UID=1_A1; CID=2; SEID=3; KID=4");
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_file_rules() {
+    use crate::cloud::file_rules::{FileSelector, SkipReason};
+    use crate::cloud_manager::FileInfo;
+    let file = |name: &str, size: Option<u64>| FileInfo {
+        folder_id: "1".to_string(),
+        file_id: size.map(|_| "2".to_string()),
+        name: name.to_string(),
+        sha1: None,
+        size,
+        pick_code: String::new(),
+    };
+    let rules = FileRules {
+        extensions: vec![".MKV".to_string(), "mp4".to_string()],
+        min_size: 1024,
+        include: vec![r"\[\d{2}\]".to_string()],
+        exclude: vec!["(?i)NC(OP|ED)".to_string(), "^SPs$".to_string()],
+    };
+    let selector = FileSelector::new(&rules).unwrap();
+    assert_eq!(
+        selector.check(&file("[Sub] Ani [01].mkv", Some(2048))),
+        Ok(())
+    );
+    assert_eq!(
+        selector.check(&file("[Sub] Ani [01].Mp4", Some(2048))),
+        Ok(())
+    );
+    assert_eq!(
+        selector.check(&file("[Sub] Ani [01].ass", Some(2048))),
+        Err(SkipReason::Extension)
+    );
+    assert_eq!(
+        selector.check(&file("[Sub] Ani [01].mkv", Some(10))),
+        Err(SkipReason::TooSmall(10))
+    );
+    assert_eq!(
+        selector.check(&file("[Sub] Ani sample.mkv", Some(2048))),
+        Err(SkipReason::NotIncluded)
+    );
+    assert!(matches!(
+        selector.check(&file("[Sub] Ani NCOP [01].mkv", Some(2048))),
+        Err(SkipReason::Excluded(_))
+    ));
+    // folders are only checked by `exclude`
+    assert_eq!(selector.check(&file("Fonts", None)), Ok(()));
+    assert!(selector.check(&file("SPs", None)).is_err());
+    assert!(
+        FileSelector::default()
+            .check(&file("a.txt", Some(0)))
+            .is_ok()
+    );
+    assert!(
+        FileSelector::new(&FileRules {
+            include: vec!["[".to_string()],
+            ..Default::default()
+        })
+        .is_err()
+    );
+
+    let mut config = Config::default();
+    config
+        .rss_links
+        .insert("3519".to_string(), ("Ani".to_string(), String::new()));
+    assert!(config.file_rules(Some("Ani")).is_none());
+    config
+        .file_rules
+        .insert("default".to_string(), FileRules::default());
+    config.file_rules.insert("3519".to_string(), rules.clone());
+    assert_eq!(config.file_rules(Some("Ani")), Some(&rules));
+    assert_eq!(
+        config.file_rules(Some("Other")),
+        Some(&FileRules::default())
+    );
+    assert_eq!(config.file_rules(None), Some(&FileRules::default()));
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_cloud_browser() {
+    use crate::socket_utils::{ClientMsg, CloudDir, CloudEntry};
+    use crate::tui::cloud_browser::CloudBrowserState;
+    let entry = |folder_id: &str, file_id: Option<&str>, name: &str| CloudEntry {
+        folder_id: folder_id.to_string(),
+        file_id: file_id.map(|id| id.to_string()),
+        name: name.to_string(),
+        sha1: None,
+        size: Some(1),
+        pick_code: String::new(),
+    };
+    let mut browser = CloudBrowserState::new();
+    assert!(matches!(
+        browser.list(0, false),
+        ClientMsg::ListCloudDir(ptr) if *ptr == ("0".to_string(), 0, false)
+    ));
+    assert!(browser.update(CloudDir {
+        cid: "0".to_string(),
+        page: 0,
+        count: 120,
+        entries: vec![entry("10", None, "Ani"), entry("0", Some("11"), "a.mkv")],
+    }));
+    assert_eq!(browser.dir.as_ref().unwrap().page_count(), 3);
+    assert!(browser.turn_page(false).is_none());
+    browser.loading = false;
+    // select the folder, the highlight moves to the file
+    browser.toggle_selected();
+    assert_eq!(browser.current_entry().unwrap().name, "a.mkv");
+    browser.select_previous();
+    assert!(browser.enter().is_some());
+    assert_eq!(browser.path(), "root / Ani");
+    // a page of the parent folder arrives late
+    assert!(!browser.update(CloudDir {
+        cid: "0".to_string(),
+        page: 1,
+        count: 120,
+        entries: Vec::new(),
+    }));
+    assert!(browser.update(CloudDir {
+        cid: "10".to_string(),
+        page: 0,
+        count: 2,
+        entries: vec![
+            entry("10", Some("12"), "01.mkv"),
+            entry("10", Some("13"), "02.mkv")
+        ],
+    }));
+    browser.toggle_selected();
+    browser.toggle_selected();
+    assert_eq!(browser.selected.len(), 3);
+    let msgs = CloudBrowserState::download_msgs(browser.picked());
+    assert_eq!(msgs.len(), 2);
+    assert!(msgs.iter().any(|msg| matches!(
+        msg,
+        ClientMsg::DownloadFolder(cid) if cid.as_ref() == "10"
+    )));
+    assert!(msgs.iter().any(|msg| matches!(
+        msg,
+        ClientMsg::DownloadCloudFiles(ptr) if ptr.0 == "Ani" && ptr.1.len() == 2
+    )));
+    // the files of two folders with the same name are not merged
+    let msgs = CloudBrowserState::download_msgs(vec![
+        ("Ani".to_string(), entry("20", Some("21"), "01.mkv")),
+        ("Ani".to_string(), entry("30", Some("31"), "01.mkv")),
+        ("Ani".to_string(), entry("20", Some("22"), "02.mkv")),
+    ]);
+    assert_eq!(msgs.len(), 2);
+    assert!(msgs.iter().all(|msg| matches!(
+        msg,
+        ClientMsg::DownloadCloudFiles(ptr) if ptr.1.iter().all(|file| file.folder_id == ptr.1[0].folder_id)
+    )));
+    assert!(browser.back().is_some());
+    assert!(browser.back().is_none());
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_streaming_sha1() {
+    use crate::cloud_manager::{hash_reader, sha1_hex};
+    use crate::tui::progress_bar::{BasicBar, Inc, ProgressBar};
+    use sha1::{Digest, Sha1};
+    use std::io::{Cursor, Read};
+
+    let data = vec![7u8; 3 * 1024 * 1024 + 5];
+    let mut progress = 0;
+    let hasher = hash_reader(Cursor::new(&data), |n| progress += n).unwrap();
+    assert_eq!(progress, data.len() as u64);
+    let expected = sha1_hex(Sha1::new_with_prefix(&data));
+    assert_eq!(sha1_hex(hasher), expected);
+    // resume: hash the part on the disk, then the downloaded chunks
+    let offset = 1024 * 1024 + 3;
+    let mut hasher = hash_reader(Cursor::new(&data).take(offset), |_| ()).unwrap();
+    for chunk in data[offset as usize..].chunks(8192) {
+        hasher.update(chunk);
+    }
+    assert_eq!(sha1_hex(hasher), expected);
+    assert_eq!(
+        sha1_hex(Sha1::new_with_prefix(b"abc")),
+        "A9993E364706816ABA3E25717850C26C9CD0D89D"
+    );
+
+    let mut bar = ProgressBar::new("a".to_string(), 100);
+    bar.set_verifying(true);
+    bar.inc(100);
+    assert!(bar.is_verifying() && bar.is_finished());
+    // the hash check failed, the file is downloaded from the start
+    bar.set_verifying(false);
+    assert_eq!(bar.current_size(), 0);
+    bar.inc(30);
+    assert_eq!(bar.progress_state(Id::generate()).current_size, 30);
+}
+
+#[cfg(not(miri))]
+#[tokio::test]
+async fn test_chunk_writer() {
+    use crate::cloud::chunk_writer::ChunkWriter;
+    use crate::cloud_manager::sha1_hex;
+    use sha1::{Digest, Sha1};
+    let path = std::env::temp_dir().join(format!("bangumi_chunk_{}", std::process::id()));
+    std::fs::write(&path, b"hello ").unwrap();
+    let file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    let writer = ChunkWriter::spawn(file, Sha1::new_with_prefix(b"hello "));
+    for chunk in ["bangumi", " ", "download"] {
+        assert!(writer.write(chunk.into()).await);
+    }
+    let hasher = writer.finish().await.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"hello bangumi download");
+    assert_eq!(
+        sha1_hex(hasher),
+        sha1_hex(Sha1::new_with_prefix(b"hello bangumi download"))
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(not(miri))]
+#[tokio::test]
+async fn test_download_control() {
+    use crate::cloud::control::{ControlState, DOWNLOAD_CONTROL};
+    use crate::errors::DownloadError;
+    use std::time::Duration;
+
+    let id = Id::generate();
+    let mut handle = DOWNLOAD_CONTROL.register(id);
+    assert_eq!(handle.state(), ControlState::Running);
+    assert!(!DOWNLOAD_CONTROL.resume(id));
+    assert!(DOWNLOAD_CONTROL.pause(id));
+    assert!(!DOWNLOAD_CONTROL.pause(id));
+    assert!(matches!(handle.interrupted().await, DownloadError::Paused));
+    let resume = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(DOWNLOAD_CONTROL.resume(id));
+    });
+    assert_eq!(handle.wait_while_paused().await, ControlState::Running);
+    resume.await.unwrap();
+    // a running download is interrupted when it is cancelled, and it can't be resumed
+    let cancel = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(DOWNLOAD_CONTROL.cancel(id));
+    });
+    assert!(matches!(
+        handle.interrupted().await,
+        DownloadError::Cancelled
+    ));
+    cancel.await.unwrap();
+    assert!(!DOWNLOAD_CONTROL.resume(id));
+    assert_eq!(handle.state(), ControlState::Cancelled);
+    drop(handle);
+    assert!(!DOWNLOAD_CONTROL.cancel(id));
+
+    let running = DOWNLOAD_CONTROL.register(Id::generate());
+    DOWNLOAD_CONTROL.pause_all();
+    let later = DOWNLOAD_CONTROL.register(Id::generate());
+    assert_eq!(running.state(), ControlState::Paused);
+    assert_eq!(later.state(), ControlState::Paused);
+    DOWNLOAD_CONTROL.resume_all();
+    assert!(!DOWNLOAD_CONTROL.is_all_paused());
+    assert_eq!(running.state(), ControlState::Running);
+    assert_eq!(later.state(), ControlState::Running);
+}
+
+#[cfg(not(miri))]
+#[tokio::test]
+async fn test_disk_space() {
+    use crate::cloud::disk_space::{check_disk_space, free_space, required_space};
+    use crate::errors::CloudError;
+    let dir = std::env::temp_dir().join(format!("bangumi_disk_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // the library folder is created by the first download
+    let free = free_space(&dir.join("library/title")).unwrap();
+    assert!(free > 0);
+    std::fs::write(dir.join("01.mkv"), [0u8; 100]).unwrap();
+    std::fs::write(dir.join("02.mkv.part"), [0u8; 30]).unwrap();
+    let files = vec![
+        (dir.join("01.mkv"), 100),
+        (dir.join("02.mkv"), 100),
+        (dir.join("03.mkv"), 100),
+    ];
+    assert_eq!(required_space(&files), 170);
+    check_disk_space(&dir, files).await.unwrap();
+    let files = vec![(dir.join("04.mkv"), u64::MAX / 2)];
+    assert!(matches!(
+        check_disk_space(&dir, files).await,
+        Err(CloudError::DiskSpace(_))
+    ));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_part_file() {
+    use crate::cloud::part_file::{PartFile, PartInfo};
+    let dir = std::env::temp_dir().join(format!("bangumi_part_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("01.mkv");
+    let part_file = PartFile::new(&path);
+    assert_eq!(part_file.part_path(), dir.join("01.mkv.part"));
+    let info = PartInfo {
+        url: "https://cdn/1".to_string(),
+        size: 10,
+        sha1: "ABC".to_string(),
+    };
+    assert_eq!(part_file.resume_offset(&info).unwrap(), 0);
+    part_file.save_info(&info).unwrap();
+    std::fs::write(part_file.part_path(), b"12345").unwrap();
+    // the url is re-resolved, it doesn't matter
+    let resolved_again = PartInfo {
+        url: "https://cdn/2".to_string(),
+        sha1: "abc".to_string(),
+        ..info.clone()
+    };
+    assert_eq!(part_file.resume_offset(&resolved_again).unwrap(), 5);
+    // the part file of another content is removed
+    let other = PartInfo {
+        sha1: "DEF".to_string(),
+        ..info.clone()
+    };
+    assert_eq!(part_file.resume_offset(&other).unwrap(), 0);
+    assert!(!part_file.part_path().exists());
+    std::fs::write(part_file.part_path(), b"1234567890").unwrap();
+    part_file.finish().unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"1234567890");
+    assert!(!dir.join("01.mkv.part.json").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_qrcode_half_blocks() {
+    use crate::login_with_qrcode::qrcode_to_half_blocks;
+    let output = qrcode_to_half_blocks("https://115.com/scan/dg-1234567890").unwrap();
+    let lines = output.lines().collect::<Vec<_>>();
+    let width = lines[0].chars().count();
+    // two rows of modules per line, with a quiet zone of 1 on each side
+    assert_eq!(lines.len(), width.div_ceil(2));
+    assert!(lines.iter().all(|line| line.chars().count() == width));
+    assert!(lines[0].chars().all(|c| c == '█' || c == '▀'));
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_normalize_info_hash() {
+    use crate::qbittorrent::{magnet_info_hash, normalize_info_hash};
+    assert_eq!(
+        normalize_info_hash("40882FA906A4FE9DA7B57FA53A7BD880AD3244CE"),
+        Some("40882fa906a4fe9da7b57fa53a7bd880ad3244ce".to_string())
+    );
+    assert_eq!(
+        normalize_info_hash("ABCDEFGHIJKLMNOPQRSTUVWXYZ234567"),
+        Some("00443214c74254b635cf84653a56d7c675be77df".to_string())
+    );
+    assert_eq!(
+        normalize_info_hash("ABCDEFGHIJKLMNOPQRSTUVWXYZ23456!"),
+        None
+    );
+    assert_eq!(
+        magnet_info_hash(
+            "magnet:?dn=test&xt=urn:btih:ABCDEFGHIJKLMNOPQRSTUVWXYZ234567&tr=udp://..."
+        ),
+        Some("00443214c74254b635cf84653a56d7c675be77df".to_string())
+    );
+}
+
 #[cfg(not(miri))]
 #[tokio::test]
 async fn test_status_iter() {
@@ -509,3 +1370,543 @@ fn test_for_unsafe_popup_handle() {
     assert!(matches!(app.current_popup, None));
     assert_eq!(app.rss_data, rss_result);
 }
+
+#[cfg(not(miri))]
+#[test]
+fn test_download_queue() {
+    use crate::cloud::queue::{DownloadQueue, is_saved};
+    use crate::cloud::store::JsonStore;
+    use sha1::{Digest, Sha1};
+    let dir = std::env::temp_dir().join(format!("bangumi_queue_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let queued = |cid: &str, file_id: &str, manual: bool| QueuedFile {
+        cid: cid.to_string(),
+        file_id: file_id.to_string(),
+        pick_code: format!("pick_{file_id}"),
+        name: std::path::PathBuf::from(format!("sub/{file_id}.mkv")),
+        target: dir.join(format!("{file_id}.mkv")),
+        size: 5,
+        sha1: format!("{:X}", Sha1::digest(b"12345")),
+        title: "title".to_string(),
+        account: None,
+        manual,
+        state: QueueState::Queued,
+    };
+    let mut queue = DownloadQueue::default();
+    for file in [
+        queued("1", "b", false),
+        queued("1", "a", false),
+        queued("2", "c", true),
+        queued("3", "d", true),
+    ] {
+        queue.files.insert(file.file_id.clone(), file);
+    }
+    // the queue is read from its own file
+    let json = serde_json::to_string(&queue).unwrap();
+    let path = dir.join("download_queue.json");
+    std::fs::write(&path, &json).unwrap();
+    let store = JsonStore::<DownloadQueue>::new(path.to_str().unwrap().to_string().leak());
+    store.initialize().unwrap();
+    let mut queue = DownloadQueue::clone(&store.load());
+    assert_eq!(queue.files.len(), 4);
+    std::fs::write(&path, &json[1..]).unwrap();
+    assert!(store.initialize().is_err());
+    let files = queue.queued_files("1");
+    assert_eq!(
+        files
+            .iter()
+            .map(|file| file.file_id.as_str())
+            .collect::<Vec<_>>(),
+        ["a", "b"]
+    );
+    assert_eq!(files[0].file_name(), "a.mkv");
+    let manual = queue
+        .manual_queue()
+        .into_iter()
+        .map(|files| files[0].cid.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(manual, ["2", "3"]);
+    // a verified entry is kept only for the same target and SHA1
+    let mut verified = queued("1", "a", false);
+    verified.state = QueueState::Verified;
+    queue.files.insert("a".to_string(), verified);
+    let file = queued("1", "a", false);
+    assert!(queue.is_verified(&file));
+    let mut moved = file.clone();
+    moved.target = dir.join("moved.mkv");
+    assert!(!queue.is_verified(&moved));
+    let mut replaced = file.clone();
+    replaced.sha1 = "0".repeat(40);
+    assert!(!queue.is_verified(&replaced));
+    assert!(!queue.is_verified(&queued("1", "b", false)));
+    // a file is saved when its size and SHA1 match
+    let file = queued("1", "a", false);
+    assert!(!is_saved(&file.target, file.size, &file.sha1).unwrap());
+    std::fs::write(&file.target, b"1234").unwrap();
+    assert!(!is_saved(&file.target, file.size, &file.sha1).unwrap());
+    std::fs::write(&file.target, b"12346").unwrap();
+    assert!(!is_saved(&file.target, file.size, &file.sha1).unwrap());
+    std::fs::write(&file.target, b"12345").unwrap();
+    assert!(is_saved(&file.target, file.size, &file.sha1.to_lowercase()).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_download_history() {
+    use crate::cloud::history::{DownloadHistory, MAX_HISTORY};
+    use crate::socket_utils::HistoryCoder;
+    use crate::time_stamp::TimeStamp;
+    use crate::tui::history::{HistorySort, HistoryState, format_duration};
+    let entry = |file_id: &str, title: &str, size: u64, duration: u64| HistoryEntry {
+        file: QueuedFile {
+            cid: "1".to_string(),
+            file_id: file_id.to_string(),
+            pick_code: format!("pick_{file_id}"),
+            name: std::path::PathBuf::from(format!("sub/{file_id}.mkv")),
+            target: std::path::PathBuf::from(format!("downloads/{file_id}.mkv")),
+            size,
+            sha1: String::new(),
+            title: title.to_string(),
+            account: None,
+            manual: false,
+            state: QueueState::Verified,
+        },
+        duration,
+        finished_at: TimeStamp::now(),
+        error: None,
+    };
+    let mut history = DownloadHistory::default();
+    for i in 0..MAX_HISTORY + 2 {
+        history.push(entry(&i.to_string(), "title", 1, 1));
+    }
+    assert_eq!(history.entries.len(), MAX_HISTORY);
+    assert_eq!(history.entries[0].file.file_id, "2");
+    assert!(history.history_of("1").is_none());
+    let mut failed = entry("2", "title", 1, 1);
+    failed.error = Some("hash error".to_string());
+    history.push(failed);
+    assert!(history.history_of("2").unwrap().error.is_some());
+
+    let entries = [
+        entry("b", "Frieren", 300, 3000),
+        entry("a", "Dandadan", 100, 500),
+        entry("c", "Frieren", 200, 0),
+    ];
+    let coders = entries.iter().map(HistoryCoder::from).collect::<Vec<_>>();
+    assert_eq!(coders[0].name, "b.mkv");
+    assert_eq!(coders[0].path, "downloads/b.mkv");
+    assert_eq!(coders[0].speed(), 100);
+    assert_eq!(coders[1].speed(), 200);
+    let mut state = HistoryState::new();
+    state.update(coders);
+    let names = |state: &HistoryState| {
+        state
+            .visible_entries()
+            .map(|entry| entry.file_id.clone())
+            .collect::<Vec<_>>()
+    };
+    state.set_sort(HistorySort::Name);
+    assert_eq!(names(&state), ["a", "b", "c"]);
+    state.list_state.select(Some(0));
+    state.select_next();
+    assert_eq!(state.current().unwrap().file_id, "b");
+    state.set_sort(HistorySort::Size);
+    assert_eq!(names(&state), ["b", "c", "a"]);
+    // the highlighted file is kept
+    assert_eq!(state.current().unwrap().file_id, "b");
+    state.set_sort(HistorySort::Speed);
+    assert_eq!(names(&state), ["c", "a", "b"]);
+    state.set_search("frieren".to_string());
+    assert_eq!(names(&state), ["c", "b"]);
+    state.set_search("A.MKV".to_string());
+    assert_eq!(names(&state), ["a"]);
+    assert_eq!(state.current().unwrap().file_id, "a");
+    state.set_search("nothing".to_string());
+    assert!(state.current().is_none());
+
+    assert_eq!(format_duration(850), "850ms");
+    assert_eq!(format_duration(12_000), "12s");
+    assert_eq!(format_duration(185_000), "3m 05s");
+    assert_eq!(format_duration(3_723_000), "1h 02m 03s");
+}
+
+#[cfg(not(miri))]
+#[test]
+fn test_segmented_hosts() {
+    use crate::cloud::segmented::segment_ranges;
+    assert_eq!(segment_ranges(0..10, 4), [0..4, 4..8, 8..10]);
+    assert_eq!(segment_ranges(3..13, 5), [3..8, 8..13]);
+    assert!(segment_ranges(8..8, 4).is_empty());
+    let mut config = Config::default();
+    assert_eq!(config.segment_connections("cdnfhnfile.115cdn.net"), None);
+    config.segmented_hosts.insert("115cdn.net".to_string(), 4);
+    config
+        .segmented_hosts
+        .insert("slow.115cdn.net".to_string(), 2);
+    config.segmented_hosts.insert("single.net".to_string(), 1);
+    assert_eq!(config.segment_connections("115cdn.net"), Some(4));
+    assert_eq!(config.segment_connections("cdnfhnfile.115cdn.net"), Some(4));
+    // the most specific host is used
+    assert_eq!(config.segment_connections("a.slow.115cdn.net"), Some(2));
+    // it is not a sub domain
+    assert_eq!(config.segment_connections("not115cdn.net"), None);
+    // a single connection is not segmented
+    assert_eq!(config.segment_connections("single.net"), None);
+    // a host which rejected a segment is not probed again
+    config.segment_capability.insert(
+        "cdnfhnfile.115cdn.net".to_string(),
+        HostCapability::Unsupported,
+    );
+    assert_eq!(config.segment_connections("cdnfhnfile.115cdn.net"), None);
+    config
+        .segment_capability
+        .insert("b.115cdn.net".to_string(), HostCapability::Supported);
+    assert_eq!(config.segment_connections("b.115cdn.net"), Some(4));
+    let json = serde_json::to_string(&config).unwrap();
+    assert!(json.contains(r#""cdnfhnfile.115cdn.net":"unsupported""#));
+}
+
+/// serve http on a local port, `respond` gets the request and the requests before it, and
+/// returns the status and the body
+async fn mock_server<F>(respond: F) -> (String, Arc<std::sync::Mutex<Vec<String>>>)
+where
+    F: Fn(&str, &[String]) -> (u16, String) + Send + Sync + 'static,
+{
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let log = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut buf = Vec::new();
+            let mut chunk = [0u8; 4096];
+            let request = loop {
+                let n = stream.read(&mut chunk).await.unwrap();
+                buf.extend_from_slice(&chunk[..n]);
+                let text = String::from_utf8_lossy(&buf).into_owned();
+                let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                    continue;
+                };
+                let len = head
+                    .lines()
+                    .find_map(|line| {
+                        line.to_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|len| len.trim().parse::<usize>().unwrap())
+                    })
+                    .unwrap_or_default();
+                if body.len() >= len || n == 0 {
+                    break format!("{head}\r\n\r\n{body}");
+                }
+            };
+            let (status, body) = {
+                let mut requests = log.lock().unwrap();
+                let body = respond(&request, &requests);
+                requests.push(request);
+                body
+            };
+            let response = format!(
+                "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+    (base, requests)
+}
+
+/// serve the 115 apis on a local port, see `mock_server`
+async fn mock_115_server<F>(respond: F) -> (ApiUrls, Arc<std::sync::Mutex<Vec<String>>>)
+where
+    F: Fn(&str, &[String]) -> String + Send + Sync + 'static,
+{
+    let (base, requests) =
+        mock_server(move |request, requests| (200, respond(request, requests))).await;
+    let urls = ApiUrls {
+        web: base.clone(),
+        webapi: base.clone(),
+        proapi: base,
+    };
+    (urls, requests)
+}
+
+#[cfg(not(miri))]
+#[tokio::test]
+async fn test_cloud_retention() {
+    use crate::cloud::client::Pan115Client;
+    use crate::cloud_manager::clean_up_files;
+    let (urls, requests) = mock_115_server(|request, requests| {
+        let cleaned = requests.iter().any(|r| r.starts_with("POST /rb/clean"));
+        if request.starts_with("GET /rb?") && !cleaned {
+            r#"{"state":true,"count":2,"data":[{"id":"11","pick_code":"pick_x"},{"id":"12","pick_code":"pick_b"}]}"#
+        } else if request.starts_with("GET /rb?") {
+            r#"{"state":true,"count":1,"data":[{"id":"11","pick_code":"pick_x"}]}"#
+        } else {
+            r#"{"state":true}"#
+        }
+        .to_string()
+    })
+    .await;
+    let client = Pan115Client::with_cookies("UID=1_a_b")
+        .unwrap()
+        .with_urls(urls);
+    let queued = |file_id: &str, manual: bool| QueuedFile {
+        cid: "1".to_string(),
+        file_id: file_id.to_string(),
+        pick_code: format!("pick_{file_id}"),
+        name: std::path::PathBuf::from(format!("sub/{file_id}.mkv")),
+        target: std::path::PathBuf::from(format!("{file_id}.mkv")),
+        size: 5,
+        sha1: String::new(),
+        title: "title".to_string(),
+        account: None,
+        manual,
+        state: QueueState::Verified,
+    };
+    // the files downloaded by the clients are kept
+    let files = [queued("a", true), queued("b", false)];
+    clean_up_files(&client, CloudRetention::Delete, &files).await;
+    let log = requests.lock().unwrap().clone();
+    assert!(log.iter().all(|request| !request.contains("fid%5B0%5D=a")));
+    assert!(
+        log.iter()
+            .any(|request| request.starts_with("POST /rb/delete")
+                && request.contains("fid%5B0%5D=b"))
+    );
+    // `rb/clean` takes the record id instead of the file id
+    assert!(
+        log.iter()
+            .any(|request| request.starts_with("POST /rb/clean")
+                && request.ends_with("rid%5B0%5D=12"))
+    );
+    // a file which is not in the recycle bin is not purged
+    assert!(client.clean_recycled_file("pick_c").await.is_err());
+}
+
+#[cfg(not(miri))]
+#[tokio::test]
+async fn test_download_status() {
+    use crate::cloud_manager::{check_download_status, is_retryable};
+    use crate::errors::DownloadError;
+    let (base, _) = mock_server(|request, _| {
+        let status = request
+            .split(' ')
+            .nth(1)
+            .and_then(|path| path.trim_start_matches('/').parse().ok())
+            .unwrap_or(200);
+        (status, String::new())
+    })
+    .await;
+    let status = async |code: u16| {
+        let response = CLIENT_DOWNLOAD
+            .get(format!("{base}/{code}"))
+            .send()
+            .await
+            .unwrap();
+        check_download_status(response)
+    };
+    assert!(status(200).await.is_ok());
+    assert!(status(206).await.is_ok());
+    // the signed url is expired
+    assert!(matches!(
+        status(403).await,
+        Err(DownloadError::UrlExpired(403))
+    ));
+    assert!(matches!(
+        status(410).await,
+        Err(DownloadError::UrlExpired(410))
+    ));
+    assert!(matches!(status(416).await, Err(DownloadError::Resume(_))));
+    assert!(matches!(status(500).await, Err(DownloadError::Request(_))));
+    for error in [
+        DownloadError::from("timeout".to_string()),
+        DownloadError::UrlExpired(403),
+        DownloadError::Resume(String::new()),
+        DownloadError::ContentLength(String::new()),
+        DownloadError::Hash {
+            expected: "a".to_string(),
+            found: "b".to_string(),
+        },
+    ] {
+        assert!(is_retryable(&error));
+    }
+    for error in [
+        DownloadError::IO(std::io::Error::other("disk")),
+        DownloadError::Path(String::new()),
+        DownloadError::Paused,
+        DownloadError::Cancelled,
+    ] {
+        assert!(!is_retryable(&error));
+    }
+}
+
+#[cfg(not(miri))]
+#[tokio::test]
+async fn test_segmented_download() {
+    use crate::cloud::chunk_writer::ChunkWriter;
+    use crate::cloud::control::DOWNLOAD_CONTROL;
+    use crate::cloud::disk_space::SpaceWatcher;
+    use crate::cloud::segmented::{Segmented, download_segmented};
+    use crate::errors::DownloadError;
+    use sha1::{Digest, Sha1};
+    const CONTENT: &str = "0123456789";
+    let (base, _) = mock_server(|request, _| {
+        let range = request
+            .lines()
+            .find_map(|line| {
+                line.to_lowercase()
+                    .strip_prefix("range: bytes=")
+                    .map(str::to_string)
+            })
+            .and_then(|range| {
+                let (start, end) = range.split_once('-')?;
+                Some(start.parse::<usize>().ok()?..end.parse::<usize>().ok()? + 1)
+            })
+            .unwrap_or(0..CONTENT.len());
+        match request.split(' ').nth(1).unwrap_or_default() {
+            "/206" => (206, CONTENT[range].to_string()),
+            // the range is ignored
+            "/200" => (200, CONTENT.to_string()),
+            _ => (403, String::new()),
+        }
+    })
+    .await;
+    let path = std::env::temp_dir().join(format!("bangumi_segmented_{}", std::process::id()));
+    let download = async |status: &str| {
+        std::fs::write(&path, &CONTENT[..3]).unwrap();
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        let writer = ChunkWriter::spawn(file, Sha1::new_with_prefix(&CONTENT[..3]));
+        let id = Id::generate();
+        let mut watcher = SpaceWatcher::new(&path, id);
+        let mut control = DOWNLOAD_CONTROL.register(id);
+        let url = format!("{base}/{status}");
+        let result = download_segmented(
+            &url,
+            &writer,
+            &mut watcher,
+            &mut control,
+            id,
+            3..CONTENT.len() as u64,
+            4,
+        )
+        .await;
+        writer.finish().await.unwrap();
+        result
+    };
+    assert!(matches!(download("206").await, Ok(Segmented::Finished)));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), CONTENT);
+    // the part file is kept, and the rest is downloaded with a single connection
+    assert!(matches!(
+        download("200").await,
+        Ok(Segmented::Rejected {
+            written: 3,
+            error: DownloadError::Resume(_)
+        })
+    ));
+    assert!(matches!(
+        download("403").await,
+        Ok(Segmented::Rejected {
+            written: 3,
+            error: DownloadError::UrlExpired(403)
+        })
+    ));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), &CONTENT[..3]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(not(miri))]
+#[tokio::test]
+async fn test_add_task_urls() {
+    use crate::cloud::client::Pan115Client;
+    use crate::cloud_manager::AddTaskOutcome;
+    let link = "magnet:?xt=urn:btih:40882fa906a4fe9da7b57fa53a7bd880ad3244ce";
+    let (urls, _) = mock_115_server(move |_, _| {
+        format!(r#"{{"state":true,"result":[{{"errcode":0,"info_hash":"","url":"{link}"}}]}}"#)
+    })
+    .await;
+    let client = Pan115Client::with_cookies("UID=1_a_b")
+        .unwrap()
+        .with_urls(urls);
+    let links = [link.to_string(), "magnet:?xt=urn:btih:missing".to_string()];
+    let outcomes = client.add_task_urls(&links, None).await.unwrap();
+    assert_eq!(
+        outcomes,
+        [
+            (
+                links[0].clone(),
+                AddTaskOutcome::Added("40882fa906a4fe9da7b57fa53a7bd880ad3244ce".to_string())
+            ),
+            // the links which are not in the result are retried
+            (links[1].clone(), AddTaskOutcome::Unknown),
+        ]
+    );
+}
+
+#[cfg(not(miri))]
+#[tokio::test]
+async fn test_invalid_cookies() {
+    use crate::cloud_manager::is_cookies_valid;
+    // the cookies can't be a header value, so nothing is sent
+    let config = Config::default();
+    assert!(
+        !is_cookies_valid(&config, "UID=1_a_b;\nCID=2")
+            .await
+            .unwrap()
+    );
+    // the cookies are checked against the overridden base urls
+    let (urls, requests) =
+        mock_115_server(|_, _| r#"{"state":false,"errno":1,"error":"unknown api"}"#.to_string())
+            .await;
+    let config = Config {
+        api_urls: Some(urls),
+        ..Default::default()
+    };
+    assert!(!is_cookies_valid(&config, "UID=1_a_b").await.unwrap());
+    let log = requests.lock().unwrap().clone();
+    assert_eq!(log.len(), 1);
+    assert!(log[0].contains("cookie: UID=1_a_b"));
+    // expired cookies which are imported don't log out the current session
+    let (urls, _) =
+        mock_115_server(|_, _| r#"{"state":false,"errno":99,"error":"expired"}"#.to_string()).await;
+    let config = Config {
+        api_urls: Some(urls),
+        ..Default::default()
+    };
+    LOGIN_STATUS.store(true, std::sync::atomic::Ordering::Relaxed);
+    assert!(!is_cookies_valid(&config, "UID=2_a_b").await.unwrap());
+    assert!(LOGIN_STATUS.load(std::sync::atomic::Ordering::Relaxed));
+}
+
+#[cfg(not(miri))]
+#[tokio::test]
+async fn test_api_urls() {
+    use crate::cloud::client::Pan115Client;
+    let (urls, requests) = mock_115_server(|request, _| {
+        if request.starts_with("GET /files/index_info") {
+            r#"{"state":true,"data":{"space_info":{"all_total":{"size":100},"all_use":{"size":"40"},"all_remain":{"size":60}}}}"#
+        } else {
+            r#"{"state":false,"errno":1,"error":"unknown api"}"#
+        }
+        .to_string()
+    })
+    .await;
+    let config = Config {
+        cookies: "UID=1_a_b".to_string(),
+        api_urls: Some(urls),
+        ..Default::default()
+    };
+    let client = Pan115Client::from_config(&config, None).unwrap();
+    assert_eq!(client.space_info().await.unwrap(), (100, 40, 60));
+    assert!(matches!(
+        client.offline_quota().await,
+        Err(errors::CloudError::Api(_))
+    ));
+    let log = requests.lock().unwrap().clone();
+    assert!(log[0].contains("cookie: UID=1_a_b"));
+    assert!(log[1].starts_with("GET /web/lixian/?ct=lixian&ac=get_quota_package_info"));
+}
@@ -1,6 +1,6 @@
 use crate::UTC_8;
 use bitcode::{Decode, Encode};
-use chrono::{DateTime, FixedOffset};
+use chrono::{DateTime, FixedOffset, Utc};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -24,6 +24,12 @@ impl From<TimeStampCoder> for TimeStamp {
     }
 }
 
+impl TimeStamp {
+    pub fn now() -> Self {
+        Utc::now().with_timezone(&UTC_8).into()
+    }
+}
+
 impl From<DateTime<FixedOffset>> for TimeStamp {
     fn from(value: DateTime<FixedOffset>) -> Self {
         Self(value)
@@ -1,11 +1,14 @@
 use crate::config_manager::SafeSend;
 use crate::recovery_signal::Waiting;
 use crate::socket_utils::{
-    AnimeCoder, AsyncReadSocketMsg, AsyncWriteSocketMsg, ClientMsg, Filter, SocketPath,
+    AnimeCoder, ApiRateLimit, AsyncReadSocketMsg, AsyncWriteSocketMsg, BandwidthState, ClientMsg,
+    CloudQuota, Filter, SocketPath,
 };
 use crate::time_stamp::TimeStamp;
 use crate::tui::animator::{AniSender, AnimationManager};
+use crate::tui::cloud_browser::CloudBrowserState;
 use crate::tui::events::LEvent;
+use crate::tui::history::HistoryState;
 use crate::tui::loading_widget::LoadingState;
 use crate::tui::notification_widget::Notification;
 use crate::tui::progress_bar::{ProgressSuit, SimpleBar};
@@ -101,7 +104,7 @@ pub struct App {
     pub(crate) input_state: InputState,
     pub(crate) terminal: DefaultTerminal,
     pub(crate) downloading_state: ListState,
-    pub(crate) finished_state: ListState,
+    pub(crate) history: HistoryState,
     pub(crate) log_widget_state: TuiWidgetState,
     pub(crate) socket_tx: UnboundedSender<ClientMsg>,
     pub(crate) notifications_queue: VecDeque<Notification>,
@@ -114,6 +117,10 @@ pub struct App {
     pub(crate) filters: Vec<Filter>,
     pub(crate) waiting_state: Waiting,
     pub(crate) ani_sender: AniSender,
+    pub(crate) cloud_quota: Option<CloudQuota>,
+    pub(crate) rate_limit: Option<ApiRateLimit>,
+    pub(crate) bandwidth: Option<BandwidthState>,
+    pub(crate) cloud_browser: CloudBrowserState,
 }
 
 impl App {
@@ -127,7 +134,6 @@ impl App {
         // Set default level for unknown targets to Trace
         tui_logger::set_default_level(log::LevelFilter::Trace);
         let downloading_state = ListState::new();
-        let finished_state = ListState::new();
         // the event loop chanel
         let (event_tx, event_rx) = unbounded_channel::<LEvent>();
         // the socket channel, write the msg to socket
@@ -154,7 +160,7 @@ impl App {
             input_state: InputState::NotInput,
             terminal,
             downloading_state,
-            finished_state,
+            history: HistoryState::new(),
             socket_tx,
             log_widget_state,
             notifications_queue: VecDeque::new(),
@@ -167,6 +173,10 @@ impl App {
             filters: Vec::new(),
             waiting_state: Waiting::default(),
             ani_sender,
+            cloud_quota: None,
+            rate_limit: None,
+            bandwidth: None,
+            cloud_browser: CloudBrowserState::new(),
         };
         app.socket_tx.send_msg(ClientMsg::SyncQuery);
         app.socket_tx.send_msg(ClientMsg::GetWaitingState);
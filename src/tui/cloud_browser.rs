@@ -0,0 +1,172 @@
+use crate::socket_utils::{ClientMsg, CloudDir, CloudEntry};
+use ratatui::widgets::ListState as TuiListState;
+use std::collections::HashMap;
+
+/// files picked in the root folder are saved to `downloads/115/<ROOT_NAME>`
+const ROOT_NAME: &str = "root";
+
+pub struct CloudBrowserState {
+    /// (cid, name) of the folders from the root to the current one
+    pub(crate) breadcrumbs: Vec<(String, String)>,
+    /// the page which is shown, it may belong to another folder while `loading`
+    pub(crate) dir: Option<CloudDir>,
+    pub(crate) list_state: TuiListState,
+    /// - `key`: id of the entry
+    /// - `value`: (name of the folder which holds it, entry)
+    pub(crate) selected: HashMap<String, (String, CloudEntry)>,
+    pub(crate) loading: bool,
+}
+
+impl CloudBrowserState {
+    pub fn new() -> Self {
+        Self {
+            breadcrumbs: vec![("0".to_string(), ROOT_NAME.to_string())],
+            dir: None,
+            list_state: TuiListState::default(),
+            selected: HashMap::new(),
+            loading: false,
+        }
+    }
+
+    pub fn current_cid(&self) -> &str {
+        &self.breadcrumbs.last().expect("root is never popped").0
+    }
+
+    pub fn current_name(&self) -> &str {
+        &self.breadcrumbs.last().expect("root is never popped").1
+    }
+
+    pub fn path(&self) -> String {
+        self.breadcrumbs
+            .iter()
+            .map(|(_, name)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(" / ")
+    }
+
+    /// request a page of the current folder
+    pub fn list(&mut self, page: u32, refresh: bool) -> ClientMsg {
+        self.loading = true;
+        ClientMsg::ListCloudDir(Box::new((self.current_cid().to_string(), page, refresh)))
+    }
+
+    /// `false` if the page is outdated, e.g. we have entered another folder
+    pub fn update(&mut self, dir: CloudDir) -> bool {
+        if dir.cid != self.current_cid() {
+            return false;
+        }
+        self.loading = false;
+        self.list_state
+            .select((!dir.entries.is_empty()).then_some(0));
+        self.dir = Some(dir);
+        true
+    }
+
+    pub fn current_entry(&self) -> Option<&CloudEntry> {
+        let dir = self.dir.as_ref()?;
+        dir.entries.get(self.list_state.selected()?)
+    }
+
+    pub fn select_next(&mut self) {
+        let len = self.dir.as_ref().map_or(0, |dir| dir.entries.len());
+        match self.list_state.selected() {
+            Some(index) if index + 1 < len => self.list_state.select(Some(index + 1)),
+            None if len > 0 => self.list_state.select(Some(0)),
+            _ => (),
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        if let Some(index) = self.list_state.selected() {
+            self.list_state.select(Some(index.saturating_sub(1)));
+        }
+    }
+
+    pub fn toggle_selected(&mut self) {
+        let Some(entry) = self.current_entry().cloned() else {
+            return;
+        };
+        let id = entry.id().to_string();
+        if self.selected.remove(&id).is_none() {
+            let folder_name = self.current_name().to_string();
+            self.selected.insert(id, (folder_name, entry));
+        }
+        self.select_next();
+    }
+
+    pub fn is_selected(&self, entry: &CloudEntry) -> bool {
+        self.selected.contains_key(entry.id())
+    }
+
+    /// enter the highlighted folder
+    pub fn enter(&mut self) -> Option<ClientMsg> {
+        let entry = self.current_entry()?;
+        if !entry.is_folder() {
+            return None;
+        }
+        let crumb = (entry.folder_id.clone(), entry.name.clone());
+        self.breadcrumbs.push(crumb);
+        Some(self.list(0, false))
+    }
+
+    /// go back to the parent folder
+    pub fn back(&mut self) -> Option<ClientMsg> {
+        if self.breadcrumbs.len() <= 1 {
+            return None;
+        }
+        self.breadcrumbs.pop();
+        Some(self.list(0, false))
+    }
+
+    pub fn turn_page(&mut self, forward: bool) -> Option<ClientMsg> {
+        let dir = self.dir.as_ref()?;
+        let page = if forward {
+            (dir.page + 1 < dir.page_count()).then_some(dir.page + 1)?
+        } else {
+            dir.page.checked_sub(1)?
+        };
+        Some(self.list(page, false))
+    }
+
+    /// the selected entries, or the highlighted one if nothing is selected
+    pub fn picked(&self) -> Vec<(String, CloudEntry)> {
+        if self.selected.is_empty() {
+            self.current_entry()
+                .map(|entry| (self.current_name().to_string(), entry.clone()))
+                .into_iter()
+                .collect()
+        } else {
+            self.selected.values().cloned().collect()
+        }
+    }
+
+    /// folders are downloaded as a whole, files are grouped by the ids of their folders,
+    /// different folders may have the same name
+    pub fn download_msgs(picked: Vec<(String, CloudEntry)>) -> Vec<ClientMsg> {
+        let mut msgs = Vec::new();
+        let mut files = HashMap::<String, (String, Vec<CloudEntry>)>::new();
+        for (folder_name, entry) in picked {
+            if entry.is_folder() {
+                msgs.push(ClientMsg::DownloadFolder(entry.folder_id.into_boxed_str()));
+            } else {
+                files
+                    .entry(entry.folder_id.clone())
+                    .or_insert_with(|| (folder_name, Vec::new()))
+                    .1
+                    .push(entry);
+            }
+        }
+        msgs.extend(
+            files
+                .into_values()
+                .map(|files| ClientMsg::DownloadCloudFiles(Box::new(files))),
+        );
+        msgs
+    }
+}
+
+impl Default for CloudBrowserState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
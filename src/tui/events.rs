@@ -1,6 +1,8 @@
+use crate::cloud_manager::AddTaskOutcome;
 use crate::config_manager::SafeSend;
-use crate::socket_utils::{ClientMsg, DownloadState, Filter, ServerMsg};
+use crate::socket_utils::{BandwidthLimit, ClientMsg, DownloadState, Filter, ServerMsg};
 use crate::tui::app::{Anime, App, ListState};
+use crate::tui::cloud_browser::CloudBrowserState;
 use crate::tui::confirm_widget::ActionConfirm;
 use crate::tui::loading_widget::LoadingState;
 use crate::tui::notification_widget::Notification;
@@ -64,7 +66,7 @@ impl LEvent {
                                 let bar = SimpleBar::new(name, size);
                                 app.downloading_state.progress_suit.add(msg.id, bar);
                             }
-                            DownloadState::Downloading(_) => (),
+                            DownloadState::Downloading(_) | DownloadState::Verifying(_) => (),
                             DownloadState::Finished => {
                                 log::trace!("received a socket download finish msg, {}", msg.id);
                                 if let Some(bar) =
@@ -80,9 +82,20 @@ impl LEvent {
                             }
                             DownloadState::Failed => {
                                 log::trace!("received a socket download failed msg, {}", msg.id);
-                                // TODO: save and show all failed tasks
+                                // failed files are shown in the history
                                 app.downloading_state.progress_suit.remove(msg.id);
                             }
+                            DownloadState::Cancelled => {
+                                log::trace!("received a socket download cancelled msg, {}", msg.id);
+                                app.downloading_state.progress_suit.remove(msg.id);
+                            }
+                            DownloadState::Paused | DownloadState::Resumed => {
+                                if let Some(bar) =
+                                    app.downloading_state.progress_suit.get_bar_mut(msg.id)
+                                {
+                                    bar.set_paused(matches!(msg.state, DownloadState::Paused));
+                                }
+                            }
                         },
                         ServerMsg::DownloadSync(state) => {
                             // log::trace!("received a socket download sync msg");
@@ -93,6 +106,8 @@ impl LEvent {
                                 {
                                     bar.set_current_size(s.current_size);
                                     bar.set_current_speed(s.current_speed);
+                                    bar.set_verifying(s.verifying);
+                                    bar.set_paused(s.paused);
                                 } else {
                                     log::error!(
                                         "received a sync msg, but can not find its progress bar"
@@ -166,6 +181,7 @@ impl LEvent {
                         ServerMsg::Error(ptr) => {
                             let (info, error) = *ptr;
                             log::error!("{}", error);
+                            app.cloud_browser.loading = false;
                             let noti = Notification::new(
                                 "Failed".to_string(),
                                 info,
@@ -198,6 +214,9 @@ impl LEvent {
                         ServerMsg::QrcodeExpired => {
                             app.qrcode_url = Err("Qrcode expired, please reopen the login popup");
                         }
+                        ServerMsg::LoginFailed(error) => {
+                            log::error!("Login failed: {}", error);
+                        }
                         ServerMsg::LoginUrl(url) => {
                             log::info!("Login URL: {}", url);
                             app.qrcode_url = Ok(url);
@@ -214,6 +233,48 @@ impl LEvent {
                         ServerMsg::WaitingState(state) => {
                             app.waiting_state = state;
                         }
+                        ServerMsg::AddTask(ptr) => {
+                            let (ani_name, link, outcome) = *ptr;
+                            let title = match outcome {
+                                AddTaskOutcome::Added(_) | AddTaskOutcome::AlreadyExists(_) => {
+                                    log::info!("{ani_name}: {link} {outcome}");
+                                    "Task Added"
+                                }
+                                AddTaskOutcome::QuotaExceeded
+                                | AddTaskOutcome::Failed(_)
+                                | AddTaskOutcome::Unknown => {
+                                    log::warn!("{ani_name}: {link} {outcome}");
+                                    "Task Deferred"
+                                }
+                                AddTaskOutcome::InvalidLink => {
+                                    log::error!("{ani_name}: {link} {outcome}");
+                                    "Task Dropped"
+                                }
+                            };
+                            let noti = Notification::new(
+                                title.to_string(),
+                                format!("{ani_name}: {outcome}"),
+                                app.ani_sender.get_animator(),
+                            );
+                            app.notifications_queue.push_back(noti);
+                        }
+                        ServerMsg::CloudQuota(quota) => {
+                            app.cloud_quota = Some(quota);
+                        }
+                        ServerMsg::RateLimit(state) => {
+                            app.rate_limit = Some(state);
+                        }
+                        ServerMsg::Bandwidth(state) => {
+                            app.bandwidth = Some(state);
+                        }
+                        ServerMsg::History(entries) => {
+                            app.history.update(entries.into_vec());
+                        }
+                        ServerMsg::CloudDir(dir) => {
+                            if !app.cloud_browser.update(*dir) {
+                                log::trace!("received an outdated cloud folder page, ignore it");
+                            }
+                        }
                         ServerMsg::Exit => {
                             log::info!("Received exit message, exiting...");
                             READY_TO_EXIT.store(true, std::sync::atomic::Ordering::Relaxed);
@@ -329,6 +390,11 @@ impl LEvent {
                             }
                         }
                     }
+                } else if app.current_screen == CurrentScreen::Finished
+                    && !app.history.search.is_empty()
+                {
+                    // clear the search before leaving the history
+                    app.history.set_search(String::new());
                 } else if app.current_screen != CurrentScreen::Main {
                     app.current_screen = CurrentScreen::Main;
                 }
@@ -336,6 +402,11 @@ impl LEvent {
             KeyCode::Backspace => {
                 if let InputState::Text(editor) = &mut app.input_state {
                     editor.backspace();
+                } else if app.current_screen == CurrentScreen::Cloud
+                    && app.current_popup.is_none()
+                    && let Some(msg) = app.cloud_browser.back()
+                {
+                    app.socket_tx.send_msg(msg);
                 }
             }
             KeyCode::Enter => {
@@ -353,6 +424,17 @@ impl LEvent {
                                 app.current_popup = None;
                             }
                         }
+                        Popup::ImportCookies => {
+                            if let InputState::Text(editor) = app.input_state.take()
+                                && !editor.is_empty()
+                            {
+                                let msg =
+                                    ClientMsg::ImportCookies(editor.into_string().into_boxed_str());
+                                app.socket_tx.send_msg(msg);
+                                app.input_state = InputState::NotInput;
+                                app.current_popup = None;
+                            }
+                        }
                         Popup::AddRSSLink => {
                             if let InputState::Text(editor) = app.input_state.take()
                                 && !editor.is_empty()
@@ -363,6 +445,38 @@ impl LEvent {
                                 app.current_popup = None;
                             }
                         }
+                        Popup::Bandwidth(task) => {
+                            let id = task.as_ref().map(|(id, _)| *id);
+                            if let InputState::Text(editor) = app.input_state.take()
+                                && !editor.is_empty()
+                            {
+                                let input = editor.into_string();
+                                match BandwidthLimit::parse(&input) {
+                                    Some(limit) => {
+                                        let msg = ClientMsg::SetBandwidth(Box::new((id, limit)));
+                                        app.socket_tx.send_msg(msg);
+                                        app.input_state = InputState::NotInput;
+                                        app.current_popup = None;
+                                    }
+                                    None => {
+                                        let noti = Notification::new(
+                                            "Failed".to_string(),
+                                            format!("Invalid bandwidth limit: {input}"),
+                                            app.ani_sender.get_animator(),
+                                        );
+                                        app.notifications_queue.push_back(noti);
+                                        app.input_state = InputState::text(input);
+                                    }
+                                }
+                            }
+                        }
+                        Popup::SearchHistory => {
+                            // an empty search shows all files
+                            if let InputState::Text(editor) = app.input_state.take() {
+                                app.history.set_search(editor.into_string());
+                                app.current_popup = None;
+                            }
+                        }
                         _ => (),
                     }
                 } else if let CurrentScreen::Filter = app.current_screen
@@ -433,6 +547,10 @@ impl LEvent {
                             }
                         }
                     }
+                } else if app.current_screen == CurrentScreen::Cloud
+                    && let Some(msg) = app.cloud_browser.enter()
+                {
+                    app.socket_tx.send_msg(msg);
                 }
             }
             KeyCode::Delete => {
@@ -466,9 +584,8 @@ impl LEvent {
                         let state = &mut app.downloading_state;
                         scroll_down(state);
                     }
-                    CurrentScreen::Finished => {
-                        let state = &mut app.finished_state;
-                        scroll_down(state);
+                    CurrentScreen::Finished if app.current_popup.is_none() => {
+                        app.history.select_next();
                     }
                     CurrentScreen::Filter
                         if !app.filters.is_empty() && !app.input_state.is_typing() =>
@@ -499,6 +616,9 @@ impl LEvent {
                         app.log_widget_state
                             .transition(tui_logger::TuiWidgetEvent::NextPageKey);
                     }
+                    CurrentScreen::Cloud if app.current_popup.is_none() => {
+                        app.cloud_browser.select_next();
+                    }
                     _ => (),
                 }
             }
@@ -526,9 +646,8 @@ impl LEvent {
                         let state = &mut app.downloading_state;
                         scroll_up(state);
                     }
-                    CurrentScreen::Finished => {
-                        let state = &mut app.finished_state;
-                        scroll_up(state);
+                    CurrentScreen::Finished if app.current_popup.is_none() => {
+                        app.history.select_previous();
                     }
                     CurrentScreen::Filter
                         if !app.filters.is_empty() && !app.input_state.is_typing() =>
@@ -551,6 +670,9 @@ impl LEvent {
                         app.log_widget_state
                             .transition(tui_logger::TuiWidgetEvent::PrevPageKey);
                     }
+                    CurrentScreen::Cloud if app.current_popup.is_none() => {
+                        app.cloud_browser.select_previous();
+                    }
                     _ => (),
                 }
             }
@@ -566,6 +688,11 @@ impl LEvent {
                         app.input_state = InputState::empty_text();
                         app.filter_rule_state.select(Some(0));
                     }
+                } else if app.current_screen == CurrentScreen::Cloud
+                    && app.current_popup.is_none()
+                    && let Some(msg) = app.cloud_browser.enter()
+                {
+                    app.socket_tx.send_msg(msg);
                 }
             }
             KeyCode::Right => {
@@ -579,6 +706,11 @@ impl LEvent {
                     && app.filter_rule_state.selected().is_some()
                 {
                     app.filter_rule_state.select(None);
+                } else if app.current_screen == CurrentScreen::Cloud
+                    && app.current_popup.is_none()
+                    && let Some(msg) = app.cloud_browser.back()
+                {
+                    app.socket_tx.send_msg(msg);
                 }
             }
             KeyCode::Left => {
@@ -623,6 +755,13 @@ impl LEvent {
                         '6' => {
                             app.current_screen = CurrentScreen::Log;
                         }
+                        '7' => {
+                            app.current_screen = CurrentScreen::Cloud;
+                            let browser = &mut app.cloud_browser;
+                            if browser.dir.is_none() && !browser.loading && app.is_logged_in {
+                                app.socket_tx.send_msg(browser.list(0, false));
+                            }
+                        }
                         char if app.current_screen == CurrentScreen::Main
                             && app.current_popup.is_none() =>
                         {
@@ -646,6 +785,11 @@ impl LEvent {
                                     app.current_popup = Some(Popup::Login);
                                     app.socket_tx.send_msg(ClientMsg::LoginReq);
                                 }
+                                // import cookies
+                                'i' => {
+                                    app.input_state = InputState::empty_text();
+                                    app.current_popup = Some(Popup::ImportCookies);
+                                }
                                 // add rss link
                                 'a' => {
                                     // check_login!(app);
@@ -702,6 +846,165 @@ impl LEvent {
                                 }
                             }
                         }
+                        char if app.current_screen == CurrentScreen::Downloading
+                            && app.current_popup.is_none() =>
+                        {
+                            match char {
+                                // limit the bandwidth of all downloads
+                                'b' => {
+                                    app.input_state = InputState::empty_text();
+                                    app.current_popup = Some(Popup::Bandwidth(None));
+                                }
+                                // limit the bandwidth of the first download on the screen
+                                'B' => {
+                                    let state = &app.downloading_state;
+                                    if let Some((id, bar)) =
+                                        state.progress_suit.get_index(state.offset)
+                                    {
+                                        let task = (id, bar.name().to_string());
+                                        app.input_state = InputState::empty_text();
+                                        app.current_popup = Some(Popup::Bandwidth(Some(task)));
+                                    }
+                                }
+                                // pause or resume the first download on the screen
+                                'p' => {
+                                    let state = &app.downloading_state;
+                                    if let Some((id, bar)) =
+                                        state.progress_suit.get_index(state.offset)
+                                    {
+                                        let msg = if bar.is_paused() {
+                                            ClientMsg::ResumeDownload(id)
+                                        } else {
+                                            ClientMsg::PauseDownload(id)
+                                        };
+                                        app.socket_tx.send_msg(msg);
+                                    }
+                                }
+                                'P' => app.socket_tx.send_msg(ClientMsg::PauseAllDownloads),
+                                'R' => app.socket_tx.send_msg(ClientMsg::ResumeAllDownloads),
+                                // cancel the first download on the screen
+                                'x' => {
+                                    let state = &app.downloading_state;
+                                    if let Some((id, bar)) =
+                                        state.progress_suit.get_index(state.offset)
+                                    {
+                                        let content = bar.name().to_string();
+                                        let action = Box::new(move |app: &mut App| {
+                                            app.socket_tx.send_msg(ClientMsg::CancelDownload(id));
+                                        });
+                                        let question = "Do you want to cancel this download?";
+                                        let action_confirm = ActionConfirm::new(
+                                            question.into(),
+                                            content.into(),
+                                            action,
+                                        );
+                                        app.current_popup = Some(Popup::Confirm(action_confirm));
+                                    }
+                                }
+                                _ => (),
+                            }
+                        }
+                        char if app.current_screen == CurrentScreen::Finished => match char {
+                            's' => {
+                                let sort = app.history.sort.next();
+                                app.history.set_sort(sort);
+                            }
+                            '/' => {
+                                app.input_state = InputState::text(app.history.search.clone());
+                                app.current_popup = Some(Popup::SearchHistory);
+                            }
+                            // download the highlighted file again
+                            'd' => {
+                                check_login!(app);
+                                if let Some(entry) = app.history.current() {
+                                    let file_id = entry.file_id.clone();
+                                    let content = format!("{}\n{}", entry.name, entry.path);
+                                    let action = Box::new(move |app: &mut App| {
+                                        app.socket_tx.send_msg(ClientMsg::RedownloadFile(
+                                            file_id.into_boxed_str(),
+                                        ));
+                                    });
+                                    let question = "Do you want to download this file again?";
+                                    let action_confirm =
+                                        ActionConfirm::new(question.into(), content.into(), action);
+                                    app.current_popup = Some(Popup::Confirm(action_confirm));
+                                }
+                            }
+                            // show where the highlighted file is saved
+                            'o' => {
+                                if let Some(entry) = app.history.current() {
+                                    log::info!("{} is saved to {}", entry.name, entry.path);
+                                    let noti = Notification::new(
+                                        "Path".to_string(),
+                                        entry.path.clone(),
+                                        app.ani_sender.get_animator(),
+                                    );
+                                    app.notifications_queue.push_back(noti);
+                                }
+                            }
+                            'c' if !app.history.entries.is_empty() => {
+                                let action = Box::new(|app: &mut App| {
+                                    app.socket_tx.send_msg(ClientMsg::ClearHistory);
+                                });
+                                let question = "Do you want to clear the download history?";
+                                let content =
+                                    format!("{} file(s) in the history", app.history.entries.len());
+                                let action_confirm =
+                                    ActionConfirm::new(question.into(), content.into(), action);
+                                app.current_popup = Some(Popup::Confirm(action_confirm));
+                            }
+                            _ => (),
+                        },
+                        char if app.current_screen == CurrentScreen::Cloud => match char {
+                            // select or unselect a file or folder
+                            ' ' => app.cloud_browser.toggle_selected(),
+                            // next page
+                            'n' => {
+                                if let Some(msg) = app.cloud_browser.turn_page(true) {
+                                    app.socket_tx.send_msg(msg);
+                                }
+                            }
+                            // previous page
+                            'p' => {
+                                if let Some(msg) = app.cloud_browser.turn_page(false) {
+                                    app.socket_tx.send_msg(msg);
+                                }
+                            }
+                            // refresh the current page
+                            'r' => {
+                                check_login!(app);
+                                let browser = &mut app.cloud_browser;
+                                let page = match &browser.dir {
+                                    Some(dir) if dir.cid == browser.current_cid() => dir.page,
+                                    _ => 0,
+                                };
+                                app.socket_tx.send_msg(browser.list(page, true));
+                            }
+                            // download the selected files and folders
+                            'd' => {
+                                check_login!(app);
+                                let picked = app.cloud_browser.picked();
+                                if picked.is_empty() {
+                                    return false;
+                                }
+                                let content = picked
+                                    .iter()
+                                    .map(|(_, entry)| entry.name.as_str())
+                                    .collect::<Vec<_>>()
+                                    .join("\n");
+                                let action = Box::new(move |app: &mut App| {
+                                    for msg in CloudBrowserState::download_msgs(picked) {
+                                        app.socket_tx.send_msg(msg);
+                                    }
+                                    app.cloud_browser.selected.clear();
+                                });
+                                let question = "Do you want to download these files?";
+                                let action_confirm =
+                                    ActionConfirm::new(question.into(), content.into(), action);
+                                app.current_popup = Some(Popup::Confirm(action_confirm));
+                            }
+                            _ => (),
+                        },
                         char if app.current_screen == CurrentScreen::Filter => match char {
                             // add a subgroup or a rule after current
                             'a' => match app.filter_rule_state.selected() {
@@ -0,0 +1,147 @@
+use crate::socket_utils::HistoryCoder;
+use crate::time_stamp::TimeStamp;
+use ratatui::widgets::ListState as TuiListState;
+use std::cmp::Reverse;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HistorySort {
+    /// the latest first
+    #[default]
+    Time,
+    Name,
+    /// the largest first
+    Size,
+    /// the fastest first
+    Speed,
+}
+
+impl HistorySort {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Time => Self::Name,
+            Self::Name => Self::Size,
+            Self::Size => Self::Speed,
+            Self::Speed => Self::Time,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Time => "time",
+            Self::Name => "name",
+            Self::Size => "size",
+            Self::Speed => "speed",
+        }
+    }
+}
+
+pub struct HistoryState {
+    /// sorted by `sort`, the entries which don't match `search` are kept
+    pub(crate) entries: Vec<HistoryCoder>,
+    /// indexes of the entries which are shown
+    pub(crate) visible: Vec<usize>,
+    pub(crate) list_state: TuiListState,
+    pub(crate) sort: HistorySort,
+    /// matches the name or the bangumi, case-insensitively
+    pub(crate) search: String,
+}
+
+impl HistoryState {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            visible: Vec::new(),
+            list_state: TuiListState::default(),
+            sort: HistorySort::default(),
+            search: String::new(),
+        }
+    }
+
+    pub fn update(&mut self, entries: Vec<HistoryCoder>) {
+        self.entries = entries;
+        self.refresh();
+    }
+
+    pub fn set_sort(&mut self, sort: HistorySort) {
+        self.sort = sort;
+        self.refresh();
+    }
+
+    pub fn set_search(&mut self, search: String) {
+        self.search = search;
+        self.refresh();
+    }
+
+    /// sort and filter the entries again, the highlighted entry is kept if it is still shown
+    fn refresh(&mut self) {
+        let current = self.current().map(|entry| entry.file_id.clone());
+        match self.sort {
+            HistorySort::Time => self
+                .entries
+                .sort_by_key(|entry| Reverse(TimeStamp::from(entry.finished_at))),
+            HistorySort::Name => self.entries.sort_by(|a, b| a.name.cmp(&b.name)),
+            HistorySort::Size => self.entries.sort_by_key(|entry| Reverse(entry.size)),
+            HistorySort::Speed => self.entries.sort_by_key(|entry| Reverse(entry.speed())),
+        }
+        let search = self.search.to_lowercase();
+        self.visible = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                entry.name.to_lowercase().contains(&search)
+                    || entry.title.to_lowercase().contains(&search)
+            })
+            .map(|(index, _)| index)
+            .collect();
+        let selected = current
+            .and_then(|file_id| {
+                self.visible_entries()
+                    .position(|entry| entry.file_id == file_id)
+            })
+            .or((!self.visible.is_empty()).then_some(0));
+        self.list_state.select(selected);
+    }
+
+    pub fn visible_entries(&self) -> impl Iterator<Item = &HistoryCoder> {
+        self.visible.iter().map(|index| &self.entries[*index])
+    }
+
+    pub fn current(&self) -> Option<&HistoryCoder> {
+        let index = self.visible.get(self.list_state.selected()?)?;
+        self.entries.get(*index)
+    }
+
+    pub fn select_next(&mut self) {
+        match self.list_state.selected() {
+            Some(index) if index + 1 < self.visible.len() => {
+                self.list_state.select(Some(index + 1))
+            }
+            None if !self.visible.is_empty() => self.list_state.select(Some(0)),
+            _ => (),
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        if let Some(index) = self.list_state.selected() {
+            self.list_state.select(Some(index.saturating_sub(1)));
+        }
+    }
+}
+
+/// e.g. `850ms`, `12s`, `3m 05s`, `1h 02m 03s`
+pub fn format_duration(ms: u64) -> String {
+    let secs = ms / 1000;
+    match secs {
+        0 => format!("{ms}ms"),
+        1..60 => format!("{secs}s"),
+        60..3600 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m {:02}s", secs / 3600, secs / 60 % 60, secs % 60),
+    }
+}
+
+impl Default for HistoryState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
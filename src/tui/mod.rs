@@ -1,8 +1,10 @@
 pub mod animator;
 pub mod app;
+pub mod cloud_browser;
 pub mod confirm_widget;
 pub mod editor;
 pub mod events;
+pub mod history;
 pub mod input_widget;
 pub mod loading_widget;
 pub mod notification_widget;
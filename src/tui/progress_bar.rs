@@ -9,6 +9,8 @@ pub struct ProgressState {
     pub id: Id,
     pub current_size: u64,
     pub current_speed: u64,
+    pub verifying: bool,
+    pub paused: bool,
 }
 
 #[derive(Encode, Decode, Debug, Clone)]
@@ -17,6 +19,9 @@ pub struct SimpleBar {
     current_size: u64,
     current_speed: u64,
     size: u64,
+    /// the file is being hashed, `current_size` is the verified size
+    verifying: bool,
+    paused: bool,
 }
 
 pub trait Inc: BasicBar {
@@ -75,6 +80,8 @@ impl SimpleBar {
             current_size: 0,
             current_speed: 0,
             size,
+            verifying: false,
+            paused: false,
         }
     }
     pub fn pos(&self) -> u16 {
@@ -88,6 +95,8 @@ impl SimpleBar {
             last_size: 0,
             last_time: Instant::now(),
             last_speed: 0,
+            verifying: self.verifying,
+            paused: self.paused,
         }
     }
 
@@ -101,6 +110,7 @@ impl SimpleBar {
 
     pub fn inc_to_finished(&mut self) {
         self.current_size = self.size;
+        self.verifying = false;
     }
 
     pub fn current_speed(&self) -> Bytes {
@@ -114,6 +124,25 @@ impl SimpleBar {
     pub fn set_current_speed(&mut self, current_speed: u64) {
         self.current_speed = current_speed;
     }
+
+    pub fn is_verifying(&self) -> bool {
+        self.verifying
+    }
+
+    pub fn set_verifying(&mut self, verifying: bool) {
+        self.verifying = verifying;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        if paused {
+            self.current_speed = 0;
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -124,6 +153,8 @@ pub struct ProgressBar {
     last_size: u64,
     last_time: Instant,
     last_speed: u64,
+    verifying: bool,
+    paused: bool,
 }
 
 impl ProgressBar {
@@ -135,6 +166,8 @@ impl ProgressBar {
             last_size: 0,
             last_speed: 0,
             last_time: Instant::now(),
+            verifying: false,
+            paused: false,
         }
     }
     pub fn name(&self) -> &str {
@@ -147,6 +180,27 @@ impl ProgressBar {
             id,
             current_size: self.current_size,
             current_speed,
+            verifying: self.verifying,
+            paused: self.paused,
+        }
+    }
+
+    pub fn is_verifying(&self) -> bool {
+        self.verifying
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// switch between verifying and downloading, the progress starts over
+    pub fn set_verifying(&mut self, verifying: bool) {
+        if self.verifying != verifying {
+            self.verifying = verifying;
+            self.current_size = 0;
+            self.last_size = 0;
+            self.last_speed = 0;
+            self.last_time = Instant::now();
         }
     }
 
@@ -187,6 +241,8 @@ impl ProgressBar {
             current_size: self.current_size,
             current_speed,
             size: self.size,
+            verifying: self.verifying,
+            paused: self.paused,
         }
     }
 }
@@ -240,6 +296,12 @@ impl<T> ProgressSuit<T> {
         Iter::new(self.list.iter(), &self.state)
     }
 
+    /// - (id, bar) at the position of the list
+    pub fn get_index(&self, index: usize) -> Option<(Id, &T)> {
+        let id = *self.list.get(index)?;
+        self.state.get(&id).map(|bar| (id, bar))
+    }
+
     pub fn get_bar_mut(&mut self, id: Id) -> Option<&mut T> {
         self.state.get_mut(&id)
     }
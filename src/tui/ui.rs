@@ -1,10 +1,14 @@
+use crate::id::Id;
 use crate::recovery_signal::WaiterKind;
+use crate::socket_utils::BandwidthLimit;
+use crate::time_stamp::TimeStamp;
 use crate::tui::app::App;
 use crate::tui::confirm_widget::{ActionConfirm, ConfirmWidget};
 use crate::tui::editor::Editor;
+use crate::tui::history::format_duration;
 use crate::tui::input_widget::InputWidget;
 use crate::tui::notification_widget::NotificationWidget;
-use crate::tui::progress_bar::{BasicBar, SpeedSum};
+use crate::tui::progress_bar::{BasicBar, Bytes, SpeedSum};
 use crate::tui::qrcode_widget;
 use ratatui::layout::{Constraint, Direction, Layout, Margin, Rect};
 use ratatui::style::{Color, Modifier, Style, Stylize};
@@ -25,13 +29,18 @@ pub enum CurrentScreen {
     Filter,
     State,
     Log,
+    Cloud,
 }
 
 pub enum Popup {
     DownloadFolder,
     Login,
+    ImportCookies,
     AddRSSLink,
+    /// - (download id, name), the limit of all downloads when it is `None`
+    Bandwidth(Option<(Id, String)>),
     Confirm(ActionConfirm),
+    SearchHistory,
 }
 
 pub enum InputState {
@@ -99,6 +108,7 @@ pub fn render(app: &mut App) -> io::Result<()> {
             vec!["4 ".bold(), "Filter Rules".into()].into(),
             vec!["5 ".bold(), "Running State".into()].into(),
             vec!["6 ".bold(), "Log".into()].into(),
+            vec!["7 ".bold(), "Cloud".into()].into(),
         ])
         .select(app.current_screen as usize);
         let main_layout =
@@ -192,12 +202,34 @@ pub fn render(app: &mut App) -> io::Result<()> {
                     Layout::vertical([Constraint::Length(2), Constraint::Fill(1)])
                         .split(horizontal_layout[0]);
                 let download_status_area = vertical_layout[0];
+                let limit = match &app.bandwidth {
+                    Some(bandwidth) => {
+                        let global = match bandwidth.global {
+                            Some(bytes) => format!("{}/s", Bytes::from(bytes)),
+                            None => "Unlimited".to_string(),
+                        };
+                        let per_task = match bandwidth.per_task {
+                            Some(bytes) => format!("{}/s", Bytes::from(bytes)),
+                            None => "Unlimited".to_string(),
+                        };
+                        let source = match bandwidth.global_override {
+                            BandwidthLimit::Auto => "",
+                            _ => " (manual)",
+                        };
+                        format!("Limit: {global}{source}, per task: {per_task}")
+                    }
+                    None => "Limit: Unknown".to_string(),
+                };
                 let line = Line::raw(format!(
-                    "Downloading task(s): {}       Speed: {}/s",
+                    "Downloading task(s): {}       Speed: {}/s       {limit}",
                     state.progress_suit.len(),
                     state.progress_suit.speed()
                 ));
-                f.render_widget(line, download_status_area);
+                let help = Line::from(
+                    "p pause/resume  x cancel  P pause all  R resume all  b/B bandwidth limit",
+                )
+                .dark_gray();
+                f.render_widget(Text::from(vec![line, help]), download_status_area);
                 let progresses_area = vertical_layout[1];
                 let scroll_bar_area = horizontal_layout[1];
                 let height = vertical_layout[1].height as usize;
@@ -228,27 +260,51 @@ pub fn render(app: &mut App) -> io::Result<()> {
                         && j < end
                         && let Some(chunk) = chunks_iter.next()
                     {
-                        let gauge = Gauge::default()
-                            .block(Block::default().borders(Borders::ALL).title(format!(
+                        let title = if p.is_paused() {
+                            format!(
+                                "{} paused {} / {}",
+                                p.name(),
+                                p.current_size_format(),
+                                p.size_format()
+                            )
+                        } else if p.is_verifying() {
+                            format!(
+                                "{} verifying {} / {}",
+                                p.name(),
+                                p.current_size_format(),
+                                p.size_format()
+                            )
+                        } else {
+                            format!(
                                 "{} {} / {}   {}/s",
                                 p.name(),
                                 p.current_size_format(),
                                 p.size_format(),
                                 p.current_speed()
-                            )))
-                            .gauge_style(
-                                Style::default()
-                                    .fg(Color::Rgb(0, 212, 241))
-                                    .bg(Color::Rgb(37, 50, 56)),
                             )
+                        };
+                        let mut block = Block::default().borders(Borders::ALL).title(title);
+                        // the first download on the screen is controlled by the keys
+                        if j == state.offset {
+                            block = block.border_style(Style::default().fg(Color::LightBlue));
+                        }
+                        let fg = if p.is_paused() {
+                            Color::Gray
+                        } else {
+                            Color::Rgb(0, 212, 241)
+                        };
+                        let gauge = Gauge::default()
+                            .block(block)
+                            .gauge_style(Style::default().fg(fg).bg(Color::Rgb(37, 50, 56)))
                             .percent(percent);
                         f.render_widget(gauge, *chunk);
                     }
                     j += 1;
                     // we should use accurate data here, instead of using percent.
                     // percent is not accurate, when its true percent is almost 100%, it will show as 100%,
-                    // but removing the bar at that time is too early
-                    !p.is_finished()
+                    // but removing the bar at that time is too early.
+                    // a verified file is not finished until the hash check passes
+                    p.is_verifying() || !p.is_finished()
                 });
                 f.render_stateful_widget(
                     Scrollbar::new(ScrollbarOrientation::VerticalRight)
@@ -258,7 +314,99 @@ pub fn render(app: &mut App) -> io::Result<()> {
                     &mut state.scroll_state,
                 );
             }
-            CurrentScreen::Finished => {}
+            CurrentScreen::Finished => {
+                let history = &mut app.history;
+                let [status_area, content_area] =
+                    Layout::vertical([Constraint::Length(2), Constraint::Fill(1)])
+                        .areas(tab_content_area);
+                let failed = history
+                    .entries
+                    .iter()
+                    .filter(|entry| entry.error.is_some())
+                    .count();
+                let search = if history.search.is_empty() {
+                    String::new()
+                } else {
+                    format!(
+                        "       Search: {} ({} shown)",
+                        history.search,
+                        history.visible.len()
+                    )
+                };
+                let line = Line::raw(format!(
+                    "Finished: {}       Failed: {failed}       Sorted by {}{search}",
+                    history.entries.len() - failed,
+                    history.sort.as_str(),
+                ));
+                let help = Line::from(
+                    "s sort  / search  d download again  o reveal path  c clear history",
+                )
+                .dark_gray();
+                f.render_widget(Text::from(vec![line, help]), status_area);
+                let [list_area, detail_area] =
+                    Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)])
+                        .areas(content_area);
+                let list_items = history
+                    .visible_entries()
+                    .map(|entry| {
+                        let mark = match entry.error {
+                            Some(_) => Span::raw("✗ ").red(),
+                            None => Span::raw("✓ ").green(),
+                        };
+                        let lines = vec![
+                            Line::from(vec![mark, Span::raw(entry.name.clone())]),
+                            Line::from(format!(
+                                "  {}  {}  {}",
+                                entry.title,
+                                Bytes::from(entry.size),
+                                TimeStamp::from(entry.finished_at)
+                            ))
+                            .dark_gray(),
+                        ];
+                        ListItem::new(lines)
+                    })
+                    .collect::<Vec<_>>();
+                let list = List::new(list_items)
+                    .block(Block::default().title("History").borders(Borders::ALL))
+                    .highlight_spacing(ratatui::widgets::HighlightSpacing::Always)
+                    .highlight_style(
+                        Style::default()
+                            .add_modifier(Modifier::BOLD)
+                            .add_modifier(Modifier::REVERSED),
+                    )
+                    .highlight_symbol("› ");
+                f.render_stateful_widget(list, list_area, &mut history.list_state);
+                if let Some(entry) = history.current() {
+                    let detail_block = Block::default()
+                        .title("File Detail")
+                        .borders(Borders::ALL)
+                        .border_style(Style::default().fg(Color::LightBlue));
+                    let status = match &entry.error {
+                        Some(error) => Line::from(format!("Failed: {error}")).red(),
+                        None => Line::from("Finished").green(),
+                    };
+                    let lines = vec![
+                        Line::from(Span::from(entry.name.as_str()).bold()),
+                        Line::default(),
+                        status,
+                        Line::from(format!("Bangumi: {}", entry.title)),
+                        Line::from(format!("Size: {}", Bytes::from(entry.size))),
+                        Line::from(format!("Duration: {}", format_duration(entry.duration))),
+                        Line::from(format!("Average Speed: {}/s", Bytes::from(entry.speed()))),
+                        Line::from(format!(
+                            "Finished At: {}",
+                            TimeStamp::from(entry.finished_at)
+                        )),
+                        Line::default(),
+                        Line::from("Path: "),
+                        Line::from(entry.path.as_str()),
+                    ];
+                    let detail_paragraph = Paragraph::new(lines)
+                        .block(detail_block)
+                        .wrap(Wrap { trim: true });
+                    f.render_widget(detail_paragraph, detail_area);
+                }
+            }
             CurrentScreen::Filter => {
                 let horizontal_layuout =
                     Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)])
@@ -369,7 +517,132 @@ pub fn render(app: &mut App) -> io::Result<()> {
                     .header(header)
                     .block(Block::default().borders(Borders::ALL).title("Services"))
                     .widths([Constraint::Percentage(40), Constraint::Fill(1)]);
-                f.render_widget(table, tab_content_area);
+                let [services_area, cloud_area] =
+                    Layout::horizontal([Constraint::Fill(1), Constraint::Fill(1)])
+                        .areas(tab_content_area);
+                let [quota_area, rate_limit_area] =
+                    Layout::vertical([Constraint::Length(5), Constraint::Fill(1)])
+                        .areas(cloud_area);
+                f.render_widget(table, services_area);
+                let quota_rows = match &app.cloud_quota {
+                    Some(quota) => vec![
+                        Row::new([
+                            "Space".to_string(),
+                            Bytes::from(quota.space_used).to_string(),
+                            Bytes::from(quota.space_total).to_string(),
+                            Bytes::from(quota.space_remain).to_string(),
+                        ]),
+                        Row::new([
+                            "Offline".to_string(),
+                            quota.offline_used.to_string(),
+                            quota.offline_total.to_string(),
+                            quota.offline_remain.to_string(),
+                        ]),
+                    ],
+                    None => vec![Row::new(["Unknown"])],
+                };
+                let quota_header = Row::new(["Name", "Used", "Total", "Remain"])
+                    .style(Style::default().bold())
+                    .height(1);
+                let quota_table = Table::default()
+                    .rows(quota_rows)
+                    .header(quota_header)
+                    .block(Block::default().borders(Borders::ALL).title("115 Quota"))
+                    .widths([Constraint::Fill(1); 4]);
+                f.render_widget(quota_table, quota_area);
+                let rate_limit_rows = match &app.rate_limit {
+                    Some(state) => {
+                        let rate = format!("{:.2} / {:.2} per second", state.rate, state.max_rate);
+                        let rate = if state.rate < state.max_rate {
+                            Text::raw(rate).yellow()
+                        } else {
+                            Text::raw(rate).green()
+                        };
+                        let last_throttled = match state.last_throttled {
+                            Some(time) => TimeStamp::from(time).to_string(),
+                            None => "Never".to_string(),
+                        };
+                        vec![
+                            Row::new([Text::raw("Rate"), rate]),
+                            Row::new(["Throttled".to_string(), state.throttled_count.to_string()]),
+                            Row::new(["Last Throttled".to_string(), last_throttled]),
+                        ]
+                    }
+                    None => vec![Row::new(["Unknown"])],
+                };
+                let rate_limit_table = Table::default()
+                    .rows(rate_limit_rows)
+                    .block(
+                        Block::default()
+                            .borders(Borders::ALL)
+                            .title("115 API Rate Limit"),
+                    )
+                    .widths([Constraint::Percentage(40), Constraint::Fill(1)]);
+                f.render_widget(rate_limit_table, rate_limit_area);
+            }
+            CurrentScreen::Cloud => {
+                let browser = &mut app.cloud_browser;
+                let [path_area, list_area, help_area] = Layout::vertical([
+                    Constraint::Length(1),
+                    Constraint::Fill(1),
+                    Constraint::Length(1),
+                ])
+                .areas(tab_content_area);
+                f.render_widget(Line::from(browser.path()).bold(), path_area);
+                let list_items = browser
+                    .dir
+                    .iter()
+                    .flat_map(|dir| dir.entries.iter())
+                    .map(|entry| {
+                        let mark = if browser.is_selected(entry) {
+                            "[x] "
+                        } else {
+                            "[ ] "
+                        };
+                        let line = if entry.is_folder() {
+                            Line::from(vec![
+                                Span::raw(mark),
+                                Span::raw(format!("{}/", entry.name)).light_blue().bold(),
+                            ])
+                        } else {
+                            Line::from(vec![
+                                Span::raw(mark),
+                                Span::raw(entry.name.as_str()),
+                                Span::raw(format!(
+                                    "  {}",
+                                    Bytes::from(entry.size.unwrap_or_default())
+                                ))
+                                .dark_gray(),
+                            ])
+                        };
+                        ListItem::new(line)
+                    })
+                    .collect::<Vec<_>>();
+                let title = match &browser.dir {
+                    Some(dir) => format!(
+                        "Page {}/{}, {} items, {} selected{}",
+                        dir.page + 1,
+                        dir.page_count(),
+                        dir.count,
+                        browser.selected.len(),
+                        if browser.loading { ", loading..." } else { "" }
+                    ),
+                    None if browser.loading => "Loading...".to_string(),
+                    None => "Press r to list the folder".to_string(),
+                };
+                let list = List::new(list_items)
+                    .block(Block::default().title(title).borders(Borders::ALL))
+                    .highlight_spacing(ratatui::widgets::HighlightSpacing::Always)
+                    .highlight_style(
+                        Style::default()
+                            .add_modifier(Modifier::BOLD)
+                            .add_modifier(Modifier::REVERSED),
+                    )
+                    .highlight_symbol("› ");
+                f.render_stateful_widget(list, list_area, &mut browser.list_state);
+                let help =
+                    "Enter/l: open  h: back  Space: select  n/p: page  r: refresh  d: download";
+                f.render_widget(Line::from(help).dark_gray(), help_area);
             }
             CurrentScreen::Log => {
                 let logs = tui_logger::TuiLoggerWidget::default()
@@ -402,6 +675,15 @@ pub fn render(app: &mut App) -> io::Result<()> {
                     );
                     f.render_widget(input_widget, popup_area);
                 }
+                Popup::ImportCookies => {
+                    let input_widget = InputWidget::new(
+                        "Import Cookies",
+                        "Please paste the cookies, e.g. UID=...; CID=...; SEID=...; KID=...",
+                        &app.input_state,
+                        2,
+                    );
+                    f.render_widget(input_widget, popup_area);
+                }
                 Popup::AddRSSLink => {
                     let input_widget = InputWidget::new(
                         "Add a RSS Link",
@@ -411,6 +693,28 @@ pub fn render(app: &mut App) -> io::Result<()> {
                     );
                     f.render_widget(input_widget, popup_area);
                 }
+                Popup::Bandwidth(task) => {
+                    let title = match task {
+                        Some((_, name)) => Cow::from(format!("Bandwidth Limit of {name}")),
+                        None => Cow::from("Bandwidth Limit"),
+                    };
+                    let input_widget = InputWidget::new(
+                        title,
+                        "e.g. 5M, 500K, unlimited, or auto to follow the config",
+                        &app.input_state,
+                        2,
+                    );
+                    f.render_widget(input_widget, popup_area);
+                }
+                Popup::SearchHistory => {
+                    let input_widget = InputWidget::new(
+                        "Search the History",
+                        "Please enter a part of the name or the bangumi, or nothing to show all",
+                        &app.input_state,
+                        2,
+                    );
+                    f.render_widget(input_widget, popup_area);
+                }
                 Popup::Login => {
                     let vertical_layout = Layout::vertical([
                         Constraint::Fill(1),
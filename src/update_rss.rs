@@ -1,9 +1,13 @@
-use crate::cloud_manager::cloud_download;
+use crate::cloud_manager::{AddTaskOutcome, cloud_download, get_bangumi_folder, select_account};
 use crate::config_manager::{Bangumi, CONFIG, Config, Message, SafeSend, SubGroup};
 use crate::errors::{CatError, CloudError, DownloadError};
-use crate::main_proc::{restart_refresh_download, restart_refresh_download_slow};
+use crate::main_proc::{
+    restart_refresh_download, restart_refresh_download_slow, restart_refresh_qbit_download,
+};
+use crate::qbittorrent::qbit_download;
+use crate::socket_utils::ServerMsg;
 use crate::time_stamp::TimeStamp;
-use crate::{CLIENT_WITH_RETRY, RSS_DATA_PERMIT, TX};
+use crate::{BROADCAST_TX, CLIENT_WITH_RETRY, RSS_DATA_PERMIT, TX};
 use futures::future::{self, join_all};
 use quick_xml::de;
 use regex::Regex;
@@ -115,6 +119,7 @@ pub async fn start_rss_receive() -> Result<(), CatError> {
     }
     restart_refresh_download().await?;
     restart_refresh_download_slow().await?;
+    restart_refresh_qbit_download().await?;
     Ok(())
 }
 
@@ -353,11 +358,15 @@ pub async fn rss_receive(
     } else if latest_update <= old_bangumi_dict[&bangumi_id].last_update {
         // no update
         update_subgroup_name(None).await?;
-        let title = &old_config.rss_links[&bangumi_id].0;
-        println!("{title} 无更新, 上次更新: {latest_update}");
+        let old_title = &old_config.rss_links[&bangumi_id].0;
+        println!("{old_title} 无更新, 上次更新: {latest_update}");
         println!("\tlatest episode: {}", latest_episode);
-        // no need to do anything here, return now!
-        return Ok(());
+        if !old_config.magnets.contains_key(old_title) {
+            // no need to do anything here, return now!
+            return Ok(());
+        }
+        // retry the deferred magnets
+        title = old_title.clone();
     } else {
         // update an old bangumi
         update_subgroup_name(None).await?;
@@ -389,21 +398,74 @@ pub async fn rss_receive(
             "There are some magnet links of {}, let's download them!",
             title
         );
-        println!("waiting for cloud download");
-        match cloud_download(&magnet_links).await {
-            Ok(hash_list) => {
+        let download_result = match &old_config.qbittorrent {
+            Some(qbit_config) => {
+                println!("waiting for qBittorrent");
+                qbit_download(qbit_config, &title, &magnet_links)
+                    .await
+                    .map(|hash_list| {
+                        let outcomes = magnet_links
+                            .iter()
+                            .cloned()
+                            .zip(hash_list.into_iter().map(AddTaskOutcome::Added))
+                            .collect();
+                        (outcomes, None)
+                    })
+                    .map_err(CatError::from)
+            }
+            None => {
+                println!("waiting for cloud download");
+                type Outcomes = (Vec<(String, AddTaskOutcome)>, Option<String>);
+                let add_tasks = async || -> Result<Outcomes, CatError> {
+                    let account = select_account(&bangumi_id, magnet_links.len()).await?;
+                    let account = account.as_deref();
+                    let folder_id = get_bangumi_folder(&title, account).await?;
+                    let outcomes =
+                        cloud_download(&magnet_links, folder_id.as_deref(), account).await?;
+                    Ok((outcomes, account.map(|name| name.to_string())))
+                };
+                add_tasks().await
+            }
+        };
+        match download_result {
+            Ok((outcomes, account)) => {
                 let mut hash_ani = HashMap::new();
-                for i in &hash_list {
-                    hash_ani.insert(i.clone(), title.clone());
+                let mut retry_links = Vec::new();
+                for (link, outcome) in outcomes {
+                    println!("{link}: {outcome}");
+                    if let Some(hash) = outcome.hash() {
+                        hash_ani.insert(hash.to_string(), title.clone());
+                    } else if outcome.should_retry() {
+                        retry_links.push(link.clone());
+                    }
+                    BROADCAST_TX.send_msg(ServerMsg::AddTask(Box::new((
+                        title.clone(),
+                        link,
+                        outcome,
+                    ))));
                 }
                 let cmd = Box::new(move |config: &mut Config| {
-                    config.magnets.remove(&title);
+                    if retry_links.is_empty() {
+                        config.magnets.remove(&title);
+                    } else {
+                        config.magnets.insert(title, retry_links);
+                    }
                 });
                 let msg = Message::new(cmd, None);
                 tx.send_msg(msg);
                 let notify = Arc::new(Notify::new());
-                let cmd = Box::new(|config: &mut Config| {
-                    config.hash_ani.extend(hash_ani.into_iter());
+                let is_qbit = old_config.qbittorrent.is_some();
+                let cmd = Box::new(move |config: &mut Config| {
+                    if let Some(account) = account {
+                        for hash in hash_ani.keys() {
+                            config.task_accounts.insert(hash.clone(), account.clone());
+                        }
+                    }
+                    if is_qbit {
+                        config.hash_ani_qbit.extend(hash_ani);
+                    } else {
+                        config.hash_ani.extend(hash_ani);
+                    }
                 });
                 let msg = Message::new(cmd, Some(notify.clone()));
                 tx.send_msg(msg);
@@ -411,16 +473,24 @@ pub async fn rss_receive(
             }
             Err(error) => {
                 eprintln!("cloud download magnet error: {}", error);
+                let is_deferred = matches!(error, CatError::Cloud(CloudError::Quota(_)));
+                // only notify the clients when the magnets are deferred for the first time
+                if is_deferred && !old_config.magnets.contains_key(&title) {
+                    BROADCAST_TX.send_msg(ServerMsg::Error(Box::new((
+                        format!("Magnets of {title} are deferred"),
+                        error.to_string(),
+                    ))));
+                }
+                // `magnet_links` already contains the deferred magnets of this bangumi
                 let cmd = Box::new(move |config: &mut Config| {
-                    config
-                        .magnets
-                        .entry(title)
-                        .and_modify(|list| list.append(&mut magnet_links))
-                        .or_insert(magnet_links);
+                    config.magnets.insert(title, magnet_links);
                 });
                 let msg = Message::new(cmd, None);
                 tx.send_msg(msg);
-                return Err(CloudError::Api("Can not add magnet to cloud!".to_string()))?;
+                // deferred magnets will be retried in the next check, don't stop the RSS refresh
+                if !is_deferred {
+                    return Err(CloudError::Api("Can not add magnet to cloud!".to_string()))?;
+                }
             }
         }
     }